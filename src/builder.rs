@@ -0,0 +1,325 @@
+use super::lexer::Binop;
+use super::mem2reg::MemToregAlloca;
+use super::parser::SsaInstrOp::*;
+use super::parser::*;
+use super::*;
+use std::collections::HashMap;
+
+// builds the SsaProgram in memory without the textual IR.
+// the local names keep `%` (`%x`), and the blocks and the globals are named without `@` and `$`
+// (`l1`, `main`).
+pub struct ModuleBuilder<'a> {
+    spg: SsaProgram<'a>,
+    // return type of the functions defined so far
+    fns: HashMap<&'a str, VarType<'a>>,
+    // numbering of the global data
+    gfrsn: i32,
+}
+
+impl<'a> ModuleBuilder<'a> {
+    // the names made by the passes are stored in names
+    pub fn new(names: &'a NamePool) -> Self {
+        Self {
+            spg: SsaProgram::new(vec![], vec![], names),
+            fns: HashMap::new(),
+            gfrsn: -1,
+        }
+    }
+    // global data. each group is a type and its items (`z n` is `(Byte, vec![Zero(n)])`).
+    // the alignment and the linkage can be changed by the returned data.
+    pub fn data(
+        &mut self,
+        name: &'a str,
+        groups: Vec<(VarType<'a>, Vec<DataItem<'a>>)>,
+    ) -> &mut Gdata<'a> {
+        self.spg.names.reserve(name);
+        let mut gd = Gdata::new(self.gfrsn, 0, name, vec![], VarType::Void);
+        self.gfrsn -= 1;
+        let mut typesv = vec![];
+        // natural alignment is that of the largest element
+        let mut natal = 1;
+        for (dty, items) in groups {
+            if let [DataItem::Zero(size)] = items[..] {
+                typesv.push((VarType::Byte, size as u32));
+            } else {
+                natal = natal.max(dty.stacksize());
+                typesv.push((dty, items.len() as u32));
+            }
+            gd.dts.extend(items);
+        }
+        gd.al = natal;
+        gd.types = VarType::ConT(typesv);
+        self.spg.gvs.push(gd);
+        self.spg.gvs.last_mut().unwrap()
+    }
+    // function with the parameters. the body is added by the returned builder and finished by
+    // FunctionBuilder::finish.
+    pub fn function(
+        &mut self,
+        name: &'a str,
+        retty: VarType<'a>,
+        params: Vec<(VarType<'a>, &'a str)>,
+    ) -> FunctionBuilder<'_, 'a> {
+        let mut sfn = SsaFunction::new(name, retty.clone());
+        self.fns.insert(name, retty);
+        self.spg.names.reserve(name);
+        for (vty, lb) in params {
+            self.spg.names.reserve(lb);
+            sfn.args
+                .push(Var::new(lb, vty, self.spg.regs.nextfreshregister()));
+        }
+        FunctionBuilder {
+            module: self,
+            func: sfn,
+        }
+    }
+    // address of the global data or the function
+    pub fn global(&mut self, name: &'a str) -> Var<'a> {
+        let mut var = Var::new_all(
+            name,
+            VarType::Long,
+            self.spg.regs.nextfreshregister(),
+            Some(name),
+        );
+        var.thread = self
+            .spg
+            .gvs
+            .iter()
+            .any(|gd| gd.lb == name && gd.linkage.thread);
+        var
+    }
+    pub fn finish(self) -> SsaProgram<'a> {
+        self.spg
+    }
+}
+
+pub struct FunctionBuilder<'m, 'a> {
+    module: &'m mut ModuleBuilder<'a>,
+    func: SsaFunction<'a>,
+}
+
+impl<'m, 'a> FunctionBuilder<'m, 'a> {
+    pub fn linkage(&mut self, linkage: Linkage<'a>) {
+        self.func.linkage = linkage;
+    }
+    pub fn variadic(&mut self) {
+        self.func.variadic = true;
+    }
+    // i-th parameter
+    pub fn arg(&self, i: usize) -> Var<'a> {
+        self.func.args[i].clone()
+    }
+    // append the basic block. the blocks are laid out in the order of the creation.
+    pub fn block(&mut self, lb: Label<'a>) -> usize {
+        let id = self.func.bls.len();
+        self.module.spg.names.reserve(lb);
+        self.func.bls.push(SsaBlock::new(lb, id, vec![]));
+        id
+    }
+    // builder which appends the instructions to the block id
+    pub fn at(&mut self, id: usize) -> BlockBuilder<'_, 'a> {
+        BlockBuilder {
+            module: self.module,
+            func: &mut self.func,
+            id,
+        }
+    }
+    pub fn global(&mut self, name: &'a str) -> Var<'a> {
+        self.module.global(name)
+    }
+    // add the incoming value to the phi of phivar (e.g. the value defined later in the loop)
+    pub fn addincoming(&mut self, phivar: &Var<'a>, lb: Label<'a>, fco: FirstClassObj<'a>) {
+        for bb in &mut self.func.bls {
+            for instr in &mut bb.instrs {
+                if let Assign(_, var, rhs) = &mut instr.op {
+                    if let Phi(_, pv) = &mut rhs.op {
+                        if var.rg_vr == phivar.rg_vr {
+                            pv.push((lb, fco));
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        panic!("addincoming error: {} is not phi.", phivar);
+    }
+    // compute the transition blocks and the information for mem2reg, then add the function to
+    // the module
+    pub fn finish(mut self) {
+        let nbls = self.func.bls.len();
+        for i in 0..nbls {
+            let mut transbbs = vec![];
+            for instr in &self.func.bls[i].instrs {
+                match &instr.op {
+                    Jnz(_, blb1, blb2) => transbbs.extend([*blb1, *blb2]),
+                    Jmp(blb) => transbbs.push(*blb),
+                    _ => {}
+                }
+            }
+            // falls through to the next block as the parser does
+            let jumps = matches!(
+                self.func.bls[i].instrs.last().map(|instr| &instr.op),
+                Some(Jnz(..) | Jmp(..))
+            );
+            if !jumps && i + 1 < nbls {
+                transbbs.push(self.func.bls[i + 1].lb);
+            }
+            self.func.bls[i].transbbs = transbbs;
+        }
+        MemToregAlloca::decision_type(&mut self.func.m2rinfo, &self.func.bls);
+        self.module.spg.funcs.push(self.func);
+    }
+}
+
+pub struct BlockBuilder<'f, 'a> {
+    module: &'f mut ModuleBuilder<'a>,
+    func: &'f mut SsaFunction<'a>,
+    id: usize,
+}
+
+impl<'f, 'a> BlockBuilder<'f, 'a> {
+    fn push(&mut self, op: SsaInstrOp<'a>) {
+        self.func.bls[self.id].instrs.push(SsaInstr::new(op));
+    }
+    fn newvar(&mut self, name: &'a str, ty: VarType<'a>) -> Var<'a> {
+        self.module.spg.names.reserve(name);
+        Var::new(name, ty, self.module.spg.regs.nextfreshregister())
+    }
+    // `name =valty rhs`
+    pub fn assign(&mut self, name: &'a str, valty: ValueType, rhs: SsaInstrOp<'a>) -> Var<'a> {
+        let var = self.newvar(name, valty.tovarty());
+        self.push(Assign(valty, var.clone(), Box::new(SsaInstr::new(rhs))));
+        var
+    }
+    pub fn global(&mut self, name: &'a str) -> Var<'a> {
+        self.module.global(name)
+    }
+    pub fn alloc(&mut self, name: &'a str, align: i32, size: i32) -> Var<'a> {
+        let ty = if align == 4 {
+            VarType::Ptr2Word
+        } else {
+            VarType::Ptr2Long
+        };
+        let var = self.newvar(name, ty);
+        self.func
+            .m2rinfo
+            .insert(var.name, MemToregAlloca::new(var.name));
+        self.push(Alloc(var.clone(), align, size));
+        var
+    }
+    pub fn store(&mut self, valty: ValueType, val: FirstClassObj<'a>, addr: &Var<'a>) {
+        // stores to the stack slot of alloc
+        if let Some(m2ralloc) = self.func.m2rinfo.get_mut(addr.name) {
+            m2ralloc.strpush(self.id, valty);
+        }
+        self.push(Store(valty, val, addr.clone()));
+    }
+    pub fn load(
+        &mut self,
+        name: &'a str,
+        valty: ValueType,
+        ldop: LoadOp,
+        addr: &Var<'a>,
+    ) -> Var<'a> {
+        // loads from the stack slot of alloc
+        if let Some(m2ralloc) = self.func.m2rinfo.get_mut(addr.name) {
+            m2ralloc.ldpush(self.id, ldop);
+        }
+        self.assign(name, valty, Load(ldop, addr.clone()))
+    }
+    pub fn copy(&mut self, name: &'a str, valty: ValueType, src: FirstClassObj<'a>) -> Var<'a> {
+        self.assign(name, valty, Src(src))
+    }
+    pub fn bop(
+        &mut self,
+        name: &'a str,
+        valty: ValueType,
+        binop: Binop,
+        lhs: FirstClassObj<'a>,
+        rhs: FirstClassObj<'a>,
+    ) -> Var<'a> {
+        self.assign(name, valty, Bop(binop, lhs, rhs))
+    }
+    // `name =valty cmp{opty} lhs, rhs`
+    pub fn comp(
+        &mut self,
+        name: &'a str,
+        valty: ValueType,
+        cop: CompOp,
+        opty: ValueType,
+        lhs: FirstClassObj<'a>,
+        rhs: FirstClassObj<'a>,
+    ) -> Var<'a> {
+        let var = self.newvar(name, valty.tovarty());
+        self.push(Comp(cop, opty, var.clone(), lhs, rhs));
+        var
+    }
+    pub fn ext(
+        &mut self,
+        name: &'a str,
+        valty: ValueType,
+        extop: ExtOp,
+        src: FirstClassObj<'a>,
+    ) -> Var<'a> {
+        self.assign(name, valty, Ext(extop, src))
+    }
+    pub fn cvt(
+        &mut self,
+        name: &'a str,
+        valty: ValueType,
+        cvtop: CvtOp,
+        src: FirstClassObj<'a>,
+    ) -> Var<'a> {
+        self.assign(name, valty, Cvt(cvtop, valty, src))
+    }
+    // call whose result is assigned to name (`name =:agg call` for the aggregate retty)
+    pub fn call(
+        &mut self,
+        name: &'a str,
+        retty: VarType<'a>,
+        funlb: Label<'a>,
+        args: Vec<FirstClassObj<'a>>,
+        variadic: bool,
+    ) -> Var<'a> {
+        let assignty = match retty {
+            VarType::Aggre(_) => ValueType::Long,
+            _ => retty.tovalty(),
+        };
+        let var = self.newvar(name, retty.clone());
+        let rhs = SsaInstr::new(Call(retty, funlb, args, variadic));
+        self.push(Assign(assignty, var.clone(), Box::new(rhs)));
+        var
+    }
+    // call whose result is not used
+    pub fn callvoid(&mut self, funlb: Label<'a>, args: Vec<FirstClassObj<'a>>, variadic: bool) {
+        let retty = self.module.fns.get(funlb).cloned().unwrap_or(VarType::Void);
+        self.push(Call(retty, funlb, args, variadic));
+    }
+    pub fn vastart(&mut self, ap: &Var<'a>) {
+        // va_list is accessed other than load and store
+        self.func.m2rinfo.remove(ap.name);
+        self.push(Vastart(ap.clone()));
+    }
+    pub fn vaarg(&mut self, name: &'a str, valty: ValueType, ap: &Var<'a>) -> Var<'a> {
+        self.func.m2rinfo.remove(ap.name);
+        self.assign(name, valty, Vaarg(valty, ap.clone()))
+    }
+    // the incoming values defined later are added by FunctionBuilder::addincoming
+    pub fn phi(
+        &mut self,
+        name: &'a str,
+        valty: ValueType,
+        incoming: Vec<(Label<'a>, FirstClassObj<'a>)>,
+    ) -> Var<'a> {
+        self.assign(name, valty, Phi(None, incoming))
+    }
+    pub fn jnz(&mut self, cond: &Var<'a>, blb1: Label<'a>, blb2: Label<'a>) {
+        self.push(Jnz(cond.clone(), blb1, blb2));
+    }
+    pub fn jmp(&mut self, blb: Label<'a>) {
+        self.push(Jmp(blb));
+    }
+    pub fn ret(&mut self, val: FirstClassObj<'a>) {
+        self.push(Ret(val));
+    }
+}
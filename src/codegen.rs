@@ -1,11 +1,22 @@
 use super::lexer::Binop;
-use super::lowir::{LowIrInstr, LowIrProgram, RegorNum, Register};
-use super::parser::FirstClassObj;
+use super::lowir::{
+    Address, AggRet, LowIrFunction, LowIrInstr, LowIrProgram, Register, RegorNum, VaSave,
+};
+use super::parser::{AggType, DataItem, LoadOp, VarType};
 use super::*;
-use parser::CompOp;
+use parser::{CompOp, CvtOp};
 
 extern crate rand;
 use rand::seq::SliceRandom;
+use std::convert::TryFrom;
+
+// append a line of the assembly to out
+macro_rules! emit {
+    ($out:expr, $($arg:tt)*) => {{
+        $out.push_str(&format!($($arg)*));
+        $out.push('\n');
+    }};
+}
 
 const REGQUANTITY: usize = 13;
 pub const NORMALREGQUANTITY: usize = 7;
@@ -16,41 +27,433 @@ pub static X64_REG64: [&str; REGQUANTITY] = [
 pub static X64_REG32: [&str; REGQUANTITY] = [
     "r10d", "r11d", "ebx", "r12d", "r13d", "r14d", "r15d", "edi", "esi", "edx", "ecx", "r8d", "r9d",
 ];
+pub static X64_REG16: [&str; REGQUANTITY] = [
+    "r10w", "r11w", "bx", "r12w", "r13w", "r14w", "r15w", "di", "si", "dx", "cx", "r8w", "r9w",
+];
 pub static X64_REG8: [&str; REGQUANTITY] = [
     "r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b", "dil", "sil", "dl", "cl", "r8b", "r9b",
 ];
 
+// registers preserved across calls in the System V ABI
+static CALLEESAVED: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+
+const XMMQUANTITY: usize = 16;
+pub const NORMALXMMQUANTITY: usize = 8;
+
+// xmm8 ~ xmm14 are allocated, xmm15 is buffer and xmm0 ~ xmm7 are for arguments.
+pub static X64_XMM: [&str; XMMQUANTITY] = [
+    "xmm8", "xmm9", "xmm10", "xmm11", "xmm12", "xmm13", "xmm14", "xmm15", "xmm0", "xmm1", "xmm2",
+    "xmm3", "xmm4", "xmm5", "xmm6", "xmm7",
+];
+
+pub const ARGREGQUANTITY: usize = REGQUANTITY - NORMALREGQUANTITY;
+pub const ARGXMMQUANTITY: usize = XMMQUANTITY - NORMALXMMQUANTITY;
+
+// location of the argument decided by the System V calling convention
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArgLoc {
+    // scalar in the index-th integer (false) or sse (true) argument register
+    Reg(bool, usize),
+    // each eightbyte of the aggregate in the argument registers
+    Aggre(Vec<(bool, usize)>),
+    // scalar at the offset of the stack argument area
+    Stack(i32),
+    // aggregate copied to the offset of the stack argument area with its size
+    Memory(i32, i32),
+}
+
+// class of each eightbyte of the aggregate (true for SSE).
+// None means that the aggregate belongs to MEMORY class.
+pub fn classify(agg: &AggType) -> Option<Vec<bool>> {
+    if agg.size == 0 || agg.size > 16 {
+        return None;
+    }
+    // opaque aggregate is passed in the integer registers
+    let mut classes = vec![!agg.fields.is_empty(); (agg.size as usize).div_ceil(8)];
+    for (offset, ty) in &agg.fields {
+        // unaligned member
+        if offset % ty.stacksize() != 0 {
+            return None;
+        }
+        if !ty.isfloat() {
+            classes[*offset as usize / 8] = false;
+        }
+    }
+    Some(classes)
+}
+
+// whether the result of retty is returned by the memory the caller passes in rdi
+pub fn hiddenret(retty: &VarType) -> bool {
+    matches!(retty, VarType::Aggre(agg) if classify(agg).is_none())
+}
+
+// locations of the arguments and the size of the stack argument area
+pub fn argslocations(tys: &[VarType], hidden: bool) -> (Vec<ArgLoc>, i32) {
+    let mut locs = vec![];
+    let mut gi = if hidden { 1 } else { 0 };
+    let mut fi = 0;
+    let mut stacksize = 0;
+    for ty in tys {
+        if let VarType::Aggre(agg) = ty {
+            if let Some(classes) = classify(agg) {
+                let gn = classes.iter().filter(|c| !**c).count();
+                let fnum = classes.len() - gn;
+                // the aggregate is passed in memory unless all eightbytes fit
+                if gi + gn <= ARGREGQUANTITY && fi + fnum <= ARGXMMQUANTITY {
+                    let mut parts = vec![];
+                    for sse in classes {
+                        if sse {
+                            parts.push((true, fi));
+                            fi += 1;
+                        } else {
+                            parts.push((false, gi));
+                            gi += 1;
+                        }
+                    }
+                    locs.push(ArgLoc::Aggre(parts));
+                    continue;
+                }
+            }
+            let align = agg.align.max(8);
+            stacksize = (stacksize + align - 1) / align * align;
+            locs.push(ArgLoc::Memory(stacksize, agg.size));
+            stacksize += (agg.size + 7) / 8 * 8;
+        } else if ty.isfloat() && fi < ARGXMMQUANTITY {
+            locs.push(ArgLoc::Reg(true, fi));
+            fi += 1;
+        } else if !ty.isfloat() && gi < ARGREGQUANTITY {
+            locs.push(ArgLoc::Reg(false, gi));
+            gi += 1;
+        } else {
+            locs.push(ArgLoc::Stack(stacksize));
+            stacksize += 8;
+        }
+    }
+    (locs, (stacksize + 15) / 16 * 16)
+}
+
 fn selreg(r: &Register) -> &'static str {
-    if r.regsize == 4 {
-        return X64_REG32[r.rr as usize];
-    } else if r.regsize == 8 {
-        return X64_REG64[r.rr as usize];
-    } else if r.regsize == 1 {
-        // need fix
-        return X64_REG32[r.rr as usize];
+    if r.fp {
+        return X64_XMM[r.rr as usize];
     }
-    panic!("undefined register size. {:?}", r);
+    selsizedreg(r.rr, r.regsize)
 }
 
-fn selargreg(size: usize, index: usize) -> &'static str {
+// r viewed as the 64 bits register
+fn selsizedreg8(r: &Register) -> &'static str {
+    if r.fp {
+        X64_XMM[r.rr as usize]
+    } else {
+        X64_REG64[r.rr as usize]
+    }
+}
+
+// suffix of SSE scalar instructions
+fn selfpsuffix(size: i32) -> &'static str {
     if size == 4 {
-        return X64_REG32[index + NORMALREGQUANTITY];
-    } else if size == 8 {
-        return X64_REG64[index + NORMALREGQUANTITY];
-    } else if size == 1 {
-        // need fix
-        return X64_REG32[index + NORMALREGQUANTITY];
+        "ss"
+    } else {
+        "sd"
+    }
+}
+
+// name of the real register rr viewed with the given size
+fn selsizedreg(rr: i32, regsize: i32) -> &'static str {
+    if regsize == 4 {
+        return X64_REG32[rr as usize];
+    } else if regsize == 8 {
+        return X64_REG64[rr as usize];
+    } else if regsize == 2 {
+        return X64_REG16[rr as usize];
+    } else if regsize == 1 {
+        return X64_REG8[rr as usize];
     }
-    panic!("undefined argument register size. register: index: {}, size: {}", index, size);
+    panic!("undefined register size. {} {}", rr, regsize);
+}
+
+fn selargreg(size: usize, index: usize) -> &'static str {
+    selsizedreg((index + NORMALREGQUANTITY) as i32, size as i32)
 }
 
-fn memoryaccesssize(r: &Register) -> &'static str {
-    if r.regsize == 4 {
-        return "DWORD PTR";
-    } else if r.regsize == 8 {
-        return "QWORD PTR";
+fn selxmmargreg(index: usize) -> &'static str {
+    X64_XMM[index + NORMALXMMQUANTITY]
+}
+
+fn memoryaccesssize(size: i32) -> &'static str {
+    match size {
+        1 => "BYTE PTR",
+        2 => "WORD PTR",
+        4 => "DWORD PTR",
+        8 => "QWORD PTR",
+        _ => panic!("memoryaccesssize error. {}", size),
+    }
+}
+
+fn memoryaddress(addr: &Address) -> String {
+    match addr {
+        Address::Stack(offset) => format!("[rbp-{}]", offset),
+        Address::Base(r, 0) => format!("[{}]", X64_REG64[r.rr as usize]),
+        Address::Base(r, disp) => format!("[{}{:+}]", X64_REG64[r.rr as usize], disp),
+        Address::Arg(offset) => format!("[rbp+{}]", offset + 16),
+        Address::Global(lb) => format!("[rip+{}]", lb),
+        Address::Tls(lb) => format!("fs:{}@tpoff", lb),
+        Address::Out(offset) => format!("[rsp+{}]", offset),
+    }
+}
+
+// element of global data
+fn gendataitem(out: &mut String, item: DataItem) {
+    let directive = |size: i32| match size {
+        1 => ".byte",
+        2 => ".short",
+        4 => ".long",
+        8 => ".quad",
+        _ => panic!("gendataitem error. {}", size),
+    };
+    match item {
+        DataItem::Num(ty, num) => {
+            let size = ty.stacksize();
+            // truncate to the size of the element
+            let bits = (num as u64) & (u64::MAX >> (64 - size * 8));
+            emit!(out, "\t{} {}", directive(size), bits);
+        }
+        DataItem::Float(ty, num) => {
+            if ty.stacksize() == 4 {
+                emit!(out, "\t.long {}", (num as f32).to_bits());
+            } else {
+                emit!(out, "\t.quad {}", num.to_bits());
+            }
+        }
+        DataItem::String(text) => emit!(out, "\t.ascii \"{}\"", text),
+        DataItem::Symbol(lb, 0) => emit!(out, "\t.quad {}", lb),
+        DataItem::Symbol(lb, offset) => emit!(out, "\t.quad {}{:+}", lb, offset),
+        DataItem::Zero(size) => emit!(out, "\t.zero {}", size),
+    }
+}
+
+// sizes of the area saving registers across calls and
+// the outgoing stack argument area at the bottom of the frame
+fn framelayout(func: &LowIrFunction) -> (i32, i32) {
+    let mut savesize = 0;
+    let mut outsize = 0;
+    for instr in func.rbbs.iter().flat_map(|bb| bb.instrs.iter()) {
+        match instr {
+            LowIrInstr::Storereg(_, Address::Out(offset))
+            | LowIrInstr::Storenum(_, _, Address::Out(offset)) => {
+                outsize = outsize.max(offset + 8);
+            }
+            LowIrInstr::Call(_, _, args, usedrs, _) => {
+                savesize = savesize.max(usedrs.len() as i32 * 8);
+                for (_, loc) in args {
+                    if let ArgLoc::Memory(offset, size) = loc {
+                        outsize = outsize.max(offset + (size + 7) / 8 * 8);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    (savesize, outsize)
+}
+
+fn gensection(out: &mut String, section: (&str, Option<&str>)) {
+    match section {
+        (name, Some(flags)) => emit!(out, ".section {},\"{}\"", name, flags),
+        (name, None) => emit!(out, ".section {}", name),
+    }
+}
+
+// callee-saved registers used in the function and their stack slots
+fn calleesavedregs(func: &LowIrFunction) -> Vec<(&'static str, i32)> {
+    // the buffer register is used without allocation
+    let mut rrs = func.usedregs.clone();
+    rrs.push(NORMALREGQUANTITY as i32 - 1);
+    let base = (func.framesize + 7) / 8 * 8;
+    rrs.into_iter()
+        .map(|rr| X64_REG64[rr as usize])
+        .filter(|reg| CALLEESAVED.contains(reg))
+        .enumerate()
+        .map(|(i, reg)| (reg, base + (i as i32 + 1) * 8))
+        .collect()
+}
+
+fn genepilogue(out: &mut String, stmsize: i32, calleesaved: &[(&str, i32)]) {
+    for (reg, offset) in calleesaved {
+        emit!(out, "\tmov {}, QWORD PTR [rbp-{}]", reg, offset);
+    }
+    if stmsize > 0 {
+        emit!(out, "\tadd rsp, {}", stmsize);
+    }
+    emit!(out, "\tpop rbp");
+    emit!(out, "\tret");
+}
+
+// copy size bytes from [src] to [dst] through rax
+fn gencopy(out: &mut String, dst: &str, src: &str, size: i32) {
+    for offset in (0..size).step_by(8) {
+        emit!(out, "\tmov rax, QWORD PTR [{}+{}]", src, offset);
+        emit!(out, "\tmov QWORD PTR [{}+{}], rax", dst, offset);
+    }
+}
+
+// return registers of each eightbyte of the aggregate result
+fn selretregs(classes: &[bool]) -> Vec<&'static str> {
+    let (mut gi, mut fi) = (0, 0);
+    let mut regs = vec![];
+    for sse in classes {
+        if *sse {
+            regs.push(["xmm0", "xmm1"][fi]);
+            fi += 1;
+        } else {
+            regs.push(["rax", "rdx"][gi]);
+            gi += 1;
+        }
+    }
+    regs
+}
+
+// extend src of srcsize bytes to the size of r
+fn genextend(out: &mut String, srcsize: i32, signed: bool, r: &Register, src: &str) {
+    if srcsize == 4 {
+        if signed {
+            emit!(out, "\tmovsxd {}, {}", selreg(r), src);
+        } else {
+            // writing the 32 bit register clears the upper bits
+            emit!(out, "\tmov {}, {}", selsizedreg(r.rr, 4), src);
+        }
+    } else if signed {
+        emit!(out, "\tmovsx {}, {}", selreg(r), src);
+    } else {
+        emit!(out, "\tmovzx {}, {}", selsizedreg(r.rr, 4), src);
+    }
+}
+
+// load the value at addr to r, extending it to the size of r
+fn genload(out: &mut String, ldop: LoadOp, r: &Register, addr: &str) {
+    let memsize = ldop.memty().bytesize();
+    if r.fp {
+        emit!(
+            out,
+            "\tmov{} {}, {} {}",
+            selfpsuffix(r.regsize),
+            selreg(r),
+            memoryaccesssize(r.regsize),
+            addr
+        );
+    } else if memsize >= r.regsize {
+        emit!(
+            out,
+            "\tmov {}, {} {}",
+            selreg(r),
+            memoryaccesssize(r.regsize),
+            addr
+        );
+    } else {
+        let src = format!("{} {}", memoryaccesssize(memsize), addr);
+        genextend(out, memsize, ldop.signed(), r, &src);
+    }
+}
+
+// float comparison. the result is set to the 8 bit register dst.
+fn genfcomp(out: &mut String, op: CompOp, dst: &'static str, r1: &Register, r2: &Register) {
+    use CompOp::*;
+    let ucomis = format!("ucomi{}", selfpsuffix(r1.regsize));
+    let buf = selsizedreg(NORMALREGQUANTITY as i32 - 1, 1);
+    match op {
+        // lhs < rhs is rhs > lhs
+        Clt | Cle => emit!(out, "\t{} {}, {}", ucomis, selreg(r2), selreg(r1)),
+        _ => emit!(out, "\t{} {}, {}", ucomis, selreg(r1), selreg(r2)),
+    }
+    match op {
+        Ceq => {
+            // unordered (NaN) sets ZF and PF
+            emit!(out, "\tsete {}", dst);
+            emit!(out, "\tsetnp {}", buf);
+            emit!(out, "\tand {}, {}", dst, buf);
+        }
+        Cne => {
+            emit!(out, "\tsetne {}", dst);
+            emit!(out, "\tsetp {}", buf);
+            emit!(out, "\tor {}, {}", dst, buf);
+        }
+        Clt | Cgt => emit!(out, "\tseta {}", dst),
+        Cle | Cge => emit!(out, "\tsetae {}", dst),
+        Co => emit!(out, "\tsetnp {}", dst),
+        Cuo => emit!(out, "\tsetp {}", dst),
+        _ => panic!("genfcomp error. {:?}", op),
+    }
+}
+
+// conversion between integer and floating point: r1 = r2
+fn gencvt(out: &mut String, cvtop: CvtOp, r1: &Register, r2: &Register) {
+    use CvtOp::*;
+    let buf = NORMALREGQUANTITY as i32 - 1;
+    match cvtop {
+        Exts => emit!(out, "\tcvtss2sd {}, {}", selreg(r1), selreg(r2)),
+        Truncd => emit!(out, "\tcvtsd2ss {}, {}", selreg(r1), selreg(r2)),
+        Stosi | Dtosi => {
+            let op = format!("cvtt{}2si", selfpsuffix(r2.regsize));
+            emit!(out, "\t{} {}, {}", op, selreg(r1), selreg(r2));
+        }
+        Stoui | Dtoui => {
+            // the value over 2^63 is converted as the value minus 2^63 (given by
+            // -(2^63 - value) in the buffer registers) with the top bit set
+            let (suffix, fpbits) = if r2.regsize == 4 {
+                ("ss", 0x5f000000u64)
+            } else {
+                ("sd", 0x43e0000000000000u64)
+            };
+            let dst = selsizedreg(r1.rr, 8);
+            let (xbuf, gbuf) = (X64_XMM[NORMALXMMQUANTITY - 1], selsizedreg(buf, 8));
+            emit!(out, "\tcvtt{}2si {}, {}", suffix, dst, selreg(r2));
+            emit!(out, "\tmov {}, {}", gbuf, fpbits);
+            emit!(out, "\tmovq {}, {}", xbuf, gbuf);
+            emit!(out, "\tsub{} {}, {}", suffix, xbuf, selreg(r2));
+            emit!(out, "\tcvtt{}2si {}, {}", suffix, gbuf, xbuf);
+            emit!(out, "\tneg {}", gbuf);
+            emit!(out, "\tor {}, {}", gbuf, dst);
+            emit!(out, "\ttest {}, {}", dst, dst);
+            emit!(out, "\tcmovs {}, {}", dst, gbuf);
+        }
+        Swtof | Sltof => {
+            let op = format!("cvtsi2{}", selfpsuffix(r1.regsize));
+            emit!(out, "\t{} {}, {}", op, selreg(r1), selreg(r2));
+        }
+        Ultof => {
+            // the value over 2^63 is halved keeping the lowest bit for the rounding, converted
+            // and doubled
+            let op = format!("cvtsi2{}", selfpsuffix(r1.regsize));
+            let (src, gbuf) = (selreg(r2), selsizedreg(buf, 8));
+            let lb = format!(".Lultof{}", r1.vr);
+            emit!(out, "\tmov {}, {}", gbuf, src);
+            emit!(out, "\tand {}, 1", gbuf);
+            emit!(out, "\tadd {}, {}", gbuf, gbuf);
+            emit!(out, "\tor {}, {}", gbuf, src);
+            emit!(out, "\tshr {}, 1", gbuf);
+            emit!(out, "\ttest {}, {}", src, src);
+            emit!(out, "\tcmovns {}, {}", gbuf, src);
+            emit!(out, "\t{} {}, {}", op, selreg(r1), gbuf);
+            emit!(out, "\tjns {}", lb);
+            emit!(
+                out,
+                "\tadd{} {}, {}",
+                selfpsuffix(r1.regsize),
+                selreg(r1),
+                selreg(r1)
+            );
+            emit!(out, "{}:", lb);
+        }
+        Uwtof => {
+            // zero extend to 64 bits in the buffer register
+            let op = format!("cvtsi2{}", selfpsuffix(r1.regsize));
+            emit!(out, "\tmov {}, {}", selsizedreg(buf, 4), selreg(r2));
+            emit!(out, "\t{} {}, {}", op, selreg(r1), selsizedreg(buf, 8));
+        }
+        Cast => {
+            let op = if r1.regsize == 8 { "movq" } else { "movd" };
+            emit!(out, "\t{} {}, {}", op, selreg(r1), selreg(r2));
+        }
     }
-    panic!("memoryaccesssize error.");
 }
 
 fn selrax(size: usize) -> &'static str {
@@ -62,217 +465,555 @@ fn selrax(size: usize) -> &'static str {
     panic!("selrax error.");
 }
 
-fn movregreg(r1: &Register, r2: &Register) {
-    if let Some(gl_lb) = r2.global {
-        print!("\tmov {}, OFFSET FLAT:{}\n", X64_REG64[r1.rr as usize], gl_lb);
+// condition code of setcc for each comparison
+fn selcond(op: CompOp) -> &'static str {
+    use CompOp::*;
+    match op {
+        Ceq => "e",
+        Cne => "ne",
+        Csle => "le",
+        Cslt => "l",
+        Csge => "ge",
+        Csgt => "g",
+        Cule => "be",
+        Cult => "b",
+        Cuge => "ae",
+        Cugt => "a",
+        _ => panic!("{} is floating point comparison.", op.name()),
+    }
+}
+
+fn seldividend(size: i32) -> (&'static str, &'static str) {
+    if size == 8 {
+        ("rax", "rdx")
     } else {
-        print!("\tmov {}, {}\n", selreg(r1), selreg(r2));
+        ("eax", "edx")
+    }
+}
+
+// two-address binary operation: r1 = r1 <op> r2
+fn genbop(out: &mut String, binop: Binop, r1: &Register, r2: &RegorNum) {
+    if r1.fp {
+        let op = match binop {
+            Binop::Add => "add",
+            Binop::Sub => "sub",
+            Binop::Mul => "mul",
+            Binop::Div => "div",
+            _ => panic!("{} is not floating point operation.", binop.name()),
+        };
+        match r2 {
+            RegorNum::Reg(r) => {
+                emit!(
+                    out,
+                    "\t{}{} {}, {}",
+                    op,
+                    selfpsuffix(r1.regsize),
+                    selreg(r1),
+                    selreg(r)
+                );
+            }
+            RegorNum::Num(_) => panic!("genbop error: floating point immediate."),
+        }
+        return;
+    }
+    // rhs operand viewed with the size of r1
+    let rhs = match r2 {
+        RegorNum::Reg(r) => selsizedreg(r.rr, r1.regsize).to_string(),
+        RegorNum::Num(num) => num.to_string(),
+    };
+    match binop {
+        Binop::Add | Binop::Sub | Binop::And | Binop::Or | Binop::Xor => {
+            let op = match binop {
+                Binop::Add => "add",
+                Binop::Sub => "sub",
+                Binop::And => "and",
+                Binop::Or => "or",
+                _ => "xor",
+            };
+            emit!(out, "\t{} {}, {}", op, selreg(r1), rhs);
+        }
+        Binop::Mul => {
+            if let RegorNum::Num(num) = r2 {
+                emit!(out, "\timul {}, {}, {}", selreg(r1), selreg(r1), num);
+            } else {
+                emit!(out, "\timul {}, {}", selreg(r1), rhs);
+            }
+        }
+        Binop::Div | Binop::Udiv | Binop::Rem | Binop::Urem => {
+            // divisor goes to the buffer register because rdx is overwritten
+            // by the sign extension of the dividend.
+            let divisor = selsizedreg(NORMALREGQUANTITY as i32 - 1, r1.regsize);
+            let (ax, dx) = seldividend(r1.regsize);
+            emit!(out, "\tmov {}, {}", divisor, rhs);
+            emit!(out, "\tpush rdx");
+            emit!(out, "\tmov {}, {}", ax, selreg(r1));
+            match binop {
+                Binop::Div | Binop::Rem => {
+                    emit!(out, "\t{}", if r1.regsize == 8 { "cqo" } else { "cdq" });
+                    emit!(out, "\tidiv {}", divisor);
+                }
+                _ => {
+                    emit!(out, "\txor edx, edx");
+                    emit!(out, "\tdiv {}", divisor);
+                }
+            }
+            match binop {
+                Binop::Div | Binop::Udiv => emit!(out, "\tmov {}, {}", selreg(r1), ax),
+                _ => emit!(out, "\tmov {}, {}", selreg(r1), dx),
+            }
+            emit!(out, "\tpop rdx");
+        }
+        Binop::Shl | Binop::Shr | Binop::Sar => {
+            let op = match binop {
+                Binop::Shl => "shl",
+                Binop::Shr => "shr",
+                _ => "sar",
+            };
+            match r2 {
+                RegorNum::Num(num) => {
+                    emit!(out, "\t{} {}, {}", op, selreg(r1), num);
+                }
+                RegorNum::Reg(r) => {
+                    // shift count must be in cl
+                    emit!(out, "\tpush rcx");
+                    emit!(out, "\tmov ecx, {}", selsizedreg(r.rr, 4));
+                    emit!(out, "\t{} {}, cl", op, selreg(r1));
+                    emit!(out, "\tpop rcx");
+                }
+            }
+        }
     }
 }
 
+fn movregreg(out: &mut String, r1: &Register, r2: &Register) {
+    if r1.fp {
+        emit!(out, "\tmovaps {}, {}", selreg(r1), selreg(r2));
+    } else if let Some(gl_lb) = r2.global {
+        emit!(
+            out,
+            "\tmov {}, OFFSET FLAT:{}",
+            X64_REG64[r1.rr as usize],
+            gl_lb
+        );
+    } else {
+        emit!(
+            out,
+            "\tmov {}, {}",
+            selreg(r1),
+            selsizedreg(r2.rr, r1.regsize)
+        );
+    }
+}
 
 const BASE_STR: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
 
+// load the address of va_list to the buffer register
+fn genvalist(out: &mut String, addr: &Address) -> &'static str {
+    let buf = selsizedreg(NORMALREGQUANTITY as i32 - 1, 8);
+    if let Address::Tls(lb) = addr {
+        emit!(out, "\tmov {}, QWORD PTR fs:0", buf);
+        emit!(out, "\tlea {}, [{}+{}@tpoff]", buf, buf, lb);
+    } else {
+        emit!(out, "\tlea {}, {}", buf, memoryaddress(addr));
+    }
+    buf
+}
+
+// va_list = { gp_offset, fp_offset, overflow_arg_area, reg_save_area }
+fn genvastart(out: &mut String, addr: &Address, vasave: VaSave) {
+    let valist = genvalist(out, addr);
+    emit!(out, "\tmov DWORD PTR [{}], {}", valist, vasave.gpoffset);
+    emit!(out, "\tmov DWORD PTR [{}+4], {}", valist, vasave.fpoffset);
+    emit!(out, "\tlea rax, [rbp+{}]", vasave.overflow + 16);
+    emit!(out, "\tmov QWORD PTR [{}+8], rax", valist);
+    emit!(out, "\tlea rax, [rbp-{}]", vasave.slot);
+    emit!(out, "\tmov QWORD PTR [{}+16], rax", valist);
+}
+
+// fetch the next argument from the register save area or the stack argument area
+fn genvaarg(out: &mut String, r: &Register, addr: &Address) {
+    let valist = genvalist(out, addr);
+    // offset field, its limit and the size of each slot in the register save area
+    let (field, limit, step) = if r.fp {
+        (4, ARGREGQUANTITY * 8 + ARGXMMQUANTITY * 16, 16)
+    } else {
+        (0, ARGREGQUANTITY * 8, 8)
+    };
+    let stacklb = format!(".Lvastack{}", r.vr);
+    let loadlb = format!(".Lvaload{}", r.vr);
+    emit!(out, "\tmov eax, DWORD PTR [{}+{}]", valist, field);
+    emit!(out, "\tcmp eax, {}", limit);
+    emit!(out, "\tjae {}", stacklb);
+    emit!(out, "\tadd DWORD PTR [{}+{}], {}", valist, field, step);
+    emit!(out, "\tadd rax, QWORD PTR [{}+16]", valist);
+    emit!(out, "\tjmp {}", loadlb);
+    emit!(out, "{}:", stacklb);
+    emit!(out, "\tmov rax, QWORD PTR [{}+8]", valist);
+    emit!(out, "\tadd QWORD PTR [{}+8], 8", valist);
+    emit!(out, "{}:", loadlb);
+    if r.fp {
+        emit!(
+            out,
+            "\tmov{} {}, {} [rax]",
+            selfpsuffix(r.regsize),
+            selreg(r),
+            memoryaccesssize(r.regsize)
+        );
+    } else {
+        emit!(out, "\tmov {}, QWORD PTR [rax]", X64_REG64[r.rr as usize]);
+    }
+}
+
 fn gen_random_label(size: usize) -> String {
     let mut rng = &mut rand::thread_rng();
     String::from_utf8(
-        BASE_STR.as_bytes()
+        BASE_STR
+            .as_bytes()
             .choose_multiple(&mut rng, size)
             .cloned()
-            .collect()
-    ).unwrap()
+            .collect(),
+    )
+    .unwrap()
 }
 
-fn gen_jmp_overflow(overflow_black_label: String) {
-    print!("\tpushf\n");
-    print!("\tmov r15d, [rsp]\n");
-    print!("\tand r15d, 0x00000800\n");
-    print!("\tcmp r15d, 0\n");
-    print!("\tjne .{}\n", overflow_black_label);
-    print!("\tpopf\n");
+fn gen_jmp_overflow(out: &mut String, overflow_black_label: String) {
+    emit!(out, "\tpushf");
+    emit!(out, "\tmov r15d, [rsp]");
+    emit!(out, "\tand r15d, 0x00000800");
+    emit!(out, "\tcmp r15d, 0");
+    emit!(out, "\tjne .{}", overflow_black_label);
+    emit!(out, "\tpopf");
 }
 
-fn gen_overflow_block(num_overflow_label: String, overflow_black_label: String) {
-    print!(".{}:\n", overflow_black_label);
-    print!("\tmov edi, OFFSET FLAT:.{}\n", num_overflow_label);
-    print!("\tmov eax, 0\n");
-    print!("\tcall printf\n");
-    print!("\tpopf\n");
-    print!("\tpop rbp\n");
-    print!("\tret\n");
+fn gen_overflow_block(out: &mut String, num_overflow_label: String, overflow_black_label: String) {
+    emit!(out, ".{}:", overflow_black_label);
+    // the block is shared by the functions, so the program exits here instead of returning
+    emit!(out, "\tand rsp, -16");
+    emit!(out, "\tmov edi, OFFSET FLAT:.{}", num_overflow_label);
+    emit!(out, "\tmov eax, 0");
+    emit!(out, "\tcall printf");
+    emit!(out, "\tmov edi, 1");
+    emit!(out, "\tcall exit");
 }
 
-pub fn gen_x64code(lirpg: LowIrProgram, secure_mode: bool) {
-    print!(".intel_syntax noprefix\n");
+pub fn gen_x64code(lirpg: LowIrProgram, secure_mode: bool) -> String {
+    let mut asm = String::new();
+    let out = &mut asm;
+    emit!(out, ".intel_syntax noprefix");
 
     // data section
-    print!(".data\n");
-    print!("\n");
+    emit!(out, ".data");
+    emit!(out, "");
     let num_overflow_label = gen_random_label(100);
     let overflow_black_label = gen_random_label(50);
     if secure_mode {
-        print!(".{}:\n", &num_overflow_label[..]);
-        print!("\t.string \"execution error of integer overflow.\\n\"\n");
+        emit!(out, ".{}:", &num_overflow_label[..]);
+        emit!(out, "\t.string \"execution error of integer overflow.\\n\"");
     }
 
     for gd in lirpg.gvs {
-        print!("{}:\n", gd.lb);
-        for eled in gd.dts {
-            if let FirstClassObj::String(lb) = eled {
-                print!(".LC{}:\n", -gd.frsn);
-                print!("\t.string \"{}\"\n", lb);
-            }
+        if let Some(section) = gd.linkage.section {
+            gensection(out, section);
+        } else if gd.linkage.thread {
+            emit!(out, ".section .tdata,\"awT\",@progbits");
+        } else {
+            emit!(out, ".data");
+        }
+        if gd.linkage.export {
+            emit!(out, ".globl {}", gd.lb);
+        }
+        emit!(out, ".balign {}", gd.al);
+        emit!(out, "{}:", gd.lb);
+        for item in gd.dts {
+            gendataitem(out, item);
         }
     }
 
-    print!("\n");
+    emit!(out, "");
 
     // execution program section
-    print!(".text\n");
-    print!(".globl main\n");
-    print!("\n");
-
     for func in lirpg.funcs {
-        let stmsize = (func.framesize + 15) / 16 * 16;
-        print!("{}:\n", func.lb);
-        print!("\tpush rbp\n");
-        print!("\tmov rbp, rsp\n");
+        if let Some(section) = func.linkage.section {
+            gensection(out, section);
+        } else {
+            emit!(out, ".text");
+        }
+        // main is called from the startup routine
+        if func.linkage.export || func.lb == "main" {
+            emit!(out, ".globl {}", func.lb);
+        }
+        // rsp is kept 16 bytes aligned in the function body
+        let (savesize, outsize) = framelayout(&func);
+        let calleesaved = calleesavedregs(&func);
+        // registers living across calls are saved under the callee-saved registers
+        let savebase = (func.framesize + 7) / 8 * 8 + calleesaved.len() as i32 * 8;
+        let stmsize = (savebase + savesize + outsize + 15) / 16 * 16;
+        emit!(out, "{}:", func.lb);
+        emit!(out, "\tpush rbp");
+        emit!(out, "\tmov rbp, rsp");
         if stmsize > 0 {
-            print!("\tsub rsp, {}\n", stmsize);
+            emit!(out, "\tsub rsp, {}", stmsize);
+        }
+        for (reg, offset) in &calleesaved {
+            emit!(out, "\tmov QWORD PTR [rbp-{}], {}", offset, reg);
         }
         for bb in func.rbbs {
-            print!("{}:\n", bb.lb);
+            emit!(out, "{}:", bb.lb);
             for instr in bb.instrs {
                 use LowIrInstr::*;
                 match instr {
                     Movenum(ref r, num) => {
-                        print!("\tmov {}, {}\n", selreg(r), num);
+                        if r.regsize == 8 && i32::try_from(num).is_err() {
+                            emit!(out, "\tmovabs {}, {}", selreg(r), num);
+                        } else {
+                            emit!(out, "\tmov {}, {}", selreg(r), num);
+                        }
+                    }
+                    Movefnum(ref r, num) => {
+                        // through the buffer register
+                        let buf = NORMALREGQUANTITY as i32 - 1;
+                        if r.regsize == 4 {
+                            let bits = (num as f32).to_bits();
+                            emit!(out, "\tmov {}, {}", selsizedreg(buf, 4), bits);
+                            emit!(out, "\tmovd {}, {}", selreg(r), selsizedreg(buf, 4));
+                        } else {
+                            let bits = num.to_bits();
+                            emit!(out, "\tmov {}, {}", selsizedreg(buf, 8), bits);
+                            emit!(out, "\tmovq {}, {}", selreg(r), selsizedreg(buf, 8));
+                        }
                     }
                     Movereg(ref r1, ref r2) => {
-                        movregreg(r1, r2);
+                        movregreg(out, r1, r2);
                     }
                     Ret(ref r) => {
-                        print!("\tmov {}, {}\n", selrax(r.regsize as usize), selreg(r));
-                        if stmsize > 0 {
-                            print!("\tadd rsp, {}\n", stmsize);
+                        if r.fp {
+                            emit!(out, "\tmovaps xmm0, {}", selreg(r));
+                        } else {
+                            emit!(out, "\tmov {}, {}", selrax(r.regsize as usize), selreg(r));
+                        }
+                        genepilogue(out, stmsize, &calleesaved);
+                    }
+                    Retaggre(ref r, ref aggret) => {
+                        let src = X64_REG64[r.rr as usize];
+                        if let Some(classes) = &aggret.classes {
+                            for (i, reg) in selretregs(classes).into_iter().enumerate() {
+                                let mov = if reg.starts_with("xmm") {
+                                    "movsd"
+                                } else {
+                                    "mov"
+                                };
+                                emit!(out, "\t{} {}, QWORD PTR [{}+{}]", mov, reg, src, i * 8);
+                            }
+                        } else {
+                            // copy to the memory of the hidden pointer
+                            let buf = selsizedreg(NORMALREGQUANTITY as i32 - 1, 8);
+                            emit!(out, "\tmov {}, QWORD PTR [rbp-{}]", buf, aggret.slot);
+                            gencopy(out, buf, src, aggret.size);
+                            emit!(out, "\tmov rax, {}", buf);
                         }
-                        print!("\tpop rbp\n");
-                        print!("\tret\n");
+                        genepilogue(out, stmsize, &calleesaved);
                     }
-                    Storewreg(ref r, offset) => {
-                        print!(
-                            "\tmov {} [rbp-{}], {}\n",
-                            memoryaccesssize(r),
-                            offset,
+                    Storereg(ref r, ref addr) => {
+                        let src = if let Some(gl_lb) = r.global {
+                            let buf = selsizedreg(NORMALREGQUANTITY as i32 - 1, 8);
+                            emit!(out, "\tmov {}, OFFSET FLAT:{}", buf, gl_lb);
+                            buf
+                        } else {
                             selreg(r)
+                        };
+                        let mov = if r.fp {
+                            format!("mov{}", selfpsuffix(r.regsize))
+                        } else {
+                            "mov".to_string()
+                        };
+                        emit!(
+                            out,
+                            "\t{} {} {}, {}",
+                            mov,
+                            memoryaccesssize(r.regsize),
+                            memoryaddress(addr),
+                            src
                         );
                     }
-                    Storewnum(num, offset) => {
-                        print!("\tmov DWORD PTR [rbp-{}], {}\n", offset, num);
+                    Storenum(valty, num, ref addr) => {
+                        emit!(
+                            out,
+                            "\tmov {} {}, {}",
+                            memoryaccesssize(valty.bytesize()),
+                            memoryaddress(addr),
+                            num
+                        );
+                    }
+                    Load(ldop, ref r, ref addr) => {
+                        genload(out, ldop, r, &memoryaddress(addr));
+                    }
+                    Ext(extop, ref r1, ref r2) => {
+                        if r2.regsize >= r1.regsize {
+                            movregreg(out, r1, r2);
+                        } else {
+                            genextend(out, r2.regsize, extop.signed(), r1, selreg(r2));
+                        }
+                    }
+                    Cvt(cvtop, ref r1, ref r2) => {
+                        gencvt(out, cvtop, r1, r2);
+                    }
+                    Lea(ref r, Address::Tls(lb)) => {
+                        // thread pointer + offset of the thread local data
+                        let dst = X64_REG64[r.rr as usize];
+                        emit!(out, "\tmov {}, QWORD PTR fs:0", dst);
+                        emit!(out, "\tlea {}, [{}+{}@tpoff]", dst, dst, lb);
                     }
-                    Loadw(ref r, offset) => {
-                        print!(
-                            "\tmov {}, {} [rbp-{}]\n",
-                            selreg(r),
-                            memoryaccesssize(r),
-                            offset
+                    Lea(ref r, ref addr) => {
+                        emit!(
+                            out,
+                            "\tlea {}, {}",
+                            X64_REG64[r.rr as usize],
+                            memoryaddress(addr)
                         );
                     }
                     Bop(binop, ref r1, ref r2) => {
-                        let op = match binop {
-                            Binop::Add => "add",
-                            Binop::Sub => "sub",
-                            Binop::Mul => "mul",
-                        };
-                        match r2 {
-                            RegorNum::Reg(r) => {
-                                if op == "add" || op == "sub" {
-                                    print!("\t{} {}, {}\n", op, selreg(r1), selreg(r));
-                                } else {
-                                    assert_eq!(op, "mul");
-                                    print!("\tmov {}, {}\n", selrax(r.regsize as usize), selreg(r));
-                                    print!("\timul {}\n", selreg(r1));
-                                    print!("\tmov {}, {}\n", selreg(r1), selrax(r.regsize as usize))
-                                }
-                            }
-                            RegorNum::Num(num) => {
-                                assert!(op == "add" || op == "sub");
-                                print!("\t{} {}, {}\n", op, selreg(r1), num);
-                            }
-                        }
-                        if secure_mode {
-                            if op == "add" || op == "mul" {
-                                gen_jmp_overflow(overflow_black_label.clone());
-                            }
+                        genbop(out, binop, r1, r2);
+                        if secure_mode && (binop == Binop::Add || binop == Binop::Mul) {
+                            gen_jmp_overflow(out, overflow_black_label.clone());
                         }
                     }
-                    Call(ref r1, lb, ref args, mut usedrs) => {
-                        for i in &usedrs {
-                            print!("\tpush {}\n", X64_REG64[*i]);
+                    Call(ref r1, lb, ref args, ref usedrs, ref abi) => {
+                        // registers living across the call are saved under the local variables
+                        for (i, r) in usedrs.iter().enumerate() {
+                            let mov = if r.fp { "movsd" } else { "mov" };
+                            emit!(
+                                out,
+                                "\t{} QWORD PTR [rbp-{}], {}",
+                                mov,
+                                savebase + (i as i32 + 1) * 8,
+                                selsizedreg8(r)
+                            );
                         }
-                        for i in 0..args.len() {
-                            match args[i] {
-                                RegorNum::Reg(ref r) => {
-                                    if let Some(gl_lb) = r.global {
-                                        print!(
-                                            "\tmov {}, OFFSET FLAT:{}\n",
-                                            selargreg(r.regsize as usize, i),
-                                            gl_lb
-                                        );
-                                    } else {
-                                        print!(
-                                            "\tmov {}, {}\n",
-                                            selargreg(r.regsize as usize, i),
-                                            selreg(r)
+                        // aggregates passed in memory are copied to the outgoing area
+                        for (arg, loc) in args {
+                            if let (RegorNum::Reg(r), ArgLoc::Memory(offset, argsize)) = (arg, loc)
+                            {
+                                let buf = selsizedreg(NORMALREGQUANTITY as i32 - 1, 8);
+                                emit!(out, "\tmov {}, {}", buf, X64_REG64[r.rr as usize]);
+                                gencopy(out, &format!("rsp+{}", offset), buf, *argsize);
+                            }
+                        }
+                        // eightbytes of the aggregates passed in the registers
+                        for (arg, loc) in args {
+                            match (arg, loc) {
+                                (RegorNum::Reg(ref r), ArgLoc::Aggre(parts)) => {
+                                    // load each eightbyte through the buffer register
+                                    let buf = selsizedreg(NORMALREGQUANTITY as i32 - 1, 8);
+                                    emit!(out, "\tmov {}, {}", buf, X64_REG64[r.rr as usize]);
+                                    for (i, (sse, index)) in parts.iter().enumerate() {
+                                        let (mov, dst) = if *sse {
+                                            ("movsd", selxmmargreg(*index))
+                                        } else {
+                                            ("mov", selargreg(8, *index))
+                                        };
+                                        emit!(
+                                            out,
+                                            "\t{} {}, QWORD PTR [{}+{}]",
+                                            mov,
+                                            dst,
+                                            buf,
+                                            i * 8
                                         );
                                     }
                                 }
-                                RegorNum::Num(num) => {
-                                    print!("\tmov {}, {}\n", selargreg(4, i), num);
-                                }
+                                (_, ArgLoc::Memory(..)) => {}
+                                _ => panic!("unsupported argument location {:?}", loc),
                             }
                         }
-                        if lb == "printf" {
-                            print!("\tmov eax, 0\n");
+                        if let Some(AggRet {
+                            classes: None,
+                            slot,
+                            ..
+                        }) = abi.aggret
+                        {
+                            // the memory receiving the result
+                            emit!(out, "\tlea rdi, [rbp-{}]", slot);
+                        }
+                        if abi.variadic {
+                            // the number of vector registers used
+                            emit!(out, "\tmov eax, {}", abi.sseregs);
                         }
-                        print!("\tcall {}\n", lb);
-                        usedrs.reverse();
-                        for i in usedrs {
-                            print!("\tpop {}\n", X64_REG64[i]);
+                        emit!(out, "\tcall {}", lb);
+                        for (i, r) in usedrs.iter().enumerate() {
+                            let mov = if r.fp { "movsd" } else { "mov" };
+                            emit!(
+                                out,
+                                "\t{} {}, QWORD PTR [rbp-{}]",
+                                mov,
+                                selsizedreg8(r),
+                                savebase + (i as i32 + 1) * 8
+                            );
                         }
-                        if r1.regsize > 0 {
-                            print!("\tmov {}, {}\n", selreg(r1), selrax(r1.regsize as usize));
+                        if let Some(aggret) = &abi.aggret {
+                            // store the result to the stack slot and refer it by the address
+                            if let Some(classes) = &aggret.classes {
+                                for (i, reg) in selretregs(classes).into_iter().enumerate() {
+                                    let mov = if reg.starts_with("xmm") {
+                                        "movsd"
+                                    } else {
+                                        "mov"
+                                    };
+                                    emit!(
+                                        out,
+                                        "\t{} QWORD PTR [rbp-{}], {}",
+                                        mov,
+                                        aggret.slot - i as i32 * 8,
+                                        reg
+                                    );
+                                }
+                            }
+                            emit!(out, "\tlea {}, [rbp-{}]", selreg(r1), aggret.slot);
+                        } else if r1.fp {
+                            emit!(out, "\tmovaps {}, xmm0", selreg(r1));
+                        } else if r1.regsize > 0 {
+                            emit!(out, "\tmov {}, {}", selreg(r1), selrax(r1.regsize as usize));
                         }
                     }
                     Comp(op, ref r1, ref r2, ref rorn) => {
                         match rorn {
+                            RegorNum::Reg(r3) if r2.fp => {
+                                genfcomp(out, op, X64_REG8[r1.rr as usize], r2, r3);
+                            }
                             RegorNum::Reg(r3) => {
-                                print!("\tcmp {}, {}\n", selreg(r2), selreg(r3));
+                                emit!(
+                                    out,
+                                    "\tcmp {}, {}",
+                                    selreg(r2),
+                                    selsizedreg(r3.rr, r2.regsize)
+                                );
                             }
                             RegorNum::Num(num) => {
-                                print!("\tcmp {}, {}\n", selreg(r2), num);
+                                emit!(out, "\tcmp {}, {}", selreg(r2), num);
                             }
                         }
-                        match op {
-                            CompOp::Ceqw => {
-                                print!("\tsete {}\n", X64_REG8[r1.rr as usize]);
-                            }
-                            CompOp::Csltw => {
-                                print!("\tsetl {}\n", X64_REG8[r1.rr as usize]);
-                            }
+                        if !r2.fp {
+                            emit!(out, "\tset{} {}", selcond(op), X64_REG8[r1.rr as usize]);
                         }
-                        print!(
-                            "\tmovzb {}, {}\n",
-                            X64_REG64[r1.rr as usize], X64_REG8[r1.rr as usize]
+                        emit!(
+                            out,
+                            "\tmovzx {}, {}",
+                            X64_REG32[r1.rr as usize],
+                            X64_REG8[r1.rr as usize]
                         );
                     }
                     Jnz(ref r1, lb1, lb2) => {
-                        print!("\tcmp {}, 0\n", selreg(r1));
-                        print!("\tjne {}\n", lb1);
-                        print!("\tjmp {}\n", lb2);
+                        emit!(out, "\tcmp {}, 0", selreg(r1));
+                        emit!(out, "\tjne {}", lb1);
+                        emit!(out, "\tjmp {}", lb2);
+                    }
+                    Vastart(ref addr, vasave) => {
+                        genvastart(out, addr, vasave);
+                    }
+                    Vaarg(ref r, ref addr) => {
+                        genvaarg(out, r, addr);
                     }
                     Jmp(lb) => {
-                        print!("\tjmp {}\n", lb);
+                        emit!(out, "\tjmp {}", lb);
                     }
                     LowNop => {
                         panic!("cannot reach this instr.");
@@ -282,6 +1023,7 @@ pub fn gen_x64code(lirpg: LowIrProgram, secure_mode: bool) {
         }
     }
     if secure_mode {
-        gen_overflow_block(num_overflow_label, overflow_black_label);
+        gen_overflow_block(out, num_overflow_label, overflow_black_label);
     }
+    asm
 }
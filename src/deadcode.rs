@@ -3,39 +3,26 @@ use super::parser::*;
 use super::*;
 use std::collections::HashMap;
 
-struct BlockInfos {
-    pub lbids: HashMap<Label, usize>,
+struct BlockInfos<'a> {
+    pub lbids: HashMap<Label<'a>, usize>,
     pub livings: Vec<usize>,
-    pub livcah: Vec<usize>,
 }
 
-impl BlockInfos {
+impl<'a> BlockInfos<'a> {
     fn new() -> Self {
         Self {
             lbids: HashMap::new(),
             livings: vec![],
-            livcah: vec![],
         }
     }
-    fn newbl(&mut self, lb: Label, id: usize) {
+    fn newbl(&mut self, lb: Label<'a>, id: usize) {
         self.lbids.insert(lb, id);
         self.livings.push(0);
     }
-    fn newlivbl(&mut self, lb: Label) {
+    fn newlivbl(&mut self, lb: Label<'a>) {
         let id = self.lbids.get(lb).unwrap();
         self.livings[*id] = 1;
     }
-    fn bb_is_empty(&self, lb: Label) -> usize {
-        let id = self.lbids.get(lb).unwrap();
-        self.livings[*id]
-    }
-    pub fn init_livcah(&mut self) {
-        let mut c = 0;
-        for d in &self.livings {
-            c += d;
-            self.livcah.push(c);
-        }
-    }
 }
 
 pub fn removeuselessinstr(ssapg: &mut SsaProgram) {
@@ -50,12 +37,21 @@ pub fn removeuselessinstr(ssapg: &mut SsaProgram) {
             for isr in &mut bb.instrs {
                 isr.bblb = bb.lb;
                 match &isr.op {
-                    Ret(..) | Call(..) | Jmp(..) | Jnz(..) => {
+                    Ret(..) | Call(..) | Vastart(..) | Jmp(..) | Jnz(..) => {
+                        isr.living = true;
+                        defliveisrs.push(isr);
+                        bbinfos.newlivbl(bb.lb);
+                    }
+                    // stores through the pointer other than alloc can't be removed
+                    Store(_, _, var) if var.ty == VarType::Long => {
                         isr.living = true;
                         defliveisrs.push(isr);
                         bbinfos.newlivbl(bb.lb);
                     }
-                    Assign(_, var, ..) | Alloc4(var, _) | Storew(_, var) | Comp(_, var, ..) => {
+                    Assign(_, var, ..)
+                    | Alloc(var, ..)
+                    | Store(_, _, var)
+                    | Comp(_, _, var, ..) => {
                         if let Some(tis) = nrmisrs.get_mut(var.name) {
                             tis.push(isr);
                         } else {
@@ -73,7 +69,7 @@ pub fn removeuselessinstr(ssapg: &mut SsaProgram) {
     }
     // let mut jmpzs = vec![];
     while let Some(isr) = defliveisrs.pop() {
-        let varnames = findvarsininstr(&isr);
+        let varnames = findvarsininstr(isr);
         // let mut f = |hashs: &mut HashMap<&str, &mut SsaInstr>, varn: &str| {
         //     if let Some(isr2) = hashs.remove(varn) {
         //         isr2.living = true;
@@ -111,21 +107,21 @@ pub fn removeuselessinstr(ssapg: &mut SsaProgram) {
     // }
 }
 
-fn findvarsininstr(isr: &SsaInstr) -> Vec<VarName> {
+fn findvarsininstr<'a>(isr: &SsaInstr<'a>) -> Vec<VarName<'a>> {
     let mut varnames = vec![];
     match &isr.op {
-        Ret(fco) | Src(fco) => {
+        Ret(fco) | Src(fco) | Ext(_, fco) | Cvt(_, _, fco) => {
             if let FirstClassObj::Variable(var) = fco {
                 varnames.push(var.name);
             }
         }
         Assign(.., ssainstr) => {
-            varnames = [varnames, findvarsininstr(&ssainstr)].concat();
+            varnames = [varnames, findvarsininstr(ssainstr)].concat();
         }
-        Loadw(var) | Jnz(var, ..) => {
+        Load(_, var) | Vastart(var) | Vaarg(_, var) | Jnz(var, ..) => {
             varnames.push(var.name);
         }
-        Storew(fco, var) => {
+        Store(_, fco, var) => {
             varnames.push(var.name);
             if let FirstClassObj::Variable(var2) = fco {
                 varnames.push(var2.name);
@@ -146,11 +142,13 @@ fn findvarsininstr(isr: &SsaInstr) -> Vec<VarName> {
                 }
             }
         }
-        Comp(_, var1, var2, fco) => {
-            varnames.push(var1.name);
-            varnames.push(var2.name);
-            if let FirstClassObj::Variable(var3) = fco {
-                varnames.push(var3.name);
+        Comp(_, _, var, fco1, fco2) => {
+            varnames.push(var.name);
+            if let FirstClassObj::Variable(var1) = fco1 {
+                varnames.push(var1.name);
+            }
+            if let FirstClassObj::Variable(var2) = fco2 {
+                varnames.push(var2.name);
             }
         }
         Phi(_, vs) => {
@@ -160,7 +158,7 @@ fn findvarsininstr(isr: &SsaInstr) -> Vec<VarName> {
                 }
             }
         }
-        Jmp(_) | Alloc4(..) | Nop => {}
+        Jmp(_) | Alloc(..) | Nop => {}
         DummyOp => {
             panic!("must not reach dummyOp.");
         }
@@ -0,0 +1,101 @@
+use std::fmt;
+
+// error found while compiling the program
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostic {
+    // name of the source file (empty when the program is not read from a file)
+    pub file: String,
+    // 1-origin line and column of the span start (0 when the error has no location)
+    pub line: usize,
+    pub col: usize,
+    // byte range of the offending token in the source
+    pub span: (usize, usize),
+    pub message: String,
+}
+
+impl Diagnostic {
+    // error without the location (e.g. found after the parsing)
+    pub fn new(message: String) -> Self {
+        Self {
+            message,
+            ..Default::default()
+        }
+    }
+    // error at the byte range span of program
+    pub fn at(program: &str, span: (usize, usize), message: String) -> Self {
+        let poss = span.0.min(program.len());
+        let before = &program[..poss];
+        let line = before.matches('\n').count() + 1;
+        let linestart = before.rfind('\n').map_or(0, |p| p + 1);
+        let col = program[linestart..poss].chars().count() + 1;
+        Self {
+            file: String::new(),
+            line,
+            col,
+            span: (poss, span.1.max(poss)),
+            message,
+        }
+    }
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+    pub fn haslocation(&self) -> bool {
+        self.line > 0
+    }
+    fn filename(&self) -> &str {
+        if self.file.is_empty() {
+            "<source>"
+        } else {
+            &self.file
+        }
+    }
+    // rustc style message with the caret under the offending token
+    pub fn render(&self, program: &str) -> String {
+        if !self.haslocation() {
+            return format!("{}\n", self);
+        }
+        let linestart = program[..self.span.0.min(program.len())]
+            .rfind('\n')
+            .map_or(0, |p| p + 1);
+        let text = program[linestart..].lines().next().unwrap_or("");
+        let lineno = self.line.to_string();
+        let pad = " ".repeat(lineno.len());
+        // tabs are kept so that the caret lines up with the source line
+        let indent: String = text
+            .chars()
+            .take(self.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = program
+            .get(self.span.0..self.span.1)
+            .map_or(0, |s| s.lines().next().unwrap_or("").chars().count())
+            .max(1);
+        format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.message,
+            pad,
+            self.filename(),
+            self.line,
+            self.col,
+            pad,
+            lineno,
+            text,
+            pad,
+            indent,
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.message)?;
+        if self.haslocation() {
+            write!(f, "\n --> {}:{}:{}", self.filename(), self.line, self.col)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
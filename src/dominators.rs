@@ -32,7 +32,7 @@ impl DomUnionFind {
             self.mn[v] = self.mn[self.pars[v]];
         }
         self.pars[v] = r;
-        return r;
+        r
     }
     fn eval(&mut self, v: usize, sdom: &Vec<usize>) -> usize {
         self.find(v, sdom);
@@ -50,6 +50,8 @@ pub struct ControlFlowGraph {
     pub vertex: Vec<usize>,
     pub weight: usize,
     pub parents: Vec<usize>,
+    // children of each block in the dominator tree
+    pub domtree: Vec<Vec<usize>>,
 }
 
 impl ControlFlowGraph {
@@ -61,6 +63,7 @@ impl ControlFlowGraph {
             vertex: vec![0; lg],
             weight: 0,
             parents: vec![0; lg],
+            domtree: vec![vec![]; lg],
         }
     }
     fn dfs(&mut self, sdom: &mut Vec<usize>, v: usize) {
@@ -69,7 +72,7 @@ impl ControlFlowGraph {
         self.weight += 1;
         for i in 0..self.graph[v].len() {
             let u = self.graph[v][i];
-            if sdom[u] == std::usize::MAX {
+            if sdom[u] == usize::MAX {
                 self.parents[u] = v;
                 self.dfs(sdom, u);
             }
@@ -89,8 +92,8 @@ struct DominatorsTree {
 impl DominatorsTree {
     fn new(n: usize) -> Self {
         Self {
-            sdom: vec![std::usize::MAX; n],
-            idom: vec![std::usize::MAX; n],
+            sdom: vec![usize::MAX; n],
+            idom: vec![usize::MAX; n],
             colu: vec![0; n],
             bucket: vec![vec![]; n],
             tree: vec![vec![]; n],
@@ -134,8 +137,8 @@ impl DominatorsTree {
     fn make_bb_domtree(&mut self, bbs: &mut Vec<SsaBlock>, n: usize) -> ControlFlowGraph {
         let mut bbids = HashMap::new();
 
-        for i in 0..n {
-            bbids.insert(bbs[i].lb, bbs[i].id);
+        for bb in bbs.iter().take(n) {
+            bbids.insert(bb.lb, bb.id);
         }
 
         // make graph and rgraph
@@ -160,10 +163,11 @@ impl DominatorsTree {
         // save idom and tree structure
         for bb in bbs {
             bb.idom = self.idom[bb.id];
-            if bb.idom != std::usize::MAX {
+            if bb.idom != usize::MAX {
                 self.tree[bb.idom].push(bb.id);
             }
         }
+        cfg.domtree.clone_from(&self.tree);
 
         cfg
     }
@@ -177,7 +181,7 @@ struct DominatorFrontier {
 impl DominatorFrontier {
     fn new(n: usize) -> Self {
         Self {
-            domf: vec![vec![std::usize::MAX; 1]; n],
+            domf: vec![vec![usize::MAX; 1]; n],
         }
     }
     fn compute(
@@ -198,7 +202,7 @@ impl DominatorFrontier {
 
         // Y for (for all Z in child(X), { Y in DF(Z) and IDOM(Y) != X })
         for child_x in &domt.tree[bbi] {
-            if let Some(&std::usize::MAX) = self.domf[*child_x].first() {
+            if let Some(&usize::MAX) = self.domf[*child_x].first() {
                 self.compute(cfg, domt, *child_x, bbs);
             }
             for y in &self.domf[*child_x] {
@@ -0,0 +1,914 @@
+use super::diagnostic::Diagnostic;
+use super::lexer::Binop;
+use super::parser::SsaInstrOp::*;
+use super::parser::*;
+use super::verify::Form;
+use super::Label;
+use std::collections::HashMap;
+use std::panic;
+use std::thread;
+
+// instructions executed before giving up (e.g. an infinite loop made by a broken pass)
+const STEPLIMIT: usize = 50_000_000;
+// depth of the nested calls
+const CALLLIMIT: usize = 4096;
+// stack of the interpreter thread, enough for CALLLIMIT nested calls in the debug build
+const STACKSIZE: usize = 1 << 28;
+
+// result of running main
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    // return value of main (the value of ret even if main has no return type)
+    pub ret: i64,
+    pub stdout: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value {
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    // integer view (the bits of the floating point)
+    fn int(self) -> i64 {
+        match self {
+            Value::Int(num) => num,
+            Value::Float(num) => num.to_bits() as i64,
+        }
+    }
+    fn float(self) -> f64 {
+        match self {
+            Value::Int(num) => num as f64,
+            Value::Float(num) => num,
+        }
+    }
+}
+
+// value of valty made from the result of the computation
+fn norm(val: Value, valty: ValueType) -> Value {
+    use ValueType::*;
+    match valty {
+        Word => Value::Int(val.int() as i32 as i64),
+        Half => Value::Int(val.int() as i16 as i64),
+        Byte => Value::Int(val.int() as i8 as i64),
+        Single => Value::Float(val.float() as f32 as f64),
+        Double => Value::Float(val.float()),
+        Long | Z => Value::Int(val.int()),
+    }
+}
+
+// memory is divided into the segments (global data, stack slot or heap block) so that the
+// accesses out of them are detected. the address is the segment number in the upper 32 bits
+// and the offset in the lower 32 bits.
+struct Segment {
+    bytes: Vec<u8>,
+    live: bool,
+}
+
+fn segaddr(seg: usize) -> i64 {
+    (seg as i64) << 32
+}
+
+// escape sequences in the string of the textual IR (same as the assembler)
+fn unescape(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut res = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            res.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match bytes[i] {
+            b'n' => res.push(b'\n'),
+            b't' => res.push(b'\t'),
+            b'r' => res.push(b'\r'),
+            b'0'..=b'7' => {
+                let mut num = 0u32;
+                let start = i;
+                while i < bytes.len() && i < start + 3 && (b'0'..=b'7').contains(&bytes[i]) {
+                    num = num * 8 + (bytes[i] - b'0') as u32;
+                    i += 1;
+                }
+                res.push(num as u8);
+                continue;
+            }
+            c => res.push(c),
+        }
+        i += 1;
+    }
+    res
+}
+
+// local variables and the variadic arguments of the running function
+struct Frame<'a, 'b> {
+    func: &'b SsaFunction<'a>,
+    vars: HashMap<i32, Value>,
+    varargs: Vec<Value>,
+    // stack slots released at the return
+    slots: Vec<usize>,
+    bblb: Label<'a>,
+}
+
+impl<'a, 'b> Frame<'a, 'b> {
+    fn error(&self, message: String) -> Diagnostic {
+        Diagnostic::new(format!("${} @{}: {}", self.func.name, self.bblb, message))
+    }
+}
+
+// what follows the instruction
+enum Flow<'a> {
+    Next,
+    Jump(Label<'a>),
+    Return(Value),
+}
+
+struct Interpreter<'a, 'b> {
+    spg: &'b SsaProgram<'a>,
+    form: Form,
+    segs: Vec<Segment>,
+    // address of the global data and the functions
+    globals: HashMap<&'a str, i64>,
+    strings: HashMap<&'a str, i64>,
+    // position of vastart
+    valists: Vec<(Vec<Value>, usize)>,
+    stdout: Vec<u8>,
+    steps: usize,
+    depth: usize,
+}
+
+impl<'a, 'b> Interpreter<'a, 'b> {
+    fn new(spg: &'b SsaProgram<'a>, form: Form) -> Self {
+        let mut ip = Self {
+            spg,
+            form,
+            // segment 0 makes the null address invalid
+            segs: vec![Segment {
+                bytes: vec![],
+                live: false,
+            }],
+            globals: HashMap::new(),
+            strings: HashMap::new(),
+            valists: vec![],
+            stdout: vec![],
+            steps: 0,
+            depth: 0,
+        };
+        for func in &spg.funcs {
+            let addr = ip.newseg(0);
+            ip.globals.insert(func.name, addr);
+        }
+        for gd in &spg.gvs {
+            let addr = ip.newseg(0);
+            ip.globals.insert(gd.lb, addr);
+        }
+        // global data may refer to the others
+        for gd in &spg.gvs {
+            let mut bytes = vec![];
+            for item in &gd.dts {
+                match item {
+                    DataItem::Num(ty, num) => {
+                        let size = ty.stacksize() as usize;
+                        bytes.extend(&num.to_le_bytes()[..size]);
+                    }
+                    DataItem::Float(VarType::Single, num) => {
+                        bytes.extend((*num as f32).to_bits().to_le_bytes())
+                    }
+                    DataItem::Float(_, num) => bytes.extend(num.to_bits().to_le_bytes()),
+                    DataItem::String(text) => bytes.extend(unescape(text)),
+                    DataItem::Symbol(lb, offset) => {
+                        let addr = ip.globals.get(lb).copied().unwrap_or(0) + *offset as i64;
+                        bytes.extend(addr.to_le_bytes());
+                    }
+                    DataItem::Zero(size) => bytes.extend(vec![0; *size as usize]),
+                }
+            }
+            let seg = (ip.globals[gd.lb] >> 32) as usize;
+            ip.segs[seg].bytes = bytes;
+        }
+        ip
+    }
+    fn newseg(&mut self, size: usize) -> i64 {
+        self.segs.push(Segment {
+            bytes: vec![0; size],
+            live: true,
+        });
+        segaddr(self.segs.len() - 1)
+    }
+    // bytes [addr, addr + size) in the live segment
+    fn memory(&mut self, addr: i64, size: usize) -> Result<&mut [u8], String> {
+        let seg = (addr as u64 >> 32) as usize;
+        let offset = (addr as u64 & 0xffff_ffff) as usize;
+        match self.segs.get_mut(seg) {
+            Some(s) if s.live && offset + size <= s.bytes.len() => {
+                Ok(&mut s.bytes[offset..offset + size])
+            }
+            Some(s) if !s.live && seg > 0 => {
+                Err(format!("access to the released memory at {:#x}", addr))
+            }
+            _ => Err(format!(
+                "invalid memory access of {} bytes at {:#x}",
+                size, addr
+            )),
+        }
+    }
+    fn load(&mut self, addr: i64, memty: ValueType, signed: bool) -> Result<Value, String> {
+        let size = memty.bytesize() as usize;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(self.memory(addr, size)?);
+        let bits = u64::from_le_bytes(buf);
+        Ok(match memty {
+            ValueType::Single => Value::Float(f32::from_bits(bits as u32) as f64),
+            ValueType::Double => Value::Float(f64::from_bits(bits)),
+            _ if signed => norm(Value::Int(bits as i64), memty),
+            _ => Value::Int(bits as i64),
+        })
+    }
+    fn store(&mut self, addr: i64, valty: ValueType, val: Value) -> Result<(), String> {
+        let bits = match (valty, val) {
+            (ValueType::Single, Value::Float(num)) => (num as f32).to_bits() as u64,
+            (ValueType::Double, Value::Float(num)) => num.to_bits(),
+            _ => val.int() as u64,
+        };
+        let size = valty.bytesize() as usize;
+        self.memory(addr, size)?
+            .copy_from_slice(&bits.to_le_bytes()[..size]);
+        Ok(())
+    }
+    // NUL terminated string at addr
+    fn cstring(&mut self, addr: i64) -> Result<Vec<u8>, String> {
+        let mut res = vec![];
+        loop {
+            let byte = self.memory(addr + res.len() as i64, 1)?[0];
+            if byte == 0 {
+                return Ok(res);
+            }
+            res.push(byte);
+        }
+    }
+    // copy of the aggregate passed or returned by value
+    fn copyagg(&mut self, addr: i64, agg: &AggType) -> Result<i64, String> {
+        let bytes = self.memory(addr, agg.size as usize)?.to_vec();
+        let dst = self.newseg(0);
+        self.segs[(dst >> 32) as usize].bytes = bytes;
+        Ok(dst)
+    }
+    fn operand(&mut self, frame: &Frame<'a, 'b>, fco: &FirstClassObj<'a>) -> Result<Value, String> {
+        match fco {
+            FirstClassObj::Variable(var) => self.variable(frame, var),
+            FirstClassObj::Num(_, num) => Ok(Value::Int(*num)),
+            FirstClassObj::Float(_, num) => Ok(Value::Float(*num)),
+            FirstClassObj::String(text) => {
+                if let Some(addr) = self.strings.get(text) {
+                    return Ok(Value::Int(*addr));
+                }
+                let mut bytes = unescape(text);
+                bytes.push(0);
+                let addr = self.newseg(0);
+                self.segs[(addr >> 32) as usize].bytes = bytes;
+                self.strings.insert(text, addr);
+                Ok(Value::Int(addr))
+            }
+        }
+    }
+    fn variable(&mut self, frame: &Frame<'a, 'b>, var: &Var<'a>) -> Result<Value, String> {
+        if let Some(lb) = var.global {
+            return match self.globals.get(lb) {
+                Some(addr) => Ok(Value::Int(*addr)),
+                None => Err(format!("undefined symbol ${}", lb)),
+            };
+        }
+        match frame.vars.get(&var.rg_vr) {
+            Some(val) => Ok(*val),
+            None => Err(format!("{} is used before the assignment", var)),
+        }
+    }
+    // instructions executed in the form
+    fn instrs<'c>(&self, bb: &'c SsaBlock<'a>) -> impl Iterator<Item = &'c SsaInstr<'a>> {
+        let form = self.form;
+        bb.instrs
+            .iter()
+            .filter(move |instr| form == Form::Parsed || instr.living)
+    }
+    fn call(&mut self, funlb: Label<'a>, args: Vec<Value>) -> Result<Option<Value>, Diagnostic> {
+        let spg = self.spg;
+        let Some(func) = spg.funcs.iter().find(|func| func.name == funlb) else {
+            return self.builtin(funlb, args).map_err(Diagnostic::new);
+        };
+        if self.depth == CALLLIMIT {
+            return Err(Diagnostic::new(format!("${}: too deep calls", funlb)));
+        }
+        let mut frame = Frame {
+            func,
+            vars: HashMap::new(),
+            varargs: vec![],
+            slots: vec![],
+            bblb: func.bls.first().map_or("", |bb| bb.lb),
+        };
+        if args.len() < func.args.len() {
+            return Err(frame.error(format!(
+                "{} arguments are passed to {} parameters",
+                args.len(),
+                func.args.len()
+            )));
+        }
+        for (i, val) in args.into_iter().enumerate() {
+            match func.args.get(i) {
+                Some(param) => {
+                    let val = match &param.ty {
+                        VarType::Aggre(agg) => {
+                            Value::Int(self.copyagg(val.int(), agg).map_err(|e| frame.error(e))?)
+                        }
+                        ty => norm(val, ty.tovalty()),
+                    };
+                    frame.vars.insert(param.rg_vr, val);
+                }
+                None => frame.varargs.push(val),
+            }
+        }
+        self.depth += 1;
+        let res = self.execfun(&mut frame);
+        self.depth -= 1;
+        for seg in frame.slots {
+            self.segs[seg] = Segment {
+                bytes: vec![],
+                live: false,
+            };
+        }
+        res
+    }
+    fn execfun(&mut self, frame: &mut Frame<'a, 'b>) -> Result<Option<Value>, Diagnostic> {
+        let func = frame.func;
+        let mut cur = 0;
+        let mut pred: Option<Label<'a>> = None;
+        loop {
+            let Some(bb) = func.bls.get(cur) else {
+                return Err(frame.error("the last block doesn't end in ret".to_string()));
+            };
+            frame.bblb = bb.lb;
+            // phis read the values at the end of the predecessor at once
+            let mut phivals = vec![];
+            for instr in self.instrs(bb) {
+                if let Assign(valty, var, rhs) = &instr.op {
+                    if let Phi(_, pv) = &rhs.op {
+                        let fco = pred
+                            .and_then(|lb| pv.iter().find(|(plb, _)| *plb == lb))
+                            .map(|(_, fco)| fco)
+                            .ok_or_else(|| {
+                                frame.error(format!(
+                                    "phi of {} has no operand for @{}",
+                                    var,
+                                    pred.unwrap_or("")
+                                ))
+                            })?;
+                        let val = self.operand(frame, fco).map_err(|e| frame.error(e))?;
+                        phivals.push((var.rg_vr, norm(val, *valty)));
+                    }
+                }
+            }
+            frame.vars.extend(phivals);
+            let mut next = cur + 1;
+            for instr in self.instrs(bb) {
+                self.steps += 1;
+                if self.steps > STEPLIMIT {
+                    return Err(frame.error("too many steps".to_string()));
+                }
+                match self.exec(frame, &instr.op)? {
+                    Flow::Next => {}
+                    Flow::Jump(lb) => {
+                        next = func
+                            .bls
+                            .iter()
+                            .position(|bb| bb.lb == lb)
+                            .ok_or_else(|| frame.error(format!("jump to undefined @{}", lb)))?;
+                        break;
+                    }
+                    Flow::Return(val) => {
+                        return match &func.retty {
+                            VarType::Aggre(agg) => {
+                                let addr =
+                                    self.copyagg(val.int(), agg).map_err(|e| frame.error(e))?;
+                                Ok(Some(Value::Int(addr)))
+                            }
+                            // the value is left in rax as the compiled function does (e.g. the
+                            // exit status of main without the return type)
+                            VarType::Void => Ok(Some(val)),
+                            retty => Ok(Some(norm(val, retty.tovalty()))),
+                        };
+                    }
+                }
+            }
+            pred = Some(bb.lb);
+            cur = next;
+        }
+    }
+    fn exec(
+        &mut self,
+        frame: &mut Frame<'a, 'b>,
+        op: &SsaInstrOp<'a>,
+    ) -> Result<Flow<'a>, Diagnostic> {
+        match op {
+            Assign(valty, var, rhs) => {
+                if let Phi(..) = rhs.op {
+                    return Ok(Flow::Next);
+                }
+                let val = match &rhs.op {
+                    Call(..) => self.execcall(frame, &rhs.op)?,
+                    op => Some(self.eval(frame, *valty, op).map_err(|e| frame.error(e))?),
+                };
+                let val =
+                    val.ok_or_else(|| frame.error(format!("{} is assigned no value", var)))?;
+                let val = match var.ty {
+                    // aggregate is referred by its address
+                    VarType::Aggre(_) => val,
+                    _ => norm(val, *valty),
+                };
+                frame.vars.insert(var.rg_vr, val);
+            }
+            Alloc(var, _, size) => {
+                let addr = self.newseg(*size as usize);
+                frame.slots.push((addr >> 32) as usize);
+                frame.vars.insert(var.rg_vr, Value::Int(addr));
+            }
+            Store(valty, fco, var) => {
+                let res = self.operand(frame, fco).and_then(|val| {
+                    let addr = self.variable(frame, var)?.int();
+                    self.store(addr, *valty, val)
+                });
+                res.map_err(|e| frame.error(e))?;
+            }
+            Comp(cop, valty, var, fco1, fco2) => {
+                let val = self
+                    .operand(frame, fco1)
+                    .and_then(|lhs| Ok((lhs, self.operand(frame, fco2)?)))
+                    .map(|(lhs, rhs)| comp(*cop, *valty, lhs, rhs))
+                    .map_err(|e| frame.error(e))?;
+                frame.vars.insert(var.rg_vr, norm(val, var.ty.tovalty()));
+            }
+            Call(..) => {
+                self.execcall(frame, op)?;
+            }
+            Vastart(ap) => {
+                let res = self.variable(frame, ap).and_then(|addr| {
+                    self.valists.push((frame.varargs.clone(), 0));
+                    let handle = self.valists.len() as i64 - 1;
+                    self.store(addr.int(), ValueType::Long, Value::Int(handle))
+                });
+                res.map_err(|e| frame.error(e))?;
+            }
+            Jnz(var, lb1, lb2) => {
+                let cond = self.variable(frame, var).map_err(|e| frame.error(e))?;
+                let lb = if norm(cond, ValueType::Word).int() != 0 {
+                    lb1
+                } else {
+                    lb2
+                };
+                return Ok(Flow::Jump(lb));
+            }
+            Jmp(lb) => return Ok(Flow::Jump(lb)),
+            Ret(fco) => {
+                let val = self.operand(frame, fco).map_err(|e| frame.error(e))?;
+                return Ok(Flow::Return(val));
+            }
+            Nop | DummyOp => {}
+            op => {
+                return Err(frame.error(format!("`{}` is not assigned", op)));
+            }
+        }
+        Ok(Flow::Next)
+    }
+    fn execcall(
+        &mut self,
+        frame: &Frame<'a, 'b>,
+        op: &SsaInstrOp<'a>,
+    ) -> Result<Option<Value>, Diagnostic> {
+        let Call(_, funlb, args, _) = op else {
+            unreachable!("{:?}", op);
+        };
+        let mut vals = vec![];
+        for fco in args {
+            vals.push(self.operand(frame, fco).map_err(|e| frame.error(e))?);
+        }
+        // errors in the builtin functions are reported at the call
+        self.call(funlb, vals).map_err(|diag| {
+            if self.spg.funcs.iter().any(|func| func.name == *funlb) {
+                diag
+            } else {
+                frame.error(diag.message)
+            }
+        })
+    }
+    // rhs of the assignment
+    fn eval(
+        &mut self,
+        frame: &Frame<'a, 'b>,
+        valty: ValueType,
+        op: &SsaInstrOp<'a>,
+    ) -> Result<Value, String> {
+        Ok(match op {
+            Src(fco) => self.operand(frame, fco)?,
+            Load(ldop, var) => {
+                let addr = self.variable(frame, var)?.int();
+                self.load(addr, ldop.memty(), ldop.signed())?
+            }
+            Bop(binop, fco1, fco2) => {
+                let lhs = self.operand(frame, fco1)?;
+                let rhs = self.operand(frame, fco2)?;
+                bop(*binop, valty, lhs, rhs)?
+            }
+            Ext(extop, fco) => {
+                let val = self.operand(frame, fco)?;
+                let bits = extop.srcty().bitsize();
+                let mask = (1u64 << bits) - 1;
+                let unsigned = val.int() as u64 & mask;
+                if extop.signed() {
+                    norm(Value::Int(unsigned as i64), extop.srcty())
+                } else {
+                    Value::Int(unsigned as i64)
+                }
+            }
+            Cvt(cvtop, dstty, fco) => cvt(*cvtop, *dstty, self.operand(frame, fco)?),
+            Vaarg(valty, ap) => {
+                let addr = self.variable(frame, ap)?.int();
+                let handle = self.load(addr, ValueType::Long, true)?.int() as usize;
+                let Some((vals, pos)) = self.valists.get_mut(handle) else {
+                    return Err("vaarg without vastart".to_string());
+                };
+                let val = vals
+                    .get(*pos)
+                    .copied()
+                    .ok_or("vaarg after the last argument")?;
+                *pos += 1;
+                norm(val, *valty)
+            }
+            op => return Err(format!("`{}` cannot be assigned", op)),
+        })
+    }
+    fn builtin(&mut self, funlb: &str, args: Vec<Value>) -> Result<Option<Value>, String> {
+        let arg = |i: usize| {
+            args.get(i)
+                .copied()
+                .ok_or_else(|| format!("too few arguments to ${}", funlb))
+        };
+        match funlb {
+            "printf" => {
+                let out = self.format(arg(0)?.int(), &args[1..])?;
+                self.stdout.extend(&out);
+                Ok(Some(Value::Int(out.len() as i64)))
+            }
+            "snprintf" => {
+                let (buf, size) = (arg(0)?.int(), arg(1)?.int() as usize);
+                let mut out = self.format(arg(2)?.int(), &args[3..])?;
+                let len = out.len();
+                if size > 0 {
+                    out.truncate(size - 1);
+                    out.push(0);
+                    self.memory(buf, out.len())?.copy_from_slice(&out);
+                }
+                Ok(Some(Value::Int(len as i64)))
+            }
+            "puts" => {
+                let text = self.cstring(arg(0)?.int())?;
+                self.stdout.extend(text);
+                self.stdout.push(b'\n');
+                Ok(Some(Value::Int(1)))
+            }
+            "malloc" => Ok(Some(Value::Int(self.newseg(arg(0)?.int() as usize)))),
+            "free" => {
+                let addr = arg(0)?.int();
+                if addr != 0 {
+                    let seg = (addr as u64 >> 32) as usize;
+                    self.memory(addr, 0)?;
+                    self.segs[seg].live = false;
+                }
+                Ok(None)
+            }
+            _ => Err(format!("call to undefined function ${}", funlb)),
+        }
+    }
+    // output of printf
+    fn format(&mut self, fmt: i64, args: &[Value]) -> Result<Vec<u8>, String> {
+        let fmt = self.cstring(fmt)?;
+        let mut args = args.iter().copied();
+        let mut out = vec![];
+        let mut i = 0;
+        while i < fmt.len() {
+            if fmt[i] != b'%' {
+                out.push(fmt[i]);
+                i += 1;
+                continue;
+            }
+            i += 1;
+            let mut spec = Spec::default();
+            while let Some(&flag @ (b'-' | b'+' | b' ' | b'0' | b'#')) = fmt.get(i) {
+                spec.flags.push(flag);
+                i += 1;
+            }
+            let num = |i: &mut usize, args: &mut dyn Iterator<Item = Value>| {
+                if fmt.get(*i) == Some(&b'*') {
+                    *i += 1;
+                    return args.next().map(|val| val.int() as i32 as usize);
+                }
+                let start = *i;
+                while fmt.get(*i).is_some_and(u8::is_ascii_digit) {
+                    *i += 1;
+                }
+                std::str::from_utf8(&fmt[start..*i]).unwrap().parse().ok()
+            };
+            spec.width = num(&mut i, &mut args).unwrap_or(0);
+            if fmt.get(i) == Some(&b'.') {
+                i += 1;
+                spec.prec = Some(num(&mut i, &mut args).unwrap_or(0));
+            }
+            let mut long = false;
+            while let Some(&(b'h' | b'l' | b'z' | b'j' | b't' | b'L')) = fmt.get(i) {
+                long |= fmt[i] != b'h';
+                i += 1;
+            }
+            let Some(&conv) = fmt.get(i) else {
+                break;
+            };
+            i += 1;
+            if conv == b'%' {
+                out.push(b'%');
+                continue;
+            }
+            let val = args
+                .next()
+                .ok_or_else(|| format!("too few arguments for `%{}`", conv as char))?;
+            let int = if long {
+                val.int()
+            } else {
+                val.int() as i32 as i64
+            };
+            let uint = if long {
+                val.int() as u64
+            } else {
+                val.int() as u32 as u64
+            };
+            let body = match conv {
+                b'd' | b'i' => spec.sign(int < 0, spec.digits(int.unsigned_abs().to_string())),
+                b'u' => spec.digits(uint.to_string()),
+                b'x' => spec.digits(format!("{:x}", uint)),
+                b'X' => spec.digits(format!("{:X}", uint)),
+                b'o' => spec.digits(format!("{:o}", uint)),
+                b'p' => format!("{:#x}", val.int()),
+                b'c' => (int as u8 as char).to_string(),
+                b's' => {
+                    let mut text = self.cstring(val.int())?;
+                    if let Some(prec) = spec.prec {
+                        text.truncate(prec);
+                    }
+                    String::from_utf8_lossy(&text).into_owned()
+                }
+                b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                    let num = val.float();
+                    spec.sign(
+                        num.is_sign_negative() && num != 0.0,
+                        spec.float(num.abs(), conv),
+                    )
+                }
+                _ => return Err(format!("unsupported conversion `%{}`", conv as char)),
+            };
+            out.extend(spec.pad(body).bytes());
+        }
+        Ok(out)
+    }
+}
+
+// conversion specification of printf
+#[derive(Default)]
+struct Spec {
+    flags: Vec<u8>,
+    width: usize,
+    prec: Option<usize>,
+}
+
+impl Spec {
+    fn has(&self, flag: u8) -> bool {
+        self.flags.contains(&flag)
+    }
+    fn digits(&self, digits: String) -> String {
+        match self.prec {
+            Some(prec) => format!("{:0>1$}", digits, prec),
+            None => digits,
+        }
+    }
+    fn sign(&self, negative: bool, body: String) -> String {
+        let sign = if negative {
+            "-"
+        } else if self.has(b'+') {
+            "+"
+        } else if self.has(b' ') {
+            " "
+        } else {
+            ""
+        };
+        format!("{}{}", sign, body)
+    }
+    fn float(&self, num: f64, conv: u8) -> String {
+        let prec = self.prec.unwrap_or(6);
+        let res = match conv {
+            b'f' | b'F' => format!("{:.*}", prec, num),
+            b'e' | b'E' => expform(num, prec),
+            _ => {
+                // %g chooses %e or %f by the exponent and removes the trailing zeros
+                let prec = prec.max(1);
+                let exp = if num == 0.0 {
+                    0
+                } else {
+                    num.abs().log10().floor() as i32
+                };
+                let text = if exp < -4 || exp >= prec as i32 {
+                    expform(num, prec - 1)
+                } else {
+                    format!("{:.*}", (prec as i32 - 1 - exp) as usize, num)
+                };
+                if self.has(b'#') {
+                    text
+                } else {
+                    trimzeros(&text)
+                }
+            }
+        };
+        if conv.is_ascii_uppercase() {
+            res.to_uppercase()
+        } else {
+            res
+        }
+    }
+    // padding to the width
+    fn pad(&self, body: String) -> String {
+        let len = body.chars().count();
+        if len >= self.width {
+            body
+        } else if self.has(b'-') {
+            format!("{}{}", body, " ".repeat(self.width - len))
+        } else if self.has(b'0') {
+            let signlen = body.starts_with(['-', '+', ' ']) as usize;
+            format!(
+                "{}{}{}",
+                &body[..signlen],
+                "0".repeat(self.width - len),
+                &body[signlen..]
+            )
+        } else {
+            format!("{}{}", " ".repeat(self.width - len), body)
+        }
+    }
+}
+
+// 1.500000e+00
+fn expform(num: f64, prec: usize) -> String {
+    let text = format!("{:.*e}", prec, num);
+    let (mantissa, exp) = text.split_once('e').unwrap();
+    let exp = exp.parse::<i32>().unwrap();
+    format!(
+        "{}e{}{:02}",
+        mantissa,
+        if exp < 0 { '-' } else { '+' },
+        exp.abs()
+    )
+}
+
+fn trimzeros(text: &str) -> String {
+    let (mantissa, exp) = match text.find('e') {
+        Some(pos) => text.split_at(pos),
+        None => (text, ""),
+    };
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{}{}", mantissa, exp)
+}
+
+fn bop(binop: Binop, valty: ValueType, lhs: Value, rhs: Value) -> Result<Value, String> {
+    use Binop::*;
+    if valty.isfloat() {
+        let (lhs, rhs) = (lhs.float(), rhs.float());
+        return Ok(Value::Float(match binop {
+            Add => lhs + rhs,
+            Sub => lhs - rhs,
+            Mul => lhs * rhs,
+            Div => lhs / rhs,
+            _ => return Err(format!("{} of the floating point", binop.name())),
+        }));
+    }
+    let bits = valty.bitsize() as u32;
+    let (lhs, rhs) = (norm(lhs, valty).int(), norm(rhs, valty).int());
+    // unsigned view of the operands
+    let mask = u64::MAX >> (64 - bits);
+    let (ulhs, urhs) = (lhs as u64 & mask, rhs as u64 & mask);
+    if matches!(binop, Div | Udiv | Rem | Urem) && rhs == 0 {
+        return Err("division by zero".to_string());
+    }
+    let shift = (rhs as u32) & (bits - 1);
+    Ok(Value::Int(match binop {
+        Add => lhs.wrapping_add(rhs),
+        Sub => lhs.wrapping_sub(rhs),
+        Mul => lhs.wrapping_mul(rhs),
+        Div => lhs.wrapping_div(rhs),
+        Rem => lhs.wrapping_rem(rhs),
+        Udiv => (ulhs / urhs) as i64,
+        Urem => (ulhs % urhs) as i64,
+        And => lhs & rhs,
+        Or => lhs | rhs,
+        Xor => lhs ^ rhs,
+        Shl => lhs.wrapping_shl(shift),
+        Shr => (ulhs >> shift) as i64,
+        Sar => lhs >> shift,
+    }))
+}
+
+fn comp(cop: CompOp, valty: ValueType, lhs: Value, rhs: Value) -> Value {
+    use CompOp::*;
+    let res = if valty.isfloat() {
+        let (lhs, rhs) = (lhs.float(), rhs.float());
+        match cop {
+            Ceq => lhs == rhs,
+            Cne => lhs != rhs,
+            Cle => lhs <= rhs,
+            Clt => lhs < rhs,
+            Cge => lhs >= rhs,
+            Cgt => lhs > rhs,
+            Co => !lhs.is_nan() && !rhs.is_nan(),
+            Cuo => lhs.is_nan() || rhs.is_nan(),
+            _ => false,
+        }
+    } else {
+        let (lhs, rhs) = (norm(lhs, valty).int(), norm(rhs, valty).int());
+        let mask = u64::MAX >> (64 - valty.bitsize());
+        let (ulhs, urhs) = (lhs as u64 & mask, rhs as u64 & mask);
+        match cop {
+            Ceq => lhs == rhs,
+            Cne => lhs != rhs,
+            Csle => lhs <= rhs,
+            Cslt => lhs < rhs,
+            Csge => lhs >= rhs,
+            Csgt => lhs > rhs,
+            Cule => ulhs <= urhs,
+            Cult => ulhs < urhs,
+            Cuge => ulhs >= urhs,
+            Cugt => ulhs > urhs,
+            _ => false,
+        }
+    };
+    Value::Int(res as i64)
+}
+
+fn cvt(cvtop: CvtOp, dstty: ValueType, val: Value) -> Value {
+    use CvtOp::*;
+    match cvtop {
+        Exts | Truncd => norm(Value::Float(val.float()), dstty),
+        Stosi | Dtosi => norm(Value::Int(val.float() as i64), dstty),
+        Stoui | Dtoui => norm(Value::Int(val.float() as u64 as i64), dstty),
+        Swtof => norm(Value::Float(val.int() as i32 as f64), dstty),
+        Uwtof => norm(Value::Float(val.int() as u32 as f64), dstty),
+        Sltof | Ultof => {
+            // rounded once to the destination type, as cvtsi2ss does
+            let num = val.int();
+            let num = match (cvtop, dstty) {
+                (Sltof, ValueType::Single) => num as f32 as f64,
+                (Sltof, _) => num as f64,
+                (_, ValueType::Single) => num as u64 as f32 as f64,
+                _ => num as u64 as f64,
+            };
+            norm(Value::Float(num), dstty)
+        }
+        Cast => match (dstty, val) {
+            (ValueType::Single, val) => Value::Float(f32::from_bits(val.int() as u32) as f64),
+            (ValueType::Double, val) => Value::Float(f64::from_bits(val.int() as u64)),
+            (ValueType::Word, Value::Float(num)) => {
+                Value::Int((num as f32).to_bits() as i32 as i64)
+            }
+            (_, val) => Value::Int(val.int()),
+        },
+    }
+}
+
+// run main of the program in the form (before or after the passes)
+pub fn interpret(spg: &SsaProgram, form: Form) -> Result<Run, Diagnostic> {
+    if !spg.funcs.iter().any(|func| func.name == "main") {
+        return Err(Diagnostic::new("$main is not defined".to_string()));
+    }
+    // the calls of the program nest the calls of the interpreter
+    thread::scope(|scope| {
+        let handle = thread::Builder::new()
+            .stack_size(STACKSIZE)
+            .spawn_scoped(scope, || {
+                let mut ip = Interpreter::new(spg, form);
+                let ret = ip.call("main", vec![])?;
+                Ok(Run {
+                    ret: ret.map_or(0, Value::int),
+                    stdout: String::from_utf8_lossy(&ip.stdout).into_owned(),
+                })
+            })
+            .expect("failed to spawn the interpreter thread");
+        handle.join().unwrap_or_else(|e| panic::resume_unwind(e))
+    })
+}
@@ -1,23 +1,108 @@
-use super::parser::{Env, FirstClassObj, ValueType, Var, VarType};
-use super::*;
+use super::diagnostic::Diagnostic;
+use super::parser::{CompOp, CvtOp, Env, ExtOp, FirstClassObj, LoadOp, ValueType, Var, VarType};
+use std::convert::TryFrom;
 
 pub static RESERVEDWORDS: &[(&str, TokenType)] = &[
     ("function", TokenType::Function),
     ("w", TokenType::Word),
     ("ret", TokenType::Ret),
-    ("alloc4", TokenType::Alloc4),
-    ("storew", TokenType::Storew),
-    ("loadw", TokenType::Loadw),
+    ("alloc4", TokenType::Alloc(4)),
+    ("alloc8", TokenType::Alloc(8)),
+    ("alloc16", TokenType::Alloc(16)),
+    ("storel", TokenType::Store(ValueType::Long)),
+    ("storew", TokenType::Store(ValueType::Word)),
+    ("storeh", TokenType::Store(ValueType::Half)),
+    ("storeb", TokenType::Store(ValueType::Byte)),
+    ("stores", TokenType::Store(ValueType::Single)),
+    ("stored", TokenType::Store(ValueType::Double)),
+    ("loadl", TokenType::Load(LoadOp::Loadl)),
+    ("loadw", TokenType::Load(LoadOp::Loadw)),
+    ("loadsw", TokenType::Load(LoadOp::Loadsw)),
+    ("loaduw", TokenType::Load(LoadOp::Loaduw)),
+    ("loadsh", TokenType::Load(LoadOp::Loadsh)),
+    ("loaduh", TokenType::Load(LoadOp::Loaduh)),
+    ("loadsb", TokenType::Load(LoadOp::Loadsb)),
+    ("loadub", TokenType::Load(LoadOp::Loadub)),
+    ("loads", TokenType::Load(LoadOp::Loads)),
+    ("loadd", TokenType::Load(LoadOp::Loadd)),
+    ("extsw", TokenType::Ext(ExtOp::Extsw)),
+    ("extuw", TokenType::Ext(ExtOp::Extuw)),
+    ("extsh", TokenType::Ext(ExtOp::Extsh)),
+    ("extuh", TokenType::Ext(ExtOp::Extuh)),
+    ("extsb", TokenType::Ext(ExtOp::Extsb)),
+    ("extub", TokenType::Ext(ExtOp::Extub)),
+    ("exts", TokenType::Cvt(CvtOp::Exts)),
+    ("truncd", TokenType::Cvt(CvtOp::Truncd)),
+    ("stosi", TokenType::Cvt(CvtOp::Stosi)),
+    ("stoui", TokenType::Cvt(CvtOp::Stoui)),
+    ("dtosi", TokenType::Cvt(CvtOp::Dtosi)),
+    ("dtoui", TokenType::Cvt(CvtOp::Dtoui)),
+    ("swtof", TokenType::Cvt(CvtOp::Swtof)),
+    ("uwtof", TokenType::Cvt(CvtOp::Uwtof)),
+    ("sltof", TokenType::Cvt(CvtOp::Sltof)),
+    ("ultof", TokenType::Cvt(CvtOp::Ultof)),
+    ("cast", TokenType::Cvt(CvtOp::Cast)),
+    ("copy", TokenType::Copy),
     ("add", TokenType::Bop(Binop::Add)),
     ("mul", TokenType::Bop(Binop::Mul)),
     ("sub", TokenType::Bop(Binop::Sub)),
+    ("div", TokenType::Bop(Binop::Div)),
+    ("udiv", TokenType::Bop(Binop::Udiv)),
+    ("rem", TokenType::Bop(Binop::Rem)),
+    ("urem", TokenType::Bop(Binop::Urem)),
+    ("and", TokenType::Bop(Binop::And)),
+    ("or", TokenType::Bop(Binop::Or)),
+    ("xor", TokenType::Bop(Binop::Xor)),
+    ("shl", TokenType::Bop(Binop::Shl)),
+    ("shr", TokenType::Bop(Binop::Shr)),
+    ("sar", TokenType::Bop(Binop::Sar)),
     ("call", TokenType::Call),
-    ("ceqw", TokenType::Ceqw),
-    ("csltw", TokenType::Csltw),
+    ("vastart", TokenType::Vastart),
+    ("vaarg", TokenType::Vaarg),
+    ("ceqw", TokenType::Comp(CompOp::Ceq, ValueType::Word)),
+    ("cnew", TokenType::Comp(CompOp::Cne, ValueType::Word)),
+    ("cslew", TokenType::Comp(CompOp::Csle, ValueType::Word)),
+    ("csltw", TokenType::Comp(CompOp::Cslt, ValueType::Word)),
+    ("csgew", TokenType::Comp(CompOp::Csge, ValueType::Word)),
+    ("csgtw", TokenType::Comp(CompOp::Csgt, ValueType::Word)),
+    ("culew", TokenType::Comp(CompOp::Cule, ValueType::Word)),
+    ("cultw", TokenType::Comp(CompOp::Cult, ValueType::Word)),
+    ("cugew", TokenType::Comp(CompOp::Cuge, ValueType::Word)),
+    ("cugtw", TokenType::Comp(CompOp::Cugt, ValueType::Word)),
+    ("ceql", TokenType::Comp(CompOp::Ceq, ValueType::Long)),
+    ("cnel", TokenType::Comp(CompOp::Cne, ValueType::Long)),
+    ("cslel", TokenType::Comp(CompOp::Csle, ValueType::Long)),
+    ("csltl", TokenType::Comp(CompOp::Cslt, ValueType::Long)),
+    ("csgel", TokenType::Comp(CompOp::Csge, ValueType::Long)),
+    ("csgtl", TokenType::Comp(CompOp::Csgt, ValueType::Long)),
+    ("culel", TokenType::Comp(CompOp::Cule, ValueType::Long)),
+    ("cultl", TokenType::Comp(CompOp::Cult, ValueType::Long)),
+    ("cugel", TokenType::Comp(CompOp::Cuge, ValueType::Long)),
+    ("cugtl", TokenType::Comp(CompOp::Cugt, ValueType::Long)),
+    ("ceqs", TokenType::Comp(CompOp::Ceq, ValueType::Single)),
+    ("cnes", TokenType::Comp(CompOp::Cne, ValueType::Single)),
+    ("cles", TokenType::Comp(CompOp::Cle, ValueType::Single)),
+    ("clts", TokenType::Comp(CompOp::Clt, ValueType::Single)),
+    ("cges", TokenType::Comp(CompOp::Cge, ValueType::Single)),
+    ("cgts", TokenType::Comp(CompOp::Cgt, ValueType::Single)),
+    ("cos", TokenType::Comp(CompOp::Co, ValueType::Single)),
+    ("cuos", TokenType::Comp(CompOp::Cuo, ValueType::Single)),
+    ("ceqd", TokenType::Comp(CompOp::Ceq, ValueType::Double)),
+    ("cned", TokenType::Comp(CompOp::Cne, ValueType::Double)),
+    ("cled", TokenType::Comp(CompOp::Cle, ValueType::Double)),
+    ("cltd", TokenType::Comp(CompOp::Clt, ValueType::Double)),
+    ("cged", TokenType::Comp(CompOp::Cge, ValueType::Double)),
+    ("cgtd", TokenType::Comp(CompOp::Cgt, ValueType::Double)),
+    ("cod", TokenType::Comp(CompOp::Co, ValueType::Double)),
+    ("cuod", TokenType::Comp(CompOp::Cuo, ValueType::Double)),
     ("jnz", TokenType::Jnz),
     ("jmp", TokenType::Jmp),
     ("phi", TokenType::Phi),
     ("data", TokenType::Data),
+    ("type", TokenType::Type),
+    ("export", TokenType::Export),
+    ("thread", TokenType::Thread),
+    ("section", TokenType::Section),
     ("align", TokenType::Align),
 ];
 
@@ -30,8 +115,11 @@ pub static SIGNALS: &[(&str, TokenType)] = &[
     (":", TokenType::Colon),
     ("=w", TokenType::Eqw),
     ("=l", TokenType::Eql),
+    ("=s", TokenType::Eqs),
+    ("=d", TokenType::Eqd),
     ("=", TokenType::Eq),
     (",", TokenType::Comma),
+    ("+", TokenType::Plus),
     ("...", TokenType::Threedot),
     ("#", TokenType::Hash),
     (">", TokenType::Rturbo),
@@ -45,6 +133,38 @@ pub enum Binop {
     Add,
     Mul,
     Sub,
+    Div,
+    Udiv,
+    Rem,
+    Urem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Sar,
+}
+
+impl Binop {
+    // instruction name in the textual IR
+    pub fn name(self) -> &'static str {
+        use Binop::*;
+        match self {
+            Add => "add",
+            Mul => "mul",
+            Sub => "sub",
+            Div => "div",
+            Udiv => "udiv",
+            Rem => "rem",
+            Urem => "urem",
+            And => "and",
+            Or => "or",
+            Xor => "xor",
+            Shl => "shl",
+            Shr => "shr",
+            Sar => "sar",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -60,21 +180,29 @@ pub enum TokenType {
     Dollar,
     Ret,
     Ilit,
+    Flit,
     Block,
     Colon,
     Blocklb,
-    Alloc4,
+    Alloc(i32),
     Eql,
     Eqw,
+    Eqs,
+    Eqd,
     Eq,
     Bop(Binop),
-    Storew,
-    Loadw,
+    Store(ValueType),
+    Load(LoadOp),
+    Ext(ExtOp),
+    Cvt(CvtOp),
+    Copy,
     Comma,
+    Plus,
     Threedot,
     Call,
-    Ceqw,
-    Csltw,
+    Vastart,
+    Vaarg,
+    Comp(CompOp, ValueType),
     Jnz,
     Jmp,
     Hash,
@@ -83,31 +211,85 @@ pub enum TokenType {
     Lturbo,
     Semi,
     Data,
+    Type,
+    Export,
+    Thread,
+    Section,
     Excla,
     Align,
     String,
     Eof,
 }
 
+impl TokenType {
+    // description of the token used in the error messages
+    pub fn describe(self) -> String {
+        use TokenType::*;
+        match self {
+            Ident => "identifier".to_string(),
+            Ilit => "integer".to_string(),
+            Flit => "floating point literal".to_string(),
+            Blocklb => "block label".to_string(),
+            String => "string".to_string(),
+            Bop(_) => "binary operation".to_string(),
+            Eof => "end of file".to_string(),
+            _ => RESERVEDWORDS
+                .iter()
+                .chain(SIGNALS)
+                .find(|(_, tty)| *tty == self)
+                .map_or(format!("{:?}", self), |(text, _)| format!("`{}`", text)),
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct TokenMass {
+pub struct TokenMass<'a> {
     pub tks: Vec<Token>,
     pub cpos: usize,
+    // source text the tokens refer to
+    pub program: &'a str,
 }
 
-impl TokenMass {
-    pub fn new() -> Self {
+impl<'a> TokenMass<'a> {
+    pub fn new(program: &'a str) -> Self {
         Self {
             tks: vec![],
             cpos: 0,
+            program,
         }
     }
+    // text of the id-th token
+    pub fn tktext(&self, id: usize) -> &'a str {
+        self.tks[id].get_text(self.program)
+    }
     fn push(&mut self, tk: Token) {
         self.tks.push(tk)
     }
-    pub fn as_tkty(&mut self, tty: TokenType) {
-        assert_eq!(self.tks[self.cpos].tty, tty);
+    // error at the id-th token
+    pub fn error_at(&self, id: usize, message: String) -> Diagnostic {
+        let tk = self.tks[id];
+        Diagnostic::at(self.program, (tk.poss, tk.pose), message)
+    }
+    // error at the current token
+    pub fn error(&self, message: String) -> Diagnostic {
+        self.error_at(self.cpos, message)
+    }
+    // description of the current token used in the error messages
+    pub fn found(&self) -> String {
+        match self.cur_tkty() {
+            TokenType::Eof => "end of file".to_string(),
+            _ => format!("`{}`", self.tktext(self.cpos)),
+        }
+    }
+    pub fn expected(&self, what: &str) -> Diagnostic {
+        self.error(format!("expected {}, found {}", what, self.found()))
+    }
+    pub fn as_tkty(&mut self, tty: TokenType) -> Result<(), Diagnostic> {
+        if self.tks[self.cpos].tty != tty {
+            return Err(self.expected(&tty.describe()));
+        }
         self.cpos += 1;
+        Ok(())
     }
     pub fn cur_tkty(&self) -> TokenType {
         self.tks[self.cpos].tty
@@ -118,114 +300,190 @@ impl TokenMass {
             self.cpos += 1;
             true
         } else {
-            match (tty, tk) {
-                (TokenType::Bop(_), TokenType::Bop(_)) => true,
-                _ => false,
-            }
+            matches!((tty, tk), (TokenType::Bop(_), TokenType::Bop(_)))
         }
     }
-    pub fn getnum_n(&mut self) -> i32 {
-        assert_eq!(self.tks[self.cpos].tty, TokenType::Ilit);
-        let res = self.tks[self.cpos].num;
+    pub fn getnum_n(&mut self) -> Result<i32, Diagnostic> {
+        if self.tks[self.cpos].tty != TokenType::Ilit {
+            return Err(self.expected("integer"));
+        }
+        let res = i32::try_from(self.tks[self.cpos].num)
+            .map_err(|_| self.error(format!("integer {} is out of range", self.found())))?;
         self.cpos += 1;
-        res
+        Ok(res)
     }
-    pub fn getvar_n(&mut self, env: &Env) -> Var {
-        let key = self.tks[self.cpos].get_text();
-        let res = env.g_lvs(&key);
+    // integer literal of the value of vty. it must fit the width of vty as the signed or the
+    // unsigned integer, and is kept sign-extended from the width.
+    pub fn getint_n(&mut self, vty: &VarType<'a>) -> Result<i64, Diagnostic> {
+        if self.tks[self.cpos].tty != TokenType::Ilit {
+            return Err(self.expected("integer"));
+        }
+        let num = self.tks[self.cpos].num;
+        let bits = match vty {
+            VarType::Word => 32,
+            VarType::Half => 16,
+            VarType::Byte => 8,
+            _ => 64,
+        };
+        if bits < 64 && (num < -(1 << (bits - 1)) || num >= 1 << bits) {
+            return Err(self.error(format!(
+                "integer {} doesn't fit in {} bits",
+                self.found(),
+                bits
+            )));
+        }
         self.cpos += 1;
-        res
+        Ok(num << (64 - bits) >> (64 - bits))
     }
-    pub fn gettype_n(&mut self) -> VarType {
-        let tktxt = self.tks[self.cpos].get_text();
-        // There is change for room
+    pub fn getvar_n(&mut self, env: &mut Env<'a>) -> Result<Var<'a>, Diagnostic> {
+        // global symbol
+        self.eq_tkty(TokenType::Dollar);
+        if self.cur_tkty() != TokenType::Ident {
+            return Err(self.expected("variable"));
+        }
+        let key = self.tktext(self.cpos);
+        let res = env
+            .g_lvs(key)
+            .ok_or_else(|| self.error(format!("cannot find `{}` in this scope", key)))?;
         self.cpos += 1;
-        match tktxt {
+        Ok(res)
+    }
+    // variable holding the address (e.g. operand of load and store)
+    pub fn getaddrvar_n(&mut self, env: &mut Env<'a>) -> Result<Var<'a>, Diagnostic> {
+        let id = self.cpos;
+        let var = self.getvar_n(env)?;
+        if !var.ty.isaddress() {
+            return Err(self.error_at(id, format!("`{}` is not an address", var.name)));
+        }
+        Ok(var)
+    }
+    pub fn gettype_n(&mut self) -> Result<VarType<'a>, Diagnostic> {
+        let tktxt = self.tktext(self.cpos);
+        let ty = match tktxt {
             "w" => VarType::Word,
             "l" => VarType::Long,
+            "h" => VarType::Half,
             "b" => VarType::Byte,
+            "s" => VarType::Single,
+            "d" => VarType::Double,
             _ => {
-                panic!("TokenMass.gettype() error. {}", tktxt);
+                return Err(self.expected("type"));
             }
+        };
+        self.cpos += 1;
+        Ok(ty)
+    }
+    // base type or :aggregate type
+    pub fn getabity_n(&mut self, env: &Env<'a>) -> Result<VarType<'a>, Diagnostic> {
+        if self.eq_tkty(TokenType::Colon) {
+            let tyn = self.tktext(self.cpos);
+            let ty = env
+                .g_tys(tyn)
+                .ok_or_else(|| self.error(format!("type :{} is not defined", tyn)))?;
+            self.cpos += 1;
+            Ok(ty)
+        } else {
+            self.gettype_n()
         }
     }
-    pub fn getvaltype_n(&mut self) -> ValueType {
-        let tktxt = self.tks[self.cpos].get_text();
-        self.cpos += 1;
-        match tktxt {
+    pub fn getvaltype_n(&mut self) -> Result<ValueType, Diagnostic> {
+        let tktxt = self.tktext(self.cpos);
+        let ty = match tktxt {
             "w" => ValueType::Word,
             "l" => ValueType::Long,
+            "h" => ValueType::Half,
             "b" => ValueType::Byte,
+            "s" => ValueType::Single,
+            "d" => ValueType::Double,
             _ => {
-                panic!("getvaltype_n() error. {}", tktxt);
+                return Err(self.expected("type"));
             }
-        }
+        };
+        self.cpos += 1;
+        Ok(ty)
     }
-    pub fn gettext_n(&mut self) -> &'static str {
-        let tktext = self.tks[self.cpos].get_text();
+    pub fn gettext_n(&mut self) -> &'a str {
+        let tktext = self.tktext(self.cpos);
         self.cpos += 1;
         tktext
     }
-    pub fn gettext(&mut self) -> &'static str {
-        let tktext = self.tks[self.cpos].get_text();
-        tktext
+    pub fn gettext(&mut self) -> &'a str {
+        (self.tktext(self.cpos)) as _
+    }
+    // name of the function, data or type
+    pub fn getname_n(&mut self) -> Result<&'a str, Diagnostic> {
+        // reserved words are also available for names
+        let isname = self.cur_tkty() != TokenType::Flit
+            && self
+                .tktext(self.cpos)
+                .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '%');
+        if !isname {
+            return Err(self.expected("name"));
+        }
+        Ok(self.gettext_n())
     }
-    pub fn getfco_n(&mut self, vty: VarType, env: &mut Env) -> FirstClassObj {
+    pub fn getfco_n(
+        &mut self,
+        vty: VarType<'a>,
+        env: &mut Env<'a>,
+    ) -> Result<FirstClassObj<'a>, Diagnostic> {
+        self.eq_tkty(TokenType::Dollar);
         let ctk = self.getcurrent_token();
-        let lb = self.gettext_n();
         match ctk.tty {
-            TokenType::Ident => {
-                let var = env.g_lvs(&lb);
-                FirstClassObj::Variable(var)
+            TokenType::Ident => Ok(FirstClassObj::Variable(self.getvar_n(env)?)),
+            TokenType::Ilit => {
+                let num = self.getint_n(&vty)?;
+                Ok(FirstClassObj::Num(vty, num))
             }
-            TokenType::Ilit => FirstClassObj::Num(vty, ctk.num),
-            TokenType::String => FirstClassObj::String(lb),
-            _ => {
-                panic!("getfco_n error. {:?}", self.getcurrent_token());
+            TokenType::Flit => {
+                // s_1.5 or d_1.5
+                let lb = self.tktext(self.cpos);
+                let fty = if lb.starts_with('s') {
+                    VarType::Single
+                } else {
+                    VarType::Double
+                };
+                let num = lb[2..]
+                    .parse::<f64>()
+                    .map_err(|_| self.error(format!("invalid floating point literal `{}`", lb)))?;
+                self.cpos += 1;
+                Ok(FirstClassObj::Float(fty, num))
             }
+            TokenType::String => Ok(FirstClassObj::String(self.gettext_n())),
+            _ => Err(self.expected("value")),
         }
     }
-    pub fn getblocklb_n(&mut self) -> &'static str {
-        let lb = &self.tks[self.cpos].get_text();
-        self.eq_tkty(TokenType::Blocklb);
-        lb
+    // operand which is truncated to vty (e.g. of storeb and extsb). the integer literal is
+    // given as the word for the halfword and the byte.
+    pub fn getnarrowfco_n(
+        &mut self,
+        vty: VarType<'a>,
+        env: &mut Env<'a>,
+    ) -> Result<FirstClassObj<'a>, Diagnostic> {
+        if !matches!(vty, VarType::Half | VarType::Byte) {
+            return self.getfco_n(vty, env);
+        }
+        match self.getfco_n(VarType::Word, env)? {
+            FirstClassObj::Num(_, num) => Ok(FirstClassObj::Num(vty, num)),
+            fco => Ok(fco),
+        }
+    }
+    pub fn getblocklb_n(&mut self) -> Result<&'a str, Diagnostic> {
+        if self.cur_tkty() != TokenType::Blocklb {
+            return Err(self.expected("block label"));
+        }
+        Ok(self.gettext_n())
     }
     pub fn getcurrent_token(&self) -> Token {
         self.tks[self.cpos]
     }
     pub fn getbinop(&mut self) -> Option<Binop> {
-        let tty = self.cur_tkty();
-        use TokenType::*;
-        match tty {
-            Bop(Binop::Add) => {
-                self.cpos += 1;
-                Some(Binop::Add)
-            }
-            Bop(Binop::Sub) => {
-                self.cpos += 1;
-                Some(Binop::Sub)
-            }
-            Bop(Binop::Mul) => {
-                self.cpos += 1;
-                Some(Binop::Mul)
-            }
-            _ => None,
-        }
-    }
-    pub fn getfuncdata(&mut self) -> (&'static str, VarType) {
-        let retty;
-        let back;
-        if self.cur_tkty() == TokenType::Dollar {
-            retty = VarType::Void;
-            back = 3;
+        if let TokenType::Bop(binop) = self.cur_tkty() {
+            self.cpos += 1;
+            Some(binop)
         } else {
-            retty = self.gettype_n();
-            back = 4;
+            None
         }
-        self.as_tkty(TokenType::Dollar);
-        let funlb = self.gettext_n();
-        self.cpos -= back;
-        (funlb, retty)
     }
 }
 
@@ -234,11 +492,11 @@ pub struct Token {
     pub tty: TokenType,
     pub poss: usize,
     pub pose: usize,
-    pub num: i32,
+    pub num: i64,
 }
 
 impl Token {
-    pub fn new(tty: TokenType, poss: usize, pose: usize, num: i32) -> Self {
+    pub fn new(tty: TokenType, poss: usize, pose: usize, num: i64) -> Self {
         Self {
             tty,
             poss,
@@ -246,42 +504,74 @@ impl Token {
             num,
         }
     }
-    pub fn get_text(&self) -> &'static str {
-        &(*PROGRAM)[self.poss..self.pose]
+    pub fn get_text<'a>(&self, program: &'a str) -> &'a str {
+        &program[self.poss..self.pose]
     }
 }
 
-pub fn lex() -> TokenMass {
-    let program = (*PROGRAM).clone();
-    let pgchars: Vec<char> = (&program[..]).chars().collect();
-    let pglen = program.len();
+pub fn lex<'a>(program: &'a str) -> Result<TokenMass<'a>, Diagnostic> {
+    // comments and the contents of strings are skipped as bytes, so positions are byte offsets
+    let pgchars = program.as_bytes();
+    let pglen = pgchars.len();
     let mut pos = 0;
-    let mut tms = TokenMass::new();
+    let mut tms = TokenMass::new(program);
 
     loop {
         if pos == pglen {
             break;
         }
-        if pgchars[pos].is_whitespace() {
+        if pgchars[pos].is_ascii_whitespace() {
             pos += 1;
             continue;
         }
-        if pgchars[pos] == '#' {
-            while pgchars[pos] != '\n' {
+        if pgchars[pos] == b'#' {
+            while pos < pglen && pgchars[pos] != b'\n' {
                 pos += 1;
             }
             continue;
         }
 
+        // floating point literal (s_1.5, d_-2e3), but not the name like $d_tab or :s_t
+        let afterprefix = tms
+            .tks
+            .last()
+            .is_some_and(|tk| matches!(tk.tty, TokenType::Dollar | TokenType::Colon));
+        if !afterprefix
+            && (pgchars[pos] == b's' || pgchars[pos] == b'd')
+            && pos + 1 < pglen
+            && pgchars[pos + 1] == b'_'
+        {
+            let mut pose = pos + 2;
+            while pose < pglen
+                && (pgchars[pose].is_ascii_alphanumeric()
+                    || pgchars[pose] == b'.'
+                    || pgchars[pose] == b'-'
+                    || pgchars[pose] == b'+')
+            {
+                pose += 1;
+            }
+            tms.push(Token::new(TokenType::Flit, pos, pose, -1));
+            pos = pose;
+            continue;
+        }
+
         // identification or reserved words
-        if pgchars[pos] == '@' || pgchars[pos] == '%' || pgchars[pos].is_ascii_alphabetic() {
+        if pgchars[pos] == b'@'
+            || pgchars[pos] == b'%'
+            || pgchars[pos] == b'_'
+            || pgchars[pos].is_ascii_alphabetic()
+        {
             let mut pose = pos;
             let mut tty = TokenType::Ident;
             pose += 1;
-            while pgchars[pose].is_ascii_alphanumeric() || pgchars[pose] == '.' {
+            while pose < pglen
+                && (pgchars[pose].is_ascii_alphanumeric()
+                    || pgchars[pose] == b'.'
+                    || pgchars[pose] == b'_')
+            {
                 pose += 1;
             }
-            if pgchars[pos] == '@' {
+            if pgchars[pos] == b'@' {
                 tty = TokenType::Blocklb;
                 tms.push(Token::new(tty, pos + 1, pose, -1));
                 pos = pose;
@@ -299,23 +589,50 @@ pub fn lex() -> TokenMass {
         }
 
         // integer
-        if pgchars[pos].is_ascii_digit() {
-            let mut num = 0;
+        if pgchars[pos].is_ascii_digit()
+            || (pgchars[pos] == b'-' && pos + 1 < pglen && pgchars[pos + 1].is_ascii_digit())
+        {
+            // the literal up to 64 bits is read as the unsigned integer
+            let mut num: Option<u64> = Some(0);
             let poss = pos;
-            while pgchars[pos].is_ascii_digit() {
-                num = num * 10 + (pgchars[pos] as i32 - 48);
+            if pgchars[pos] == b'-' {
                 pos += 1;
             }
+            while pos < pglen && pgchars[pos].is_ascii_digit() {
+                num = num
+                    .and_then(|num| num.checked_mul(10))
+                    .and_then(|num| num.checked_add((pgchars[pos] - b'0') as u64));
+                pos += 1;
+            }
+            let num = match num {
+                Some(num) if pgchars[poss] != b'-' => Some(num as i64),
+                Some(num) if num <= 1 << 63 => Some((num as i64).wrapping_neg()),
+                _ => None,
+            };
+            let Some(num) = num else {
+                return Err(Diagnostic::at(
+                    program,
+                    (poss, pos),
+                    "integer literal doesn't fit in 64 bits".to_string(),
+                ));
+            };
             tms.push(Token::new(TokenType::Ilit, poss, pos, num));
             continue;
         }
 
         // string
-        if pgchars[pos] == '\"' {
+        if pgchars[pos] == b'\"' {
             let mut pose = pos + 1;
-            while pgchars[pose] != '\"' {
+            while pose < pglen && pgchars[pose] != b'\"' {
                 pose += 1;
             }
+            if pose == pglen {
+                return Err(Diagnostic::at(
+                    program,
+                    (pos, pose),
+                    "unterminated string".to_string(),
+                ));
+            }
             tms.push(Token::new(TokenType::String, pos + 1, pose, -1));
             pos = pose + 1;
             continue;
@@ -326,7 +643,7 @@ pub fn lex() -> TokenMass {
         for sig in SIGNALS {
             let signal = sig.0;
             let pose = pos + signal.len();
-            if signal == &program[pos..pose] {
+            if program.get(pos..pose) == Some(signal) {
                 let tty = sig.1;
                 nextloop = true;
                 tms.push(Token::new(tty, pos, pose, -1));
@@ -338,8 +655,14 @@ pub fn lex() -> TokenMass {
             continue;
         }
 
-        panic!("failed lex program. next letter = {}", pgchars[pos]);
+        // pos is at the start of the character since the tokens are ascii
+        let ch = program[pos..].chars().next().unwrap();
+        return Err(Diagnostic::at(
+            program,
+            (pos, pos + ch.len_utf8()),
+            format!("unexpected character `{}`", ch),
+        ));
     }
-    tms.push(Token::new(TokenType::Eof, 0, 0, -1));
-    tms
+    tms.push(Token::new(TokenType::Eof, pglen, pglen, -1));
+    Ok(tms)
 }
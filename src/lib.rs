@@ -1,22 +1,94 @@
-use once_cell::sync::Lazy;
-use std::env;
-use std::fs;
+use std::panic;
 
+pub mod builder;
 pub mod codegen;
 pub mod deadcode;
+pub mod diagnostic;
 pub mod dominators;
+pub mod interp;
 pub mod lexer;
 pub mod lowir;
 pub mod mem2reg;
 pub mod parser;
 pub mod rega;
 pub mod rev_ssa;
+pub mod sroa;
+pub mod verify;
 
-type Label = &'static str;
-type VarName = &'static str;
-type BBLabel = &'static str;
+use verify::{verify, Form};
 
-pub static PROGRAM: Lazy<String> = Lazy::new(|| {
-    let file: String = env::args().collect::<Vec<String>>().last().unwrap().clone();
-    fs::read_to_string(file).expect("failed to read file.")
-});
+type Label<'a> = &'a str;
+type VarName<'a> = &'a str;
+
+pub use diagnostic::Diagnostic;
+
+// options of the compilation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    // promote the stack slots to registers (-O1)
+    pub optimize: bool,
+    // check the integer overflow at runtime (-Sec)
+    pub secure: bool,
+    // check the well-formedness of the program after each pass (--verify-each)
+    pub verify: bool,
+}
+
+// compile the source program to the x64 assembly. all the errors in the program are returned.
+pub fn compile(source: &str, options: Options) -> Result<String, Vec<Diagnostic>> {
+    let names = parser::NamePool::default();
+    let spg = catchpanic(|| {
+        let mut tms = lexer::lex(source).map_err(|diag| vec![diag])?;
+        parser::parse(&mut tms, &names)
+    })?;
+    compile_program(spg, options)
+}
+
+// compile the program built in memory (e.g. by builder::ModuleBuilder) to the x64 assembly
+pub fn compile_program(
+    mut spg: parser::SsaProgram,
+    options: Options,
+) -> Result<String, Vec<Diagnostic>> {
+    let check = |spg: &parser::SsaProgram, form: Form, pass: &str| {
+        if !options.verify {
+            return Ok(());
+        }
+        verify(spg, form).map_err(|diags| {
+            diags
+                .into_iter()
+                .map(|diag| Diagnostic::new(format!("after {}: {}", pass, diag.message)))
+                .collect::<Vec<_>>()
+        })
+    };
+    catchpanic(move || {
+        dominators::dominators(&mut spg);
+        check(&spg, Form::Parsed, "parse")?;
+        deadcode::removeuselessinstr(&mut spg);
+        check(&spg, Form::Pruned, "removeuselessinstr")?;
+        if options.optimize {
+            sroa::sroa(&mut spg);
+            check(&spg, Form::Pruned, "sroa")?;
+            mem2reg::ezmem2reg(&mut spg);
+            mem2reg::mem2reg(&mut spg);
+            check(&spg, Form::Pruned, "mem2reg")?;
+        }
+        rev_ssa::rev_ssa(&mut spg);
+        check(&spg, Form::Normal, "rev_ssa")?;
+        let lirpg = rega::registeralloc(lowir::genlowir(spg));
+        Ok(codegen::gen_x64code(lirpg, options.secure))
+    })
+}
+
+// run f reporting the panic as the error. the errors after the parsing are reported by panics.
+fn catchpanic<T>(f: impl FnOnce() -> Result<T, Vec<Diagnostic>>) -> Result<T, Vec<Diagnostic>> {
+    // the name pool is only appended, so it is consistent after the panic
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).map_err(|payload| {
+        let message = if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg.to_string()
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.clone()
+        } else {
+            "unknown error".to_string()
+        };
+        vec![Diagnostic::new(message)]
+    })?
+}
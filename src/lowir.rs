@@ -1,14 +1,18 @@
+use super::codegen::{
+    argslocations, classify, hiddenret, ArgLoc, ARGREGQUANTITY, ARGXMMQUANTITY, NORMALREGQUANTITY,
+    NORMALXMMQUANTITY,
+};
 use super::lexer::Binop;
 use super::parser::*;
 use super::*;
-use super::codegen::NORMALREGQUANTITY;
-use rega::GENEREGSIZE;
+use rega::RealRegs;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 
 pub static NULLNUMBER: i32 = -100;
 pub static REGDEFASIZE: i32 = 4;
-static MAXLIFE: i32 = std::i32::MAX;
+static MAXLIFE: i32 = i32::MAX;
 
 trait AppHash {
     fn rgup(&mut self, frsn: i32, bd: i32, dd: i32, cdd: i32);
@@ -26,30 +30,61 @@ impl AppHash for HashMap<i32, (i32, i32)> {
 pub enum NeedStack {
     Exist(i32),
     NoExist(i32),
+    // stashed virtual register is loaded to the free register
+    Reload(i32),
+    // virtual register living in memory is loaded from its home (after the victim is stashed)
+    Home(Option<i32>, i32),
     NoNeed,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct Register {
+pub struct Register<'a> {
     pub vr: i32,
     pub rr: i32,
     pub btday: i32,
     pub daday: i32,
     pub regsize: i32,
-    pub global: Option<Label>,
+    pub global: Option<Label<'a>>,
+    // floating point register (xmm)
+    pub fp: bool,
 }
 
-pub struct StashStacked {
-    pub vecs: Vec<Option<Register>>,
+pub struct StashStacked<'a> {
+    pub vecs: Vec<Option<Register<'a>>>,
+    // stack slot of the virtual register which always lives in memory
+    pub homes: HashMap<i32, i32>,
+    // virtual registers spilled in the middle of their lives
+    pub spilled: Vec<i32>,
 }
 
-impl StashStacked {
+impl<'a> Default for StashStacked<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> StashStacked<'a> {
     pub fn new() -> Self {
-        Self { vecs: vec![] }
+        Self {
+            vecs: vec![],
+            homes: HashMap::new(),
+            spilled: vec![],
+        }
+    }
+    // the home slots are placed first and never reused by the stash
+    pub fn withhomes(vrs: &[i32]) -> Self {
+        let mut stash_stacked = Self::new();
+        for vr in vrs {
+            stash_stacked.vecs.push(Some(Register::new(*vr)));
+            stash_stacked
+                .homes
+                .insert(*vr, stash_stacked.vecs.len() as i32 * 8);
+        }
+        stash_stacked
     }
-    pub fn store2stack(&mut self, reg: Register) -> i32 {
+    pub fn store2stack(&mut self, reg: Register<'a>) -> i32 {
         for (i, v) in &mut self.vecs.iter_mut().enumerate() {
-            if let None = v {
+            if v.is_none() {
                 *v = Some(reg);
                 return (i as i32 + 1) * 8;
             }
@@ -57,7 +92,7 @@ impl StashStacked {
         self.vecs.push(Some(reg));
         self.vecs.len() as i32 * 8
     }
-    pub fn read4stack(&mut self, reg: Register) -> Option<i32> {
+    pub fn read4stack(&mut self, reg: Register<'a>) -> Option<i32> {
         for (i, v) in self.vecs.iter_mut().enumerate() {
             if v.is_some() && v.unwrap().vr == reg.vr {
                 *v = None;
@@ -68,7 +103,7 @@ impl StashStacked {
     }
 }
 
-impl Register {
+impl<'a> Register<'a> {
     pub fn new(vr: i32) -> Self {
         Self {
             vr,
@@ -77,9 +112,16 @@ impl Register {
             daday: NULLNUMBER,
             regsize: REGDEFASIZE,
             global: None,
+            fp: false,
         }
     }
-    pub fn newall(vr: i32, btday: i32, daday: i32, regsize: i32, global: Option<Label>) -> Self {
+    pub fn newall(
+        vr: i32,
+        btday: i32,
+        daday: i32,
+        regsize: i32,
+        global: Option<Label<'a>>,
+    ) -> Self {
         Self {
             vr,
             rr: NULLNUMBER,
@@ -87,86 +129,196 @@ impl Register {
             daday,
             regsize,
             global,
+            fp: false,
         }
     }
+    // register holding the value of var
+    pub fn newvar(var: &Var<'a>, btday: i32, daday: i32) -> Self {
+        let mut r = Self::newall(var.rg_vr, btday, daday, var.ty.toregrefsize(), var.global);
+        r.fp = var.ty.isfloat();
+        r
+    }
     pub fn regalloc(
         &mut self,
-        realregs: &mut [Option<Register>; GENEREGSIZE],
-        stash_stacked: &mut StashStacked,
+        realregs: &mut RealRegs<'a>,
+        stash_stacked: &mut StashStacked<'a>,
     ) -> NeedStack {
         // alloc for register for assigned arguments register
         if self.vr < 0 {
-            self.rr = NORMALREGQUANTITY as i32 - 1 + -(self.vr);
+            self.rr = if self.fp {
+                NORMALXMMQUANTITY as i32 - 1 + -(self.vr)
+            } else {
+                NORMALREGQUANTITY as i32 - 1 + -(self.vr)
+            };
             return NeedStack::NoNeed;
         }
+        let victim = realregs.victim(self.fp);
+        realregs.pinned.push(self.vr);
+        let realregs = realregs.class(self.fp);
+        let home = stash_stacked.homes.get(&self.vr).copied();
         // find register already allocated
         let mut newrr = -1;
-        for i in 0..GENEREGSIZE {
-            if let Some(reg) = realregs[i] {
+        for (i, realreg) in realregs.iter().enumerate() {
+            if let Some(reg) = realreg {
                 if reg.vr == self.vr {
                     self.rr = i as i32;
                     return NeedStack::NoNeed;
                 }
             }
-            if newrr == -1 && realregs[i] == None {
+            if newrr == -1 && realreg.is_none() {
                 newrr = i as i32;
             }
         }
+        if let Some(home) = home {
+            let stashed = if newrr == -1 {
+                let victim =
+                    victim.unwrap_or_else(|| panic!("no register can be spilled. {:?}", self));
+                let victimreg = realregs[victim].unwrap();
+                stash_stacked.spilled.push(victimreg.vr);
+                newrr = victim as i32;
+                Some(stash_stacked.store2stack(victimreg))
+            } else {
+                None
+            };
+            self.rr = newrr;
+            realregs[self.rr as usize] = Some(*self);
+            return NeedStack::Home(stashed, home);
+        }
+        let stashed = stash_stacked.read4stack(*self);
         if newrr == -1 {
+            let victim = victim.unwrap_or_else(|| panic!("no register can be spilled. {:?}", self));
+            stash_stacked.spilled.push(realregs[victim].unwrap().vr);
             // all register are used.
             // exist virtual register in memory.
-            if let Some(offset) = stash_stacked.read4stack(self.clone()) {
+            if let Some(offset) = stashed {
                 let tmp_id = offset as usize / 8 - 1;
                 assert!(stash_stacked.vecs[tmp_id].is_none());
-                stash_stacked.vecs[tmp_id] = realregs[0];
-                self.rr = 0;
-                realregs[0] = Some(self.clone());
-                return NeedStack::Exist(offset);
+                stash_stacked.vecs[tmp_id] = realregs[victim];
+                self.rr = victim as i32;
+                realregs[victim] = Some(*self);
+                NeedStack::Exist(offset)
             } else {
                 // no exist virtual register in memory.
-                let offset = stash_stacked.store2stack(realregs[0].unwrap());
-                self.rr = 0;
-                realregs[0] = Some(self.clone());
-                return NeedStack::NoExist(offset);
+                let offset = stash_stacked.store2stack(realregs[victim].unwrap());
+                self.rr = victim as i32;
+                realregs[victim] = Some(*self);
+                NeedStack::NoExist(offset)
             }
         } else {
             // new register allocate
             self.rr = newrr;
-            realregs[self.rr as usize] = Some(self.clone());
-            NeedStack::NoNeed
+            realregs[self.rr as usize] = Some(*self);
+            stashed.map_or(NeedStack::NoNeed, NeedStack::Reload)
         }
     }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub enum RegorNum {
-    Reg(Register),
-    Num(i32),
+pub enum RegorNum<'a> {
+    Reg(Register<'a>),
+    Num(i64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address<'a> {
+    // stack slot at rbp - offset
+    Stack(i32),
+    // base register + displacement
+    Base(Register<'a>, i32),
+    // stack argument at rbp + 16 + offset
+    Arg(i32),
+    // global data
+    Global(Label<'a>),
+    // thread local data at %fs + offset
+    Tls(Label<'a>),
+    // outgoing stack argument at rsp + offset
+    Out(i32),
+}
+
+impl<'a> fmt::Display for Address<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Stack(offset) => write!(f, "[base-{}]", offset),
+            Address::Base(r, disp) => write!(f, "[r[{}]({})+{}]", r.vr, r.rr, disp),
+            Address::Arg(offset) => write!(f, "[arg+{}]", offset),
+            Address::Global(lb) => write!(f, "[${}]", lb),
+            Address::Tls(lb) => write!(f, "[%fs:${}]", lb),
+            Address::Out(offset) => write!(f, "[out+{}]", offset),
+        }
+    }
+}
+
+// aggregate result passed from the callee to the caller
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggRet {
+    // class of each eightbyte. None means that it is returned by the memory.
+    pub classes: Option<Vec<bool>>,
+    pub size: i32,
+    // stack slot of the result in the caller, or of the hidden pointer in the callee
+    pub slot: i32,
+}
+
+// register save area and the initial state of va_list in the variadic function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VaSave {
+    // offsets of the first unnamed arguments in the register save area
+    pub gpoffset: i32,
+    pub fpoffset: i32,
+    // offset of the first unnamed argument in the stack argument area
+    pub overflow: i32,
+    // stack slot of the register save area
+    pub slot: i32,
+}
+
+// calling convention details of the call
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallAbi {
+    // %al is set for the variadic function
+    pub variadic: bool,
+    // the number of vector registers used by the arguments
+    pub sseregs: usize,
+    pub aggret: Option<AggRet>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum LowIrInstr {
-    Movenum(Register, i32),
-    Movereg(Register, Register),
-    Ret(Register),
-    Storewreg(Register, i32),
-    Storewnum(i32, i32),
-    Loadw(Register, i32),
-    Bop(Binop, Register, RegorNum),
-    Call(Register, Label, Vec<RegorNum>, Vec<usize>),
-    Comp(CompOp, Register, Register, RegorNum),
-    Jnz(Register, Label, Label),
-    Jmp(Label),
+pub enum LowIrInstr<'a> {
+    Movenum(Register<'a>, i64),
+    Movefnum(Register<'a>, f64),
+    Movereg(Register<'a>, Register<'a>),
+    Ret(Register<'a>),
+    Retaggre(Register<'a>, AggRet),
+    Storereg(Register<'a>, Address<'a>),
+    Storenum(ValueType, i64, Address<'a>),
+    Load(LoadOp, Register<'a>, Address<'a>),
+    Lea(Register<'a>, Address<'a>),
+    Ext(ExtOp, Register<'a>, Register<'a>),
+    Cvt(CvtOp, Register<'a>, Register<'a>),
+    Bop(Binop, Register<'a>, RegorNum<'a>),
+    Call(
+        Register<'a>,
+        Label<'a>,
+        Vec<(RegorNum<'a>, ArgLoc)>,
+        Vec<Register<'a>>,
+        CallAbi,
+    ),
+    Vastart(Address<'a>, VaSave),
+    Vaarg(Register<'a>, Address<'a>),
+    Comp(CompOp, Register<'a>, Register<'a>, RegorNum<'a>),
+    Jnz(Register<'a>, Label<'a>, Label<'a>),
+    Jmp(Label<'a>),
     LowNop,
 }
 
-impl fmt::Display for LowIrInstr {
+impl<'a> fmt::Display for LowIrInstr<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use LowIrInstr::*;
         match self {
             Movenum(r, c) => {
                 write!(f, "\tmove {}r[{}]({}), {}", r.regsize, r.vr, r.rr, c)
             }
+            Movefnum(r, c) => {
+                write!(f, "\tmove {}r[{}]({}), {}", r.regsize, r.vr, r.rr, c)
+            }
             Movereg(r1, r2) => {
                 write!(
                     f,
@@ -177,29 +329,65 @@ impl fmt::Display for LowIrInstr {
             Ret(r) => {
                 write!(f, "\tret {}r[{}]({})", r.regsize, r.vr, r.rr)
             }
-            Storewreg(r, offset) => {
+            Retaggre(r, aggret) => {
+                write!(
+                    f,
+                    "\tret :{} {}r[{}]({})",
+                    aggret.size, r.regsize, r.vr, r.rr
+                )
+            }
+            Storereg(r, addr) => {
                 write!(
                     f,
-                    "\tstorewreg [base-{}], {}r[{}]({})",
-                    offset, r.regsize, r.vr, r.rr
+                    "\tstore{} {}, {}r[{}]({})",
+                    r.regsize, addr, r.regsize, r.vr, r.rr
                 )
             }
-            Storewnum(num, offset) => {
-                write!(f, "\tstorewnum [base-{}], {}", offset, num)
+            Storenum(valty, num, addr) => {
+                write!(f, "\tstore{} {}, {}", valty.name(), addr, num)
             }
-            Loadw(r, offset) => {
+            Load(ldop, r, addr) => {
                 write!(
                     f,
-                    "\tloadw {}r[{}]({}), [base-{}]",
-                    r.regsize, r.vr, r.rr, offset
+                    "\t{} {}r[{}]({}), {}",
+                    ldop.name(),
+                    r.regsize,
+                    r.vr,
+                    r.rr,
+                    addr
+                )
+            }
+            Lea(r, addr) => {
+                write!(f, "\tlea {}r[{}]({}), {}", r.regsize, r.vr, r.rr, addr)
+            }
+            Ext(extop, r1, r2) => {
+                write!(
+                    f,
+                    "\t{} {}r[{}]({}), {}r[{}]({})",
+                    extop.name(),
+                    r1.regsize,
+                    r1.vr,
+                    r1.rr,
+                    r2.regsize,
+                    r2.vr,
+                    r2.rr
+                )
+            }
+            Cvt(cvtop, r1, r2) => {
+                write!(
+                    f,
+                    "\t{} {}r[{}]({}), {}r[{}]({})",
+                    cvtop.name(),
+                    r1.regsize,
+                    r1.vr,
+                    r1.rr,
+                    r2.regsize,
+                    r2.vr,
+                    r2.rr
                 )
             }
             Bop(binop, r1, r2) => {
-                let bop = match binop {
-                    Binop::Add => "add",
-                    Binop::Sub => "sub",
-                    Binop::Mul => "mul",
-                };
+                let bop = binop.name();
                 let rhs = match r2 {
                     RegorNum::Num(num) => format!("{}", num),
                     RegorNum::Reg(r) => format!("{}r[{}]({})", r.regsize, r.vr, r.rr),
@@ -210,7 +398,7 @@ impl fmt::Display for LowIrInstr {
                     bop, r1.regsize, r1.vr, r1.rr, rhs
                 )
             }
-            Call(r, lb, args, usedrs) => {
+            Call(r, lb, args, usedrs, _) => {
                 write!(
                     f,
                     "\t{}r[{}]({}) <- call ${} (arg * {}), (used register * {})",
@@ -222,47 +410,30 @@ impl fmt::Display for LowIrInstr {
                     usedrs.len()
                 )
             }
-            Comp(op, dst, src, rorn) => match rorn {
-                RegorNum::Reg(r) => {
-                    if *op == CompOp::Ceqw {
-                        write!(
-                            f,
-                            "\t{}r[{}]({}) <- {}r[{}]({}) == {}r[{}]({})",
-                            dst.regsize,
-                            dst.vr,
-                            dst.rr,
-                            src.regsize,
-                            src.vr,
-                            src.rr,
-                            r.regsize,
-                            r.vr,
-                            r.rr
-                        )
-                    } else {
-                        assert_eq!(*op, CompOp::Csltw);
-                        write!(
-                            f,
-                            "\t{}r[{}]({}) <- {}r[{}]({}) < {}r[{}]({})",
-                            dst.regsize,
-                            dst.vr,
-                            dst.rr,
-                            src.regsize,
-                            src.vr,
-                            src.rr,
-                            r.regsize,
-                            r.vr,
-                            r.rr
-                        )
-                    }
-                }
-                RegorNum::Num(num) => {
-                    write!(
-                        f,
-                        "\t{}r[{}]({}) <- {}r[{}]({}) == {}",
-                        dst.regsize, dst.vr, dst.rr, src.regsize, src.vr, src.rr, num
-                    )
-                }
-            },
+            Vastart(addr, _) => {
+                write!(f, "\tvastart {}", addr)
+            }
+            Vaarg(r, addr) => {
+                write!(f, "\tvaarg {}r[{}]({}), {}", r.regsize, r.vr, r.rr, addr)
+            }
+            Comp(op, dst, src, rorn) => {
+                let rhs = match rorn {
+                    RegorNum::Num(num) => format!("{}", num),
+                    RegorNum::Reg(r) => format!("{}r[{}]({})", r.regsize, r.vr, r.rr),
+                };
+                write!(
+                    f,
+                    "\t{}r[{}]({}) <- {} {}r[{}]({}), {}",
+                    dst.regsize,
+                    dst.vr,
+                    dst.rr,
+                    op.name(),
+                    src.regsize,
+                    src.vr,
+                    src.rr,
+                    rhs
+                )
+            }
             Jnz(src, lb1, lb2) => {
                 write!(
                     f,
@@ -281,71 +452,75 @@ impl fmt::Display for LowIrInstr {
 }
 
 #[derive(Clone, Debug)]
-pub struct LowIrBlock {
-    pub lb: &'static str,
-    pub instrs: Vec<LowIrInstr>,
+pub struct LowIrBlock<'a> {
+    pub lb: &'a str,
+    pub instrs: Vec<LowIrInstr<'a>>,
 }
 
-impl LowIrBlock {
-    pub fn new(lb: &'static str) -> Self {
+impl<'a> LowIrBlock<'a> {
+    pub fn new(lb: &'a str) -> Self {
         Self { lb, instrs: vec![] }
     }
-    pub fn pushinstr(&mut self, rinstr: LowIrInstr, day: &mut i32) {
+    pub fn pushinstr(&mut self, rinstr: LowIrInstr<'a>, day: &mut i32) {
         *day += 1;
         self.instrs.push(rinstr)
     }
 }
 
 #[derive(Debug)]
-pub struct LowIrFunction {
-    pub lb: &'static str,
-    pub rbbs: Vec<LowIrBlock>,
+pub struct LowIrFunction<'a> {
+    pub lb: &'a str,
+    pub linkage: Linkage<'a>,
+    pub rbbs: Vec<LowIrBlock<'a>>,
     pub framesize: i32,
+    // general registers allocated in the function
+    pub usedregs: Vec<i32>,
 }
 
-impl LowIrFunction {
-    pub fn new(lb: &'static str) -> Self {
+impl<'a> LowIrFunction<'a> {
+    pub fn new(lb: &'a str) -> Self {
         Self {
             lb,
+            linkage: Linkage::default(),
             rbbs: vec![],
             framesize: -100,
+            usedregs: vec![],
         }
     }
-    fn pushblock(&mut self, rbb: LowIrBlock) {
+    fn pushblock(&mut self, rbb: LowIrBlock<'a>) {
         self.rbbs.push(rbb)
     }
 }
 
 #[derive(Debug)]
-pub struct LowIrProgram {
-    pub funcs: Vec<LowIrFunction>,
-    pub gvs: Vec<Gdata>,
+pub struct LowIrProgram<'a> {
+    pub funcs: Vec<LowIrFunction<'a>>,
+    pub gvs: Vec<Gdata<'a>>,
 }
 
-impl LowIrProgram {
-    pub fn new(gvs: Vec<Gdata>) -> Self {
+impl<'a> LowIrProgram<'a> {
+    pub fn new(gvs: Vec<Gdata<'a>>) -> Self {
         Self { funcs: vec![], gvs }
     }
-    pub fn pushfunc(&mut self, rgfun: LowIrFunction) {
+    pub fn pushfunc(&mut self, rgfun: LowIrFunction<'a>) {
         self.funcs.push(rgfun);
     }
 }
 
-fn evalparserinstr(
-    pinstr: SsaInstr,
+fn evalparserinstr<'a>(
+    pinstr: SsaInstr<'a>,
     rglf: &mut HashMap<i32, (i32, i32)>,
+    regs: &mut FreshRegs,
     vstkd: &mut HashMap<i32, i32>,
-    rbb: &mut LowIrBlock,
+    rbb: &mut LowIrBlock<'a>,
     day: &mut i32,
     stackpointer: &mut i32,
-) -> Option<Register> {
+) -> Option<Register<'a>> {
     use SsaInstrOp::*;
     match pinstr.op {
         Ret(fco) => match fco {
             FirstClassObj::Variable(var) => {
-                let mut src = Register::new(var.rg_vr);
-                src.btday = *day + 1;
-                src.daday = *day + 1;
+                let mut src = Register::newvar(&var, *day + 1, *day + 1);
                 if let Some((btday, _)) = rglf.get(&var.rg_vr) {
                     src.btday = *btday;
                 }
@@ -354,7 +529,9 @@ fn evalparserinstr(
                 Some(src)
             }
             FirstClassObj::Num(valty, num) => {
-                let mut src = Register::new(nextfreshregister());
+                let mut src = Register::new(regs.nextfreshregister());
+                // the result of the function returning long is in rax
+                src.regsize = valty.toregrefsize().max(REGDEFASIZE);
                 src.btday = *day + 1;
                 src.daday = *day + 1;
                 rbb.pushinstr(LowIrInstr::Movenum(src, num), day);
@@ -362,16 +539,27 @@ fn evalparserinstr(
                 rbb.pushinstr(LowIrInstr::Ret(src), day);
                 Some(src)
             }
+            FirstClassObj::Float(..) => match fco2operand(fco, rglf, regs, rbb, day) {
+                RegorNum::Reg(src) => {
+                    rbb.pushinstr(LowIrInstr::Ret(src), day);
+                    Some(src)
+                }
+                RegorNum::Num(_) => panic!("evalparserinstr error in Ret"),
+            },
             FirstClassObj::String(..) => {
                 // TODO
                 panic!("evalparserinstr error in Ret: {:?}", fco);
             }
         },
         Src(fco) => match fco {
+            FirstClassObj::Variable(Var {
+                global: Some(_), ..
+            }) => match fco2operand(fco, rglf, regs, rbb, day) {
+                RegorNum::Reg(src) => Some(src),
+                RegorNum::Num(_) => panic!("evalparserinstr error in Src"),
+            },
             FirstClassObj::Variable(var) => {
-                let mut src = Register::new(var.rg_vr);
-                src.btday = *day + 1;
-                src.daday = *day + 1;
+                let mut src = Register::newvar(&var, *day + 1, *day + 1);
                 if let Some((btday, _)) = rglf.get(&var.rg_vr) {
                     src.btday = *btday;
                 }
@@ -379,22 +567,39 @@ fn evalparserinstr(
                 Some(src)
             }
             FirstClassObj::Num(valty, num) => {
-                let mut src = Register::new(nextfreshregister());
-                src.btday = *day + 1;
-                src.daday = *day + 1;
+                let src = Register::newall(
+                    regs.nextfreshregister(),
+                    *day + 1,
+                    *day + 1,
+                    valty.toregrefsize(),
+                    None,
+                );
                 rbb.pushinstr(LowIrInstr::Movenum(src, num), day);
                 rglf.insert(src.vr, (src.btday, src.daday));
                 Some(src)
             }
+            FirstClassObj::Float(valty, num) => {
+                let mut src = Register::newall(
+                    regs.nextfreshregister(),
+                    *day + 1,
+                    *day + 1,
+                    valty.toregrefsize(),
+                    None,
+                );
+                src.fp = true;
+                rbb.pushinstr(LowIrInstr::Movefnum(src, num), day);
+                rglf.insert(src.vr, (src.btday, src.daday));
+                Some(src)
+            }
             FirstClassObj::String(..) => {
                 // TODO
                 panic!("evalparserinstr error in Ret: {:?}", fco);
             }
         },
         Assign(_valuety, var, pinstr) => {
-            let mut src = evalparserinstr(*pinstr, rglf, vstkd, rbb, day, stackpointer)
+            let mut src = evalparserinstr(*pinstr, rglf, regs, vstkd, rbb, day, stackpointer)
                 .unwrap_or_else(|| panic!("evalparserinstr error: Assign"));
-            let mut dst = Register::newall(var.rg_vr, *day + 1, *day + 1, var.ty.toregrefsize(), var.global);
+            let mut dst = Register::newvar(&var, *day + 1, *day + 1);
             if let Some((btday, _)) = rglf.get(&var.rg_vr) {
                 dst.btday = *btday;
             }
@@ -404,115 +609,248 @@ fn evalparserinstr(
             rglf.insert(dst.vr, (dst.btday, dst.daday));
             None
         }
-        Alloc4(var, _align) => {
-            *stackpointer += 4;
-            let dst = Register::newall(var.rg_vr, *day, *day, var.ty.toregrefsize(), var.global);
+        Alloc(var, align, size) => {
+            *stackpointer = (*stackpointer + size + align - 1) / align * align;
+            // the address of the slot is needed only when it is used as a value
+            let dst = Register::newvar(&var, *day + 1, *day + 1);
             assert_eq!(dst.regsize, 8);
-            rglf.insert(dst.vr, (*day, *day));
+            rglf.insert(dst.vr, (dst.btday, dst.daday));
             vstkd.insert(var.rg_vr, *stackpointer);
+            rbb.pushinstr(LowIrInstr::Lea(dst, Address::Stack(*stackpointer)), day);
             None
         }
-        Storew(fco, dstvar) => {
-            let _bytesize = dstvar.ty.stacksize();
-            // variable stack pointer
-            let varsp = vstkd
-                .get(&dstvar.rg_vr)
-                .unwrap_or_else(|| panic!("var can't be found in Storew"));
-            match fco {
-                FirstClassObj::Num(valty, num) => {
-                    rbb.pushinstr(LowIrInstr::Storewnum(num, *varsp), day);
-                }
-                FirstClassObj::Variable(srcvar) => {
-                    let src;
-                    if let Some((btday, _)) = rglf.get(&srcvar.rg_vr) {
-                        src = Register::newall(
-                            srcvar.rg_vr,
-                            *btday,
-                            *day + 1,
-                            srcvar.ty.toregrefsize(),
-                            srcvar.global,
-                        );
-                        rglf.insert(srcvar.rg_vr, (src.btday, src.daday));
-                    } else {
-                        panic!("{:?} is not defined", srcvar);
-                    }
-                    rbb.pushinstr(LowIrInstr::Storewreg(src, *varsp), day);
+        Store(valty, fco, dstvar) => {
+            let value = fco2operand(fco, rglf, regs, rbb, day);
+            let addr = var2addr(dstvar, rglf, vstkd, *day);
+            match value {
+                RegorNum::Num(num) => {
+                    rbb.pushinstr(LowIrInstr::Storenum(valty, num, addr), day);
                 }
-                FirstClassObj::String(..) => {
-                    // TODO
-                    panic!("evalparserinstr error in Storew: {:?}", fco);
+                RegorNum::Reg(mut src) => {
+                    // src is viewed with the size of the memory
+                    src.regsize = valty.bytesize();
+                    rbb.pushinstr(LowIrInstr::Storereg(src, addr), day);
                 }
             }
             None
         }
-        Loadw(var) => {
-            let (scbt, _) = rglf
-                .get(&var.rg_vr)
-                .unwrap_or_else(|| panic!("{:?} is not defined.", var));
-            rglf.insert(var.rg_vr, (*scbt, *day + 1));
-            let varsp = vstkd
-                .get(&var.rg_vr)
-                .unwrap_or_else(|| panic!("{:?} is not defined.", var));
-            let src = Register::newall(nextfreshregister(), *day + 1, *day + 1, 4, None);
+        Load(ldop, var) => {
+            let addr = var2addr(var, rglf, vstkd, *day);
+            let memty = ldop.memty();
+            // the loaded integer is extended to 64 bits
+            let mut src = Register::newall(regs.nextfreshregister(), *day + 1, *day + 1, 8, None);
+            if memty.isfloat() {
+                src.regsize = memty.bytesize();
+                src.fp = true;
+            }
             rglf.insert(src.vr, (src.btday, src.daday));
-            rbb.pushinstr(LowIrInstr::Loadw(src, *varsp), day);
+            rbb.pushinstr(LowIrInstr::Load(ldop, src, addr), day);
             Some(src)
         }
-        Bop(binop, lfco, rfco) => {
-            let dst;
-            if let FirstClassObj::Variable(v1) = lfco {
-                let (v1birth, _) = rglf
-                    .get(&v1.rg_vr)
-                    .unwrap_or_else(|| panic!("{:?} is not defined.", v1));
-                dst = Register::newall(v1.rg_vr, *v1birth, *day + 1, v1.ty.toregrefsize(), v1.global);
-                rglf.insert(v1.rg_vr, (dst.btday, dst.daday));
-            } else {
-                panic!("Bop lhs error in lowir.{:?}", lfco);
-            }
-            match rfco {
-                FirstClassObj::Variable(v2) => {
-                    let (v2birth, _) = rglf
-                        .get(&v2.rg_vr)
-                        .unwrap_or_else(|| panic!("{:?} is not defined.", v2));
-                    let src = Register::newall(v2.rg_vr, *v2birth, *day + 1, v2.ty.toregrefsize(), v2.global);
-                    rglf.insert(v2.rg_vr, (src.btday, src.daday));
-                    rbb.pushinstr(LowIrInstr::Bop(binop, dst, RegorNum::Reg(src)), day);
+        Ext(extop, fco) => {
+            let srcsize = extop.srcty().bytesize();
+            let src = match fco2reg(fco, rglf, *day) {
+                RegorNum::Reg(mut src) => {
+                    src.regsize = srcsize;
+                    src
+                }
+                RegorNum::Num(num) => {
+                    let src = Register::newall(
+                        regs.nextfreshregister(),
+                        *day + 1,
+                        *day + 2,
+                        srcsize,
+                        None,
+                    );
+                    rglf.insert(src.vr, (src.btday, src.daday));
+                    rbb.pushinstr(LowIrInstr::Movenum(src, num), day);
+                    src
                 }
-                FirstClassObj::Num(valty, num) => {
-                    rbb.pushinstr(LowIrInstr::Bop(binop, dst, RegorNum::Num(num)), day);
+            };
+            // the extended value has 64 bits
+            let dst = Register::newall(regs.nextfreshregister(), *day + 1, *day + 1, 8, None);
+            rglf.insert(dst.vr, (dst.btday, dst.daday));
+            rbb.pushinstr(LowIrInstr::Ext(extop, dst, src), day);
+            Some(dst)
+        }
+        Cvt(cvtop, dstty, fco) => {
+            let srcty = cvtop.srcty(dstty);
+            let src = match fco2operand(fco, rglf, regs, rbb, day) {
+                RegorNum::Reg(mut src) => {
+                    src.regsize = srcty.bytesize();
+                    src
                 }
-                FirstClassObj::String(..) => {
-                    // TODO
-                    panic!("evalparserinstr error in Bop: {:?}", rfco);
+                RegorNum::Num(num) => {
+                    let src = Register::newall(
+                        regs.nextfreshregister(),
+                        *day + 1,
+                        *day + 2,
+                        srcty.bytesize(),
+                        None,
+                    );
+                    rglf.insert(src.vr, (src.btday, src.daday));
+                    rbb.pushinstr(LowIrInstr::Movenum(src, num), day);
+                    src
                 }
+            };
+            let mut dst = Register::newall(
+                regs.nextfreshregister(),
+                *day + 1,
+                *day + 1,
+                dstty.bytesize(),
+                None,
+            );
+            dst.fp = dstty.isfloat();
+            rglf.insert(dst.vr, (dst.btday, dst.daday));
+            rbb.pushinstr(LowIrInstr::Cvt(cvtop, dst, src), day);
+            Some(dst)
+        }
+        Bop(binop, lfco, rfco) => {
+            // dst = lhs; dst <op>= rhs
+            let mut dst = Register::newall(
+                regs.nextfreshregister(),
+                *day + 1,
+                *day + 1,
+                fcoregsize(&lfco),
+                None,
+            );
+            dst.fp = fcoisfloat(&lfco);
+            match lfco {
+                FirstClassObj::Float(_, num) => {
+                    rbb.pushinstr(LowIrInstr::Movefnum(dst, num), day);
+                }
+                _ => match fco2operand(lfco, rglf, regs, rbb, day) {
+                    RegorNum::Reg(src) => rbb.pushinstr(LowIrInstr::Movereg(dst, src), day),
+                    RegorNum::Num(num) => rbb.pushinstr(LowIrInstr::Movenum(dst, num), day),
+                },
             }
+            let rorn = fco2operand(rfco, rglf, regs, rbb, day);
+            dst.daday = *day + 1;
+            rglf.insert(dst.vr, (dst.btday, dst.daday));
+            rbb.pushinstr(LowIrInstr::Bop(binop, dst, rorn), day);
             Some(dst)
         }
         Call(retty, funlb, args, variadic) => {
-            let dst = Register::newall(
-                nextfreshregister(),
+            let tys = args.iter().map(fcotype).collect::<Vec<VarType>>();
+            let (locs, _) = argslocations(&tys, hiddenret(&retty));
+            let mut newargs = vec![];
+            let mut sseregs = 0;
+            for (arg, loc) in args.into_iter().zip(locs) {
+                let rorn = fco2operand(arg, rglf, regs, rbb, day);
+                // scalar arguments are moved to the argument registers or stored to
+                // the outgoing area in advance so that they don't occupy registers at the call
+                match (rorn, loc) {
+                    (rorn, ArgLoc::Reg(fp, index)) => {
+                        let size = match rorn {
+                            RegorNum::Reg(r) => r.regsize,
+                            RegorNum::Num(_) => 4,
+                        };
+                        let mut argr = Register::newall(-(index as i32 + 1), 0, 0, size, None);
+                        argr.fp = fp;
+                        if fp {
+                            sseregs += 1;
+                        }
+                        match rorn {
+                            RegorNum::Reg(r) => {
+                                rbb.pushinstr(LowIrInstr::Movereg(argr, r), day);
+                            }
+                            RegorNum::Num(num) => {
+                                rbb.pushinstr(LowIrInstr::Movenum(argr, num), day);
+                            }
+                        }
+                    }
+                    (RegorNum::Reg(r), ArgLoc::Stack(offset)) => {
+                        rbb.pushinstr(LowIrInstr::Storereg(r, Address::Out(offset)), day);
+                    }
+                    (RegorNum::Num(num), ArgLoc::Stack(offset)) => {
+                        let addr = Address::Out(offset);
+                        rbb.pushinstr(LowIrInstr::Storenum(ValueType::Long, num, addr), day);
+                    }
+                    (rorn, ArgLoc::Aggre(parts)) => {
+                        sseregs += parts.iter().filter(|(sse, _)| *sse).count();
+                        newargs.push((rorn, ArgLoc::Aggre(parts)));
+                    }
+                    (rorn, loc) => newargs.push((rorn, loc)),
+                }
+            }
+            // floating point arguments may be loaded before the call
+            for (arg, _) in &mut newargs {
+                if let RegorNum::Reg(r) = arg {
+                    extendlife(r, rglf, *day);
+                }
+            }
+            // stack slot receiving the aggregate result
+            let aggret = if let VarType::Aggre(agg) = &retty {
+                let align = agg.align.max(8);
+                *stackpointer =
+                    (*stackpointer + (agg.size + 7) / 8 * 8 + align - 1) / align * align;
+                Some(AggRet {
+                    classes: classify(agg),
+                    size: agg.size,
+                    slot: *stackpointer,
+                })
+            } else {
+                None
+            };
+            let mut dst = Register::newall(
+                regs.nextfreshregister(),
                 *day + 1,
                 *day + 1,
                 retty.toregrefsize(),
-                None
+                None,
             );
+            dst.fp = retty.isfloat();
             rglf.insert(dst.vr, (dst.btday, dst.daday));
-            let mut newargs = vec![];
-            for arg in args {
-                newargs.push(fco2reg(arg, rglf, *day));
+            let abi = CallAbi {
+                variadic,
+                sseregs,
+                aggret,
+            };
+            rbb.pushinstr(LowIrInstr::Call(dst, funlb, newargs, vec![], abi), day);
+            Some(dst)
+        }
+        Vaarg(valty, ap) => {
+            let addr = var2addr(ap, rglf, vstkd, *day);
+            // the integer argument is fetched with 64 bits
+            let mut dst = Register::newall(regs.nextfreshregister(), *day + 1, *day + 1, 8, None);
+            if valty.isfloat() {
+                dst.regsize = valty.bytesize();
+                dst.fp = true;
             }
-            rbb.pushinstr(LowIrInstr::Call(dst, funlb, newargs, vec![]), day);
+            rglf.insert(dst.vr, (dst.btday, dst.daday));
+            rbb.pushinstr(LowIrInstr::Vaarg(dst, addr), day);
             Some(dst)
         }
-        Comp(cop, dstv, srcv, fco) => {
-            let dst = Register::newall(dstv.rg_vr, *day + 1, *day + 1, dstv.ty.toregrefsize(), dstv.global);
+        Vastart(ap) => {
+            panic!(
+                "vastart in the function without variadic parameters: {:?}",
+                ap
+            );
+        }
+        Comp(cop, valty, dstv, lfco, rfco) => {
+            // lhs is viewed with the operand size of the comparison
+            let mut src = match fco2operand(lfco, rglf, regs, rbb, day) {
+                RegorNum::Reg(mut src) => {
+                    src.regsize = valty.bytesize();
+                    src
+                }
+                RegorNum::Num(num) => {
+                    let src = Register::newall(
+                        regs.nextfreshregister(),
+                        *day + 1,
+                        *day + 2,
+                        valty.bytesize(),
+                        None,
+                    );
+                    rglf.insert(src.vr, (src.btday, src.daday));
+                    rbb.pushinstr(LowIrInstr::Movenum(src, num), day);
+                    src
+                }
+            };
+            let rorn = fco2operand(rfco, rglf, regs, rbb, day);
+            extendlife(&mut src, rglf, *day);
+            let dst = Register::newvar(&dstv, *day + 1, *day + 1);
             rglf.insert(dst.vr, (dst.btday, dst.daday));
-            let (scbt, _) = rglf
-                .get(&srcv.rg_vr)
-                .unwrap_or_else(|| panic!("{:?} is not defined in Ceqw.", srcv));
-            let src = Register::newall(srcv.rg_vr, *scbt, *day + 1, srcv.ty.toregrefsize(), srcv.global);
-            rglf.insert(srcv.rg_vr, (src.btday, src.daday));
-            let rorn = fco2reg(fco, rglf, *day);
             rbb.pushinstr(LowIrInstr::Comp(cop, dst, src, rorn), day);
             None
         }
@@ -520,7 +858,7 @@ fn evalparserinstr(
             let (scbt, _) = rglf
                 .get(&srcv.rg_vr)
                 .unwrap_or_else(|| panic!("{:?} is not defined in Ceqw.", srcv));
-            let src = Register::newall(srcv.rg_vr, *scbt, *day + 1, srcv.ty.toregrefsize(), srcv.global);
+            let src = Register::newvar(&srcv, *scbt, *day + 1);
             rglf.insert(srcv.rg_vr, (src.btday, src.daday));
             rbb.pushinstr(LowIrInstr::Jnz(src, lb1, lb2), day);
             None
@@ -537,29 +875,139 @@ fn evalparserinstr(
     }
 }
 
-fn fco2reg(fco: FirstClassObj, rglf: &mut HashMap<i32, (i32, i32)>, day: i32) -> RegorNum {
+fn fcoregsize(fco: &FirstClassObj) -> i32 {
+    match fco {
+        FirstClassObj::Variable(var) => var.ty.toregrefsize(),
+        FirstClassObj::Num(vty, _) | FirstClassObj::Float(vty, _) => vty.toregrefsize(),
+        FirstClassObj::String(..) => 8,
+    }
+}
+
+fn fcotype<'a>(fco: &FirstClassObj<'a>) -> VarType<'a> {
+    match fco {
+        FirstClassObj::Variable(var) => var.ty.clone(),
+        FirstClassObj::Num(vty, _) | FirstClassObj::Float(vty, _) => vty.clone(),
+        FirstClassObj::String(..) => VarType::Long,
+    }
+}
+
+fn fcoisfloat(fco: &FirstClassObj) -> bool {
+    match fco {
+        FirstClassObj::Variable(var) => var.ty.isfloat(),
+        FirstClassObj::Float(..) => true,
+        FirstClassObj::Num(..) | FirstClassObj::String(..) => false,
+    }
+}
+
+// fco2reg which also loads the floating point literal to the new register
+fn fco2operand<'a>(
+    fco: FirstClassObj<'a>,
+    rglf: &mut HashMap<i32, (i32, i32)>,
+    regs: &mut FreshRegs,
+    rbb: &mut LowIrBlock<'a>,
+    day: &mut i32,
+) -> RegorNum<'a> {
+    if let FirstClassObj::Float(vty, num) = fco {
+        let mut r = Register::newall(
+            regs.nextfreshregister(),
+            *day + 1,
+            *day + 2,
+            vty.toregrefsize(),
+            None,
+        );
+        r.fp = true;
+        rglf.insert(r.vr, (r.btday, r.daday));
+        rbb.pushinstr(LowIrInstr::Movefnum(r, num), day);
+        return RegorNum::Reg(r);
+    }
+    // the address of global data is loaded to the new register
+    if let FirstClassObj::Variable(Var {
+        global: Some(gl_lb),
+        thread,
+        ..
+    }) = fco
+    {
+        let r = Register::newall(regs.nextfreshregister(), *day + 1, *day + 2, 8, None);
+        rglf.insert(r.vr, (r.btday, r.daday));
+        let addr = if thread {
+            Address::Tls(gl_lb)
+        } else {
+            Address::Global(gl_lb)
+        };
+        rbb.pushinstr(LowIrInstr::Lea(r, addr), day);
+        return RegorNum::Reg(r);
+    }
+    // the immediate is at most 32 bits except for mov, so the wider one is loaded in advance
+    if let FirstClassObj::Num(_, num) = fco {
+        if i32::try_from(num).is_err() {
+            let r = Register::newall(regs.nextfreshregister(), *day + 1, *day + 2, 8, None);
+            rglf.insert(r.vr, (r.btday, r.daday));
+            rbb.pushinstr(LowIrInstr::Movenum(r, num), day);
+            return RegorNum::Reg(r);
+        }
+    }
+    fco2reg(fco, rglf, *day)
+}
+
+// extend the life of r until the next instruction
+fn extendlife(r: &mut Register, rglf: &mut HashMap<i32, (i32, i32)>, day: i32) {
+    r.daday = day + 1;
+    let cdd = rglf.get(&r.vr).map_or(-1, |(_, dd)| *dd);
+    rglf.rgup(r.vr, r.btday, r.daday, cdd);
+}
+
+fn fco2reg<'a>(
+    fco: FirstClassObj<'a>,
+    rglf: &mut HashMap<i32, (i32, i32)>,
+    day: i32,
+) -> RegorNum<'a> {
     match fco {
         FirstClassObj::Variable(var) => {
             if let Some((btday, dday)) = rglf.get(&var.rg_vr) {
-                let r = Register::newall(var.rg_vr, *btday, day + 1, var.ty.toregrefsize(), var.global);
+                let r = Register::newvar(&var, *btday, day + 1);
                 rglf.rgup(var.rg_vr, r.btday, r.daday, *dday);
                 RegorNum::Reg(r)
-            } else if let Some(_) = var.global {
-                let r = Register::newall(var.rg_vr, day + 1, day + 1, var.ty.toregrefsize(), var.global);
+            } else if var.global.is_some() {
+                let r = Register::newvar(&var, day + 1, day + 1);
                 rglf.rgup(var.rg_vr, r.btday, r.daday, -1);
                 RegorNum::Reg(r)
             } else {
                 panic!("{:?} is not defined", var);
             }
         }
-        FirstClassObj::Num(valty, num) => RegorNum::Num(num),
-        FirstClassObj::String(..) => {
+        FirstClassObj::Num(_valty, num) => RegorNum::Num(num),
+        FirstClassObj::Float(..) | FirstClassObj::String(..) => {
             // TODO
             panic!("fco2reg error: {:?}", fco);
         }
     }
 }
 
+// memory address pointed by var
+fn var2addr<'a>(
+    var: Var<'a>,
+    rglf: &mut HashMap<i32, (i32, i32)>,
+    vstkd: &HashMap<i32, i32>,
+    day: i32,
+) -> Address<'a> {
+    // stack slot of alloc is addressed by rbp directly
+    if let Some(varsp) = vstkd.get(&var.rg_vr) {
+        return Address::Stack(*varsp);
+    }
+    match var.global {
+        Some(gl_lb) if var.thread => return Address::Tls(gl_lb),
+        Some(gl_lb) => return Address::Global(gl_lb),
+        None => {}
+    }
+    match fco2reg(FirstClassObj::Variable(var), rglf, day) {
+        RegorNum::Reg(mut base) => {
+            base.regsize = 8;
+            Address::Base(base, 0)
+        }
+        RegorNum::Num(_) => panic!("var2addr error."),
+    }
+}
+
 fn decidereglife(r: &mut Register, rglf: &mut HashMap<i32, (i32, i32)>) {
     if r.vr < 0 {
         return;
@@ -567,8 +1015,26 @@ fn decidereglife(r: &mut Register, rglf: &mut HashMap<i32, (i32, i32)>) {
     let (btday, daday) = rglf
         .get(&r.vr)
         .unwrap_or_else(|| panic!("Isn't it possible to come here? {:?}", rglf));
-    (*r).btday = *btday;
-    (*r).daday = *daday;
+    r.btday = *btday;
+    r.daday = *daday;
+}
+
+// the lifespans follow the block order, so the value live into the loop header is kept until
+// the end of the block jumping back to it (e.g. the bound of the loop used only in the header).
+// `loops` are the first day of the header and the last day of the jumping block.
+fn extendlooplife(rglf: &mut HashMap<i32, (i32, i32)>, loops: &[(i32, i32)]) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &(header, end) in loops {
+            for (btday, daday) in rglf.values_mut() {
+                if *btday < header && header <= *daday && *daday < end {
+                    *daday = end;
+                    changed = true;
+                }
+            }
+        }
+    }
 }
 
 fn registerlifeupdate(lpg: &mut LowIrProgram, rglf: &mut HashMap<i32, (i32, i32)>) {
@@ -578,19 +1044,41 @@ fn registerlifeupdate(lpg: &mut LowIrProgram, rglf: &mut HashMap<i32, (i32, i32)
                 use LowIrInstr::*;
                 match rinstr {
                     Movenum(ref mut r, _)
-                    | Storewreg(ref mut r, _)
+                    | Movefnum(ref mut r, _)
                     | Ret(ref mut r)
-                    | Loadw(ref mut r, _)
+                    | Retaggre(ref mut r, _)
                     | Jnz(ref mut r, ..) => {
                         decidereglife(r, rglf);
                     }
-                    Movereg(.., ref mut r1, ref mut r2) | Comp(_, ref mut r1, ref mut r2, _) => {
+                    Storereg(ref mut r, ref mut addr)
+                    | Load(_, ref mut r, ref mut addr)
+                    | Lea(ref mut r, ref mut addr)
+                    | Vaarg(ref mut r, ref mut addr) => {
+                        decidereglife(r, rglf);
+                        if let Address::Base(ref mut base, _) = addr {
+                            decidereglife(base, rglf);
+                        }
+                    }
+                    Storenum(_, _, Address::Base(ref mut base, _))
+                    | Vastart(Address::Base(ref mut base, _), _) => {
+                        decidereglife(base, rglf);
+                    }
+                    Movereg(ref mut r1, ref mut r2)
+                    | Ext(_, ref mut r1, ref mut r2)
+                    | Cvt(_, ref mut r1, ref mut r2) => {
                         decidereglife(r1, rglf);
                         decidereglife(r2, rglf);
                     }
-                    Call(ref mut r, _, ref mut args, _) => {
+                    Comp(_, ref mut r1, ref mut r2, ref mut r3) => {
+                        decidereglife(r1, rglf);
+                        decidereglife(r2, rglf);
+                        if let RegorNum::Reg(ref mut r) = r3 {
+                            decidereglife(r, rglf);
+                        }
+                    }
+                    Call(ref mut r, _, ref mut args, ..) => {
                         decidereglife(r, rglf);
-                        for arg in args {
+                        for (arg, _) in args {
                             if let RegorNum::Reg(r) = arg {
                                 decidereglife(r, rglf);
                             }
@@ -602,7 +1090,7 @@ fn registerlifeupdate(lpg: &mut LowIrProgram, rglf: &mut HashMap<i32, (i32, i32)
                             decidereglife(r, rglf);
                         }
                     }
-                    Storewnum(..) | Jmp(..) => {}
+                    Storenum(..) | Vastart(..) | Jmp(..) => {}
                     LowNop => {
                         panic!("cannot reach to LowNop instr.");
                     }
@@ -612,14 +1100,140 @@ fn registerlifeupdate(lpg: &mut LowIrProgram, rglf: &mut HashMap<i32, (i32, i32)
     }
 }
 
-fn processfunarguments(args: &Vec<Var>, rglf: &mut HashMap<i32, (i32, i32)>) {
-    for i in 0..args.len() {
-        let r = Register::newall(-(i as i32 + 1), 0, std::i32::MAX, args[i].ty.toregrefsize(), None);
-        rglf.insert(r.vr, (r.btday, r.daday));
+// make the arguments available in the function body and return the stack slot saving
+// the hidden pointer of the aggregate result and the register save area of the variadic function
+fn processfunarguments<'a>(
+    pfun: &SsaFunction<'a>,
+    rglf: &mut HashMap<i32, (i32, i32)>,
+    rbb: &mut LowIrBlock<'a>,
+    day: &mut i32,
+    stackpointer: &mut i32,
+) -> (Option<i32>, Option<VaSave>) {
+    let hidden = hiddenret(&pfun.retty);
+    let tys = pfun
+        .args
+        .iter()
+        .map(|arg| arg.ty.clone())
+        .collect::<Vec<VarType>>();
+    let (locs, _) = argslocations(&tys, hidden);
+    let mut hiddenslot = None;
+    if hidden {
+        *stackpointer += 8;
+        let ptr = Register::newall(-1, 0, MAXLIFE, 8, None);
+        rbb.pushinstr(
+            LowIrInstr::Storereg(ptr, Address::Stack(*stackpointer)),
+            day,
+        );
+        hiddenslot = Some(*stackpointer);
+    }
+    let vasave = if pfun.variadic {
+        Some(processregsavearea(&locs, hidden, rbb, day, stackpointer))
+    } else {
+        None
+    };
+    for (arg, loc) in pfun.args.iter().zip(locs) {
+        match loc {
+            ArgLoc::Reg(fp, index) => {
+                // copy not to be clobbered by the following calls
+                let dst = Register::newvar(arg, *day + 1, *day + 1);
+                let mut argr = Register::newall(-(index as i32 + 1), 0, 0, dst.regsize, None);
+                argr.fp = fp;
+                rglf.insert(dst.vr, (dst.btday, dst.daday));
+                rbb.pushinstr(LowIrInstr::Movereg(dst, argr), day);
+            }
+            ArgLoc::Aggre(parts) => {
+                // save the argument registers to the stack slot of the aggregate
+                let align = match &arg.ty {
+                    VarType::Aggre(agg) => agg.align.max(8),
+                    _ => 8,
+                };
+                *stackpointer =
+                    (*stackpointer + parts.len() as i32 * 8 + align - 1) / align * align;
+                for (i, (fp, index)) in parts.into_iter().enumerate() {
+                    let mut argr = Register::newall(-(index as i32 + 1), 0, MAXLIFE, 8, None);
+                    argr.fp = fp;
+                    let addr = Address::Stack(*stackpointer - i as i32 * 8);
+                    rbb.pushinstr(LowIrInstr::Storereg(argr, addr), day);
+                }
+                let dst = Register::newvar(arg, *day + 1, *day + 1);
+                rglf.insert(dst.vr, (dst.btday, dst.daday));
+                rbb.pushinstr(LowIrInstr::Lea(dst, Address::Stack(*stackpointer)), day);
+            }
+            ArgLoc::Memory(offset, _) => {
+                let dst = Register::newvar(arg, *day + 1, *day + 1);
+                rglf.insert(dst.vr, (dst.btday, dst.daday));
+                rbb.pushinstr(LowIrInstr::Lea(dst, Address::Arg(offset)), day);
+            }
+            ArgLoc::Stack(offset) => {
+                let ldop = match arg.ty {
+                    VarType::Single => LoadOp::Loads,
+                    VarType::Double => LoadOp::Loadd,
+                    VarType::Word => LoadOp::Loadw,
+                    VarType::Half => LoadOp::Loadsh,
+                    VarType::Byte => LoadOp::Loadsb,
+                    _ => LoadOp::Loadl,
+                };
+                let dst = Register::newvar(arg, *day + 1, *day + 1);
+                rglf.insert(dst.vr, (dst.btday, dst.daday));
+                rbb.pushinstr(LowIrInstr::Load(ldop, dst, Address::Arg(offset)), day);
+            }
+        }
     }
+    (hiddenslot, vasave)
 }
 
-pub fn genlowir(spg: SsaProgram) -> LowIrProgram {
+// save all the argument registers for vaarg
+fn processregsavearea(
+    locs: &[ArgLoc],
+    hidden: bool,
+    rbb: &mut LowIrBlock,
+    day: &mut i32,
+    stackpointer: &mut i32,
+) -> VaSave {
+    let mut gpnum = if hidden { 1 } else { 0 };
+    let mut fpnum = 0;
+    let mut overflow = 0;
+    for loc in locs {
+        match loc {
+            ArgLoc::Reg(false, _) => gpnum += 1,
+            ArgLoc::Reg(true, _) => fpnum += 1,
+            ArgLoc::Aggre(parts) => {
+                for (fp, _) in parts {
+                    if *fp {
+                        fpnum += 1;
+                    } else {
+                        gpnum += 1;
+                    }
+                }
+            }
+            ArgLoc::Stack(offset) => overflow = overflow.max(offset + 8),
+            ArgLoc::Memory(offset, size) => overflow = overflow.max(offset + (size + 7) / 8 * 8),
+        }
+    }
+    // 8 bytes for each integer register followed by 16 bytes for each sse register
+    *stackpointer =
+        (*stackpointer + ARGREGQUANTITY as i32 * 8 + ARGXMMQUANTITY as i32 * 16 + 15) / 16 * 16;
+    let slot = *stackpointer;
+    for index in 0..ARGREGQUANTITY {
+        let argr = Register::newall(-(index as i32 + 1), 0, MAXLIFE, 8, None);
+        let addr = Address::Stack(slot - index as i32 * 8);
+        rbb.pushinstr(LowIrInstr::Storereg(argr, addr), day);
+    }
+    for index in 0..ARGXMMQUANTITY {
+        let mut argr = Register::newall(-(index as i32 + 1), 0, MAXLIFE, 8, None);
+        argr.fp = true;
+        let addr = Address::Stack(slot - ARGREGQUANTITY as i32 * 8 - index as i32 * 16);
+        rbb.pushinstr(LowIrInstr::Storereg(argr, addr), day);
+    }
+    VaSave {
+        gpoffset: gpnum * 8,
+        fpoffset: ARGREGQUANTITY as i32 * 8 + fpnum * 16,
+        overflow,
+        slot,
+    }
+}
+
+pub fn genlowir<'a>(spg: SsaProgram<'a>) -> LowIrProgram<'a> {
     let mut day = 0;
     // manage register lifespan
     let mut rglf: HashMap<i32, (i32, i32)> = HashMap::new();
@@ -629,29 +1243,74 @@ pub fn genlowir(spg: SsaProgram) -> LowIrProgram {
     for gd in &spg.gvs {
         rglf.insert(gd.frsn, (0, MAXLIFE));
     }
+    let mut regs = spg.regs;
     let mut lpg = LowIrProgram::new(spg.gvs);
     for pfun in spg.funcs {
         let mut rfun = LowIrFunction::new(pfun.name);
+        rfun.linkage = pfun.linkage.clone();
         let mut stackpointer = 0;
         // function arguments
-        processfunarguments(&pfun.args, &mut rglf);
+        let mut entry = LowIrBlock::new(pfun.name);
+        let (hiddenslot, vasave) =
+            processfunarguments(&pfun, &mut rglf, &mut entry, &mut day, &mut stackpointer);
+        let retagg = match &pfun.retty {
+            VarType::Aggre(agg) => Some(AggRet {
+                classes: classify(agg),
+                size: agg.size,
+                slot: hiddenslot.unwrap_or(0),
+            }),
+            _ => None,
+        };
+        // days of each block and its successors
+        let mut bbdays = HashMap::new();
+        let mut edges = vec![];
         for pbb in pfun.bls {
             let mut rbb = LowIrBlock::new(pbb.lb);
+            rbb.instrs.append(&mut entry.instrs);
+            let firstday = day + 1;
             for instr in pbb.instrs {
                 if !instr.living {
                     continue;
                 }
+                // aggregate result
+                if let (SsaInstrOp::Ret(fco), Some(aggret)) = (&instr.op, &retagg) {
+                    let src = match fco2reg(fco.clone(), &mut rglf, day) {
+                        RegorNum::Reg(src) => src,
+                        RegorNum::Num(_) => panic!("aggregate result must be an address."),
+                    };
+                    rbb.pushinstr(LowIrInstr::Retaggre(src, aggret.clone()), &mut day);
+                    continue;
+                }
+                // va_list is initialized by the register save area
+                if let (SsaInstrOp::Vastart(ap), Some(vasave)) = (&instr.op, vasave) {
+                    let addr = var2addr(ap.clone(), &mut rglf, &vstkd, day);
+                    rbb.pushinstr(LowIrInstr::Vastart(addr, vasave), &mut day);
+                    continue;
+                }
                 evalparserinstr(
                     instr,
                     &mut rglf,
+                    &mut regs,
                     &mut vstkd,
                     &mut rbb,
                     &mut day,
                     &mut stackpointer,
                 );
             }
+            bbdays.insert(pbb.lb, (firstday, day));
+            edges.push((pbb.lb, pbb.transbbs));
             rfun.pushblock(rbb)
         }
+        // jumps back in the block order
+        let mut loops = vec![];
+        for (lb, succs) in edges {
+            for succ in succs {
+                if bbdays[succ].0 <= bbdays[lb].0 {
+                    loops.push((bbdays[succ].0, bbdays[lb].1));
+                }
+            }
+        }
+        extendlooplife(&mut rglf, &loops);
         rfun.framesize = stackpointer;
         lpg.pushfunc(rfun);
     }
@@ -1,21 +1,72 @@
 extern crate mirlvm;
 use std::env;
+use std::fs;
+use std::process;
 
 use mirlvm::codegen::*;
 use mirlvm::deadcode::*;
 use mirlvm::dominators::*;
+use mirlvm::interp::*;
 use mirlvm::lexer::*;
 use mirlvm::lowir::*;
 use mirlvm::mem2reg::*;
 use mirlvm::parser::*;
 use mirlvm::rega::*;
 use mirlvm::rev_ssa::*;
+use mirlvm::sroa::*;
+use mirlvm::verify::*;
+use mirlvm::Diagnostic;
+
+// report the errors with the source lines and exit
+fn report(diags: Vec<Diagnostic>, file: &str, source: &str) -> ! {
+    for diag in diags {
+        eprint!("{}", diag.with_file(file).render(source));
+    }
+    process::exit(1);
+}
+
+// check the program after the pass when --verify-each is given
+fn verifyafter(spg: &SsaProgram, form: Form, pass: &str, file: &str, source: &str) {
+    if let Err(diags) = verify(spg, form) {
+        let diags = diags
+            .into_iter()
+            .map(|diag| Diagnostic::new(format!("after {}: {}", pass, diag.message)))
+            .collect();
+        report(diags, file, source);
+    }
+}
+
+// run the program after the pass when --interp is given. the result must be the same as the
+// first run.
+fn interpafter(spg: &SsaProgram, form: Form, pass: &str, first: &mut Option<Run>) {
+    let run = interpret(spg, form).unwrap_or_else(|diag| {
+        eprintln!("error: after {}: {}", pass, diag.message);
+        process::exit(1);
+    });
+    match first {
+        None => {
+            print!("{}", run.stdout);
+            *first = Some(run);
+        }
+        Some(first) if *first != run => {
+            eprintln!(
+                "error: after {}: main returned {} and printed {:?}, but {} and {:?} before",
+                pass, run.ret, run.stdout, first.ret, first.stdout
+            );
+            process::exit(1);
+        }
+        Some(_) => {}
+    }
+}
 
 fn main() {
     let args = env::args().collect::<Vec<String>>();
     let option = &args[1];
     let mut option2 = "";
     let mut option3 = "";
+    let verifyeach = args.iter().any(|arg| arg == "--verify-each");
+    // result of the interpreter before the passes
+    let mut firstrun = None;
 
     if args.len() > 3 {
         option2 = &args[2];
@@ -25,8 +76,12 @@ fn main() {
         option3 = &args[3];
     }
 
+    // the source file is the last argument
+    let file = args.last().unwrap();
+    let source = fs::read_to_string(file).expect("failed to read file.");
+
     // lexical analysis
-    let mut tmass = lex();
+    let mut tmass = lex(&source).unwrap_or_else(|diag| report(vec![diag], file, &source));
 
     if option == "--out-lex" {
         println!("{:#?}", tmass);
@@ -34,7 +89,9 @@ fn main() {
     }
 
     // parsing
-    let mut ssaprogram = parse(&mut tmass);
+    let names = NamePool::default();
+    let mut ssaprogram =
+        parse(&mut tmass, &names).unwrap_or_else(|diags| report(diags, file, &source));
 
     if option == "--out-m2rinfo" {
         for func in ssaprogram.funcs {
@@ -52,21 +109,13 @@ fn main() {
     }
 
     if option == "--out-ssair" {
-        for func in &ssaprogram.funcs {
-            println!("function {}", func.name);
-            for b in &func.bls {
-                println!("{}:", b.lb);
-                for instr in &b.instrs {
-                    println!("{:?}", instr.op);
-                }
-            }
-        }
+        print!("{}", ssaprogram);
         return;
     }
 
     if option == "--out-gdata" {
         for gv in &ssaprogram.gvs {
-            println!("{:?}", gv);
+            println!("{}", gv);
         }
         return;
     }
@@ -74,6 +123,14 @@ fn main() {
     // compute dominators tree
     dominators(&mut ssaprogram);
 
+    if verifyeach {
+        verifyafter(&ssaprogram, Form::Parsed, "parse", file, &source);
+    }
+
+    if option == "--interp" {
+        interpafter(&ssaprogram, Form::Parsed, "parse", &mut firstrun);
+    }
+
     // information for each basic block
     if option == "--out-parsebb" {
         for func in &ssaprogram.funcs {
@@ -104,30 +161,59 @@ fn main() {
 
     removeuselessinstr(&mut ssaprogram);
 
+    if verifyeach {
+        verifyafter(
+            &ssaprogram,
+            Form::Pruned,
+            "removeuselessinstr",
+            file,
+            &source,
+        );
+    }
+
     // SSA optical phase
     // remove useless instr
     if option2 == "-O1" {
+        sroa(&mut ssaprogram);
+        if verifyeach {
+            verifyafter(&ssaprogram, Form::Pruned, "sroa", file, &source);
+        }
+        if option == "--interp" {
+            interpafter(&ssaprogram, Form::Pruned, "sroa", &mut firstrun);
+        }
         ezmem2reg(&mut ssaprogram);
         mem2reg(&mut ssaprogram);
+        if verifyeach {
+            verifyafter(&ssaprogram, Form::Pruned, "mem2reg", file, &source);
+        }
+        if option == "--interp" {
+            interpafter(&ssaprogram, Form::Pruned, "mem2reg", &mut firstrun);
+        }
     }
 
     if option == "--out-ssair_1" {
-        for func in &ssaprogram.funcs {
-            println!("function {}", func.name);
-            for b in &func.bls {
-                println!("{}:", b.lb);
-                for instr in &b.instrs {
-                    if instr.living {
-                        println!("{:?}", instr.op);
-                    }
-                }
+        // instructions removed by the passes are not printed
+        for func in &mut ssaprogram.funcs {
+            for b in &mut func.bls {
+                b.instrs.retain(|instr| instr.living);
             }
         }
+        print!("{}", ssaprogram);
         return;
     }
 
     rev_ssa(&mut ssaprogram);
 
+    if verifyeach {
+        verifyafter(&ssaprogram, Form::Normal, "rev_ssa", file, &source);
+    }
+
+    if option == "--interp" {
+        interpafter(&ssaprogram, Form::Normal, "rev_ssa", &mut firstrun);
+        // exit status is the return value of main as the compiled program
+        process::exit(firstrun.unwrap().ret as i32);
+    }
+
     if option == "--out-norm_fmt" {
         for func in &ssaprogram.funcs {
             println!("func: {}", func.name);
@@ -194,5 +280,5 @@ fn main() {
     }
 
     // generate x64 code
-    gen_x64code(lirpg2, option3 == "-Sec");
+    print!("{}", gen_x64code(lirpg2, option3 == "-Sec"));
 }
@@ -1,46 +1,9 @@
-use super::dominators::ControlFlowGraph;
 use super::parser::{
-    nextfreshregister, FirstClassObj, SsaBlock, SsaInstr, SsaInstrOp, SsaProgram, ValueType, Var,
-    VarType,
+    ExtOp, FirstClassObj, LoadOp, OperandUse, SsaBlock, SsaFunction, SsaInstr, SsaInstrOp,
+    SsaProgram, ValueType, Var,
 };
 use super::*;
-use once_cell::sync::Lazy;
-use std::collections::VecDeque;
 use std::collections::{HashMap, HashSet};
-use std::sync::Mutex;
-extern crate rand;
-use rand::seq::SliceRandom;
-
-pub static FRESH_NUM_MAP: Lazy<Mutex<HashMap<usize, &'static str>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-pub static FRESH_N: Lazy<Mutex<usize>> = Lazy::new(|| Mutex::new(0));
-
-fn new_freshnum_phi() -> usize {
-    let res = *FRESH_N.lock().unwrap();
-    *FRESH_N.lock().unwrap() = res + 1;
-    res
-}
-
-trait OwnHash<K: std::cmp::Eq + std::hash::Hash, V: std::clone::Clone> {
-    type K;
-    type V;
-    fn get_into(&mut self, key: K) -> Option<V>;
-}
-
-impl<K: std::cmp::Eq + std::hash::Hash, V: std::clone::Clone> OwnHash<K, V> for HashMap<K, V> {
-    type K = Label;
-    type V = (Label, Vec<(Label, FirstClassObj)>);
-    fn get_into(&mut self, key: K) -> Option<V> {
-        let value = self.get(&key);
-        if let Some(_) = value {
-            let res = Some(value.unwrap().clone());
-            self.remove(&key);
-            res
-        } else {
-            None
-        }
-    }
-}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MemToregType {
@@ -51,30 +14,61 @@ pub enum MemToregType {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct MemToregAlloca {
-    pub name: &'static str,
+pub struct MemToregAlloca<'a> {
+    pub name: &'a str,
     pub defbbs: HashSet<usize>,
     pub usgbbs: HashSet<usize>,
     pub strcnt: usize,
     pub ty: Option<MemToregType>,
+    // type of the value in the stack slot (e.g. Long for storel and loadl)
+    pub elty: Option<ValueType>,
+    // accessed by the loads and the stores of the different types
+    pub mixed: bool,
 }
 
-impl MemToregAlloca {
-    pub fn new(name: &'static str) -> Self {
+impl<'a> MemToregAlloca<'a> {
+    pub fn new(name: &'a str) -> Self {
         Self {
             name,
             defbbs: HashSet::new(),
             usgbbs: HashSet::new(),
             strcnt: 0,
             ty: None,
+            elty: None,
+            mixed: false,
         }
     }
-    pub fn strpush(&mut self, bbid: usize) {
+    pub fn strpush(&mut self, bbid: usize, valty: ValueType) {
         self.defbbs.insert(bbid);
         self.strcnt += 1;
+        self.access(valty);
+    }
+    pub fn ldpush(&mut self, bbid: usize, ldop: LoadOp) {
+        self.usgbbs.insert(bbid);
+        self.access(ldop.memty());
     }
-    pub fn decision_type(mtamass: &mut HashMap<&'static str, Self>) {
+    fn access(&mut self, memty: ValueType) {
+        match self.elty {
+            None => self.elty = Some(memty),
+            Some(elty) if elty != memty => self.mixed = true,
+            _ => {}
+        }
+    }
+    // type of the register holding the value. byte and half are held in the word as the stored
+    // values are.
+    pub fn regty(&self) -> ValueType {
+        match self.elty {
+            Some(ValueType::Byte | ValueType::Half) | None => ValueType::Word,
+            Some(elty) => elty,
+        }
+    }
+    pub fn decision_type(mtamass: &mut HashMap<&'a str, Self>, bls: &[SsaBlock<'a>]) {
+        let escaped = Self::escapedallocas(mtamass, bls);
         for (_, m2ralloc) in mtamass.iter_mut() {
+            if escaped.contains(m2ralloc.name) || m2ralloc.mixed {
+                m2ralloc.ty = Some(MemToregType::Necessary);
+                continue;
+            }
             if m2ralloc.strcnt == 1 {
                 m2ralloc.ty = Some(MemToregType::OneStore);
                 continue;
@@ -86,63 +80,162 @@ impl MemToregAlloca {
                 m2ralloc.ty = Some(MemToregType::OneBlock);
                 continue;
             }
-            // TODO
-            // for Necessary type
             m2ralloc.ty = Some(MemToregType::General);
         }
     }
-    pub fn eztype(mtamass: &HashMap<&'static str, Self>, vne: &'static str) -> bool {
-        use MemToregType::*;
-        if let OneStore | OneBlock = mtamass
+    // allocas whose address is used other than the address of load and store (e.g. passed to
+    // the call, stored to the memory or used in the pointer arithmetic)
+    fn escapedallocas(mtamass: &HashMap<&'a str, Self>, bls: &[SsaBlock<'a>]) -> HashSet<&'a str> {
+        let mut escaped = HashSet::new();
+        for bb in bls {
+            for instr in &bb.instrs {
+                // the addresses of load and store don't escape
+                instr.op.visitoperands(&mut |var, operanduse| {
+                    if operanduse != OperandUse::Address
+                        && var.global.is_none()
+                        && mtamass.contains_key(var.name)
+                    {
+                        escaped.insert(var.name);
+                    }
+                });
+            }
+        }
+        escaped
+    }
+    // alloca which can be converted to the register
+    pub fn promotable(mtamass: &HashMap<&'a str, Self>, vne: &'a str) -> bool {
+        mtamass
             .get(vne)
-            .unwrap_or_else(|| panic!("{} don't be defined.", vne))
-            .ty
-            .unwrap_or_else(|| panic!("{}'s memtoregtype is not defined.", vne))
-        {
-            true
-        } else {
-            false
+            .is_some_and(|m2ralloc| m2ralloc.ty != Some(MemToregType::Necessary))
+    }
+    pub fn eztype(mtamass: &HashMap<&'a str, Self>, vne: &'a str) -> bool {
+        use MemToregType::*;
+        matches!(
+            mtamass
+                .get(vne)
+                .unwrap_or_else(|| panic!("{} don't be defined.", vne))
+                .ty
+                .unwrap_or_else(|| panic!("{}'s memtoregtype is not defined.", vne)),
+            OneStore | OneBlock
+        )
+    }
+}
+
+// block a strictly dominates block b
+fn sdominates(bls: &[SsaBlock], a: usize, b: usize) -> bool {
+    let mut cur = b;
+    while cur != 0 && cur != usize::MAX {
+        cur = bls[cur].idom;
+        if cur == a {
+            return true;
         }
     }
+    false
+}
+
+// OneStore or OneBlock allocas loaded where neither the store earlier in the block nor the
+// store in the dominator gives the value (e.g. the load of the previous iteration in the loop)
+fn undominatedloads<'a>(func: &SsaFunction<'a>) -> Vec<&'a str> {
+    use SsaInstrOp::*;
+    let m2rinfo = &func.m2rinfo;
+    let ez = |vne| MemToregAlloca::promotable(m2rinfo, vne) && MemToregAlloca::eztype(m2rinfo, vne);
+    let mut stbbs = HashMap::new();
+    for bb in &func.bls {
+        for instr in &bb.instrs {
+            if let Store(_, _, var) = &instr.op {
+                if ez(var.name) {
+                    stbbs.insert(var.name, bb.id);
+                }
+            }
+        }
+    }
+    let mut res = vec![];
+    for bb in &func.bls {
+        let mut stored = HashSet::new();
+        for instr in &bb.instrs {
+            match &instr.op {
+                Assign(_, _, rhs) => {
+                    let Load(_, ldvar) = &rhs.op else {
+                        continue;
+                    };
+                    let vne = ldvar.name;
+                    if ez(vne)
+                        && !stored.contains(vne)
+                        && !stbbs
+                            .get(vne)
+                            .is_some_and(|&stbb| sdominates(&func.bls, stbb, bb.id))
+                        && !res.contains(&vne)
+                    {
+                        res.push(vne);
+                    }
+                }
+                Store(_, _, var) => {
+                    stored.insert(var.name);
+                }
+                _ => {}
+            }
+        }
+    }
+    res
 }
 
 // process for OneStore or OneBlock MemToregType
 pub fn ezmem2reg(spg: &mut SsaProgram) {
     for func in &mut spg.funcs {
+        // these are renamed with the phis by mem2reg
+        for vne in undominatedloads(func) {
+            func.m2rinfo.get_mut(vne).unwrap().ty = Some(MemToregType::General);
+        }
         let m2rinfo = &func.m2rinfo;
-        // hashmap for the latest src data for alloca var.
-        let mut sthash: HashMap<Label, SsaInstrOp> = HashMap::new();
+        // the stored value of the OneStore alloca, which is used by the loads in the blocks
+        // it dominates
+        let mut sthash: HashMap<Label, FirstClassObj> = HashMap::new();
+        for bb in &func.bls {
+            for instr in &bb.instrs {
+                if let SsaInstrOp::Store(_, fco, var) = &instr.op {
+                    if MemToregAlloca::promotable(m2rinfo, var.name)
+                        && MemToregAlloca::eztype(m2rinfo, var.name)
+                    {
+                        sthash.insert(var.name, fco.clone());
+                    }
+                }
+            }
+        }
         for bb in &mut func.bls {
-            let mut st_onebb_hash: HashMap<Label, SsaInstrOp> = HashMap::new();
+            let mut st_onebb_hash: HashMap<Label, FirstClassObj> = HashMap::new();
             for instr in &mut bb.instrs {
                 use SsaInstrOp::*;
                 match &instr.op {
-                    Assign(vty, v, rhs) if matches!(&rhs.op, Loadw(..)) => {
-                        let vne = rhs.getld_vn();
-                        if let Some(sop) = st_onebb_hash.get(vne) {
-                            instr.op =
-                                Assign(*vty, v.clone(), Box::new(SsaInstr::new(sop.clone())));
+                    Assign(vty, v, rhs) if matches!(&rhs.op, Load(..)) => {
+                        let Load(ldop, ldvar) = &rhs.op else {
+                            unreachable!()
+                        };
+                        let vne = ldvar.name;
+                        if !MemToregAlloca::promotable(m2rinfo, vne) {
+                            continue;
+                        }
+                        if let Some(fco) = st_onebb_hash.get(vne) {
+                            let sop = loadedvalue(*ldop, *vty, fco.clone());
+                            instr.op = Assign(*vty, v.clone(), Box::new(SsaInstr::new(sop)));
                             continue;
                         }
                         if MemToregAlloca::eztype(m2rinfo, vne) {
-                            instr.op = Assign(
-                                *vty,
-                                v.clone(),
-                                Box::new(SsaInstr::new(sthash.get(vne).unwrap().clone())),
-                            );
+                            let sop = loadedvalue(*ldop, *vty, sthash.get(vne).unwrap().clone());
+                            instr.op = Assign(*vty, v.clone(), Box::new(SsaInstr::new(sop)));
                         }
                     }
-                    Storew(fco, var) => {
-                        st_onebb_hash.insert(var.name, SsaInstrOp::Src(fco.clone()));
+                    // memory pointed by other than alloc may be aliased
+                    Store(_, fco, var) if MemToregAlloca::promotable(m2rinfo, var.name) => {
+                        st_onebb_hash.insert(var.name, fco.clone());
                         if MemToregAlloca::eztype(m2rinfo, var.name) {
-                            sthash.insert(&var.name, SsaInstrOp::Src(fco.clone()));
                             instr.op = Nop;
                         }
                     }
-                    Alloc4(var, _) => {
-                        if MemToregAlloca::eztype(m2rinfo, var.name) {
-                            instr.op = Nop;
-                        }
+                    Alloc(var, ..)
+                        if m2rinfo.contains_key(var.name)
+                            && MemToregAlloca::eztype(m2rinfo, var.name) =>
+                    {
+                        instr.op = Nop;
                     }
                     _ => {}
                 }
@@ -152,16 +245,55 @@ pub fn ezmem2reg(spg: &mut SsaProgram) {
     }
 }
 
+// value of the load replaced by the stored value. the value is extended as the load does
+// (e.g. loadub of the byte slot is extub of the stored word).
+fn loadedvalue<'a>(ldop: LoadOp, vty: ValueType, fco: FirstClassObj<'a>) -> SsaInstrOp<'a> {
+    use LoadOp::*;
+    let extop = match ldop {
+        Loadsb => Some(ExtOp::Extsb),
+        Loadub => Some(ExtOp::Extub),
+        Loadsh => Some(ExtOp::Extsh),
+        Loaduh => Some(ExtOp::Extuh),
+        Loadw | Loadsw if vty == ValueType::Long => Some(ExtOp::Extsw),
+        Loaduw if vty == ValueType::Long => Some(ExtOp::Extuw),
+        _ => None,
+    };
+    match extop {
+        Some(extop) => SsaInstrOp::Ext(extop, fco),
+        None => SsaInstrOp::Src(fco),
+    }
+}
+
+// value of the stack slot loaded before any store (the initial value of alloca is undefined)
+fn undefvalue<'a>(regty: ValueType) -> FirstClassObj<'a> {
+    if regty.isfloat() {
+        FirstClassObj::Float(regty.tovarty(), 0.0)
+    } else {
+        FirstClassObj::Num(regty.tovarty(), 0)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct PhiNode(&'static str);
+pub struct PhiNode<'a>(&'a str);
 
 pub fn mem2reg(spg: &mut SsaProgram) {
+    let regs = &mut spg.regs;
+    let names = spg.names;
     // insert Phi node to dominator frontier of every alloca defblock.
     for func in &mut spg.funcs {
         let mut insert_phi_bbs: Vec<Vec<PhiNode>> =
             vec![vec![]; func.cfg.as_deref().unwrap().graph.len()];
-        for (_, value) in func.m2rinfo.iter() {
-            for defbb in &value.defbbs {
+        // allocas and blocks are visited in order so that the output is reproducible
+        let mut m2rallocs = func
+            .m2rinfo
+            .values()
+            .filter(|m2ralloc| m2ralloc.ty == Some(MemToregType::General))
+            .collect::<Vec<_>>();
+        m2rallocs.sort_by_key(|m2ralloc| m2ralloc.name);
+        for value in m2rallocs {
+            let mut defbbs = value.defbbs.iter().collect::<Vec<_>>();
+            defbbs.sort();
+            for defbb in defbbs {
                 let mut inserted_domfs = func.bls[*defbb].domfros.clone();
                 while let Some(domf) = inserted_domfs.pop() {
                     if domf >= insert_phi_bbs.capacity() {
@@ -175,6 +307,7 @@ pub fn mem2reg(spg: &mut SsaProgram) {
                 }
             }
         }
+        let m2rinfo = &func.m2rinfo;
         for bb in &mut func.bls {
             if bb.id >= insert_phi_bbs.capacity() {
                 panic!("insert_phi_bbs capacity error2.");
@@ -182,21 +315,18 @@ pub fn mem2reg(spg: &mut SsaProgram) {
             for _ in insert_phi_bbs[bb.id].iter().map(|phin| {
                 let phi_instr =
                     SsaInstr::new_all(SsaInstrOp::Phi(Some(phin.0), vec![]), true, bb.lb);
-                let freshn = new_freshnum_phi();
-                let varlb = format!("z#_{}", freshn);
-                FRESH_NUM_MAP
-                    .lock()
-                    .unwrap()
-                    .insert(freshn, Box::leak(varlb.into_boxed_str()));
+                // the variable is named after the alloca (`%a.n`). it has the type of the value
+                // in the stack slot.
+                let regty = m2rinfo.get(phin.0).unwrap().regty();
                 let var = Var::new(
-                    FRESH_NUM_MAP.lock().unwrap().get(&freshn).unwrap(),
-                    VarType::Word,
-                    nextfreshregister(),
+                    names.fresh(phin.0),
+                    regty.tovarty(),
+                    regs.nextfreshregister(),
                 );
                 bb.instrs.insert(
                     0,
                     SsaInstr::new_all(
-                        SsaInstrOp::Assign(ValueType::Word, var, Box::new(phi_instr)),
+                        SsaInstrOp::Assign(regty, var, Box::new(phi_instr)),
                         true,
                         bb.lb,
                     ),
@@ -206,47 +336,7 @@ pub fn mem2reg(spg: &mut SsaProgram) {
     }
     // convert target load to src register
     for func in &mut spg.funcs {
-        let mut able_reach_nodes = vec![HashSet::new(); func.cfg.as_ref().unwrap().graph.len()];
-        let mut edge_nums = 0;
-        for nodes in &func.cfg.as_ref().unwrap().graph {
-            edge_nums += nodes.len();
-        }
-        for snode in 0..able_reach_nodes.len() {
-            let mut walked = vec![usize::MAX; able_reach_nodes.len()];
-            let mut dequeue = VecDeque::new();
-            dequeue.push_back(snode);
-            while let Some(node) = dequeue.pop_front() {
-                for enode in &func.cfg.as_ref().unwrap().graph[node] {
-                    if walked[*enode] != usize::MAX {
-                        continue;
-                    }
-                    dequeue.push_back(*enode);
-                    walked[*enode] = *enode;
-                }
-            }
-            able_reach_nodes[snode] = walked
-                .iter()
-                .filter(|&node| *node != usize::MAX)
-                .cloned()
-                .collect::<HashSet<usize>>();
-        }
-        let target_id = 0;
-        let incoming_nodes = HashMap::new();
-        let alloca_newvar_hash = HashMap::new();
-        let mut current_reached_nodes = HashSet::new();
-        let mut reached_edges = HashSet::new();
-        walk_bb(
-            incoming_nodes,
-            alloca_newvar_hash,
-            &func.cfg,
-            &mut func.bls,
-            &able_reach_nodes,
-            &mut current_reached_nodes,
-            &mut reached_edges,
-            &edge_nums,
-            target_id,
-            false,
-        );
+        rename(func);
     }
     // delete unneccessary alloca and store
     for func in &mut spg.funcs {
@@ -258,10 +348,13 @@ pub fn mem2reg(spg: &mut SsaProgram) {
                 .instrs
                 .iter()
                 .filter(|&isr| match &isr.op {
-                    Alloc4(alloca_var, _) | Storew(_, alloca_var) => {
-                        match m2rinfo.get(alloca_var.name).unwrap().ty.unwrap() {
-                            OneStore | OneBlock | General => false,
-                            Necessary => true,
+                    Alloc(alloca_var, ..) | Store(_, _, alloca_var) => {
+                        match m2rinfo
+                            .get(alloca_var.name)
+                            .map(|m2ralloc| m2ralloc.ty.unwrap())
+                        {
+                            Some(OneStore | OneBlock | General) => false,
+                            Some(Necessary) | None => true,
                         }
                     }
                     _ => true,
@@ -272,110 +365,94 @@ pub fn mem2reg(spg: &mut SsaProgram) {
     }
 }
 
-fn walk_bb(
-    mut incoming_nodes: HashMap<Label, Vec<(Label, FirstClassObj)>>,
-    mut alloca_newvar_hash: HashMap<Label, Var>,
-    cfg: &Option<Box<ControlFlowGraph>>,
-    bbs: &mut Vec<SsaBlock>,
-    able_reach_nodes: &Vec<HashSet<usize>>,
-    current_reached_nodes: &mut HashSet<usize>,
-    reached_edges: &mut HashSet<(usize, usize)>,
-    edge_nums: &usize,
-    target_id: usize,
-    mut new_edge: bool,
-) {
-    let tbb = &mut bbs[target_id];
+// stack slot converted to the register by phi
+fn promoted(m2rinfo: &HashMap<&str, MemToregAlloca>, name: &str) -> bool {
+    matches!(
+        m2rinfo.get(name).and_then(|m2ralloc| m2ralloc.ty),
+        Some(MemToregType::General)
+    )
+}
+
+enum RenameStep<'a> {
+    Enter(usize),
+    // pop the values defined in the block
+    Leave(Vec<Label<'a>>),
+}
+
+// rename the loads and the phi operands in the preorder of the dominator tree.
+// the value of each alloca is the top of its stack while the block and its dominated blocks
+// are visited.
+fn rename<'a>(func: &mut SsaFunction<'a>) {
     use SsaInstrOp::*;
-    for isr in &mut tbb.instrs {
-        match &isr.op {
-            Storew(fco, var) => {
-                let alloca_label = var.name;
-                incoming_nodes.insert(alloca_label, vec![(tbb.lb, fco.clone())]);
-            }
-            Assign(vty, var, rhs) if matches!(&rhs.op, Loadw(_)) => {
-                let alloca_label = rhs.getld_vn();
-                let phi_var_opt = alloca_newvar_hash.get(alloca_label);
-                if let Some(phi_var) = phi_var_opt {
-                    isr.op = Assign(
-                        *vty,
-                        var.clone(),
-                        Box::new(SsaInstr::new_all(
-                            SsaInstrOp::Src(FirstClassObj::Variable(phi_var.clone())),
-                            rhs.living,
-                            rhs.bblb,
-                        )),
-                    );
+    let cfg = func.cfg.as_deref().unwrap();
+    let m2rinfo = &func.m2rinfo;
+    let bbs = &mut func.bls;
+    let mut stacks: HashMap<Label<'a>, Vec<FirstClassObj<'a>>> = HashMap::new();
+    let top = |stacks: &HashMap<Label<'a>, Vec<FirstClassObj<'a>>>, alloca_label: Label<'a>| {
+        stacks
+            .get(alloca_label)
+            .and_then(|stack| stack.last())
+            .cloned()
+            .unwrap_or_else(|| undefvalue(m2rinfo.get(alloca_label).unwrap().regty()))
+    };
+    let mut steps = vec![RenameStep::Enter(0)];
+    while let Some(step) = steps.pop() {
+        let id = match step {
+            RenameStep::Enter(id) => id,
+            RenameStep::Leave(defs) => {
+                for alloca_label in defs {
+                    stacks.get_mut(alloca_label).unwrap().pop();
                 }
+                continue;
             }
-            Assign(vty, var, rhs) if matches!(&rhs.op, Phi(_, _)) => {
-                let alloca_label = rhs.getalloca_label();
-                alloca_newvar_hash.insert(alloca_label, var.clone());
-                let mut incoming_fcos = rhs.getincoming_fcos();
-                let add_incoming_fcos = incoming_nodes.get_into(alloca_label);
-                if let None = add_incoming_fcos {
-                    continue;
+        };
+        let mut defs = vec![];
+        // instructions removed by removeuselessinstr don't define the value
+        for isr in bbs[id].instrs.iter_mut().filter(|isr| isr.living) {
+            match &mut isr.op {
+                Assign(_, var, rhs) if matches!(&rhs.op, Phi(Some(_), _)) => {
+                    let alloca_label = rhs.getalloca_label();
+                    stacks
+                        .entry(alloca_label)
+                        .or_default()
+                        .push(FirstClassObj::Variable(var.clone()));
+                    defs.push(alloca_label);
                 }
-                'outer1: for (tbb_lb1, fco1) in add_incoming_fcos.unwrap() {
-                    for (tbb_lb2, _) in &incoming_fcos {
-                        if &tbb_lb1 == tbb_lb2 {
-                            continue 'outer1;
-                        }
+                Store(_, fco, var) if promoted(m2rinfo, var.name) => {
+                    stacks.entry(var.name).or_default().push(fco.clone());
+                    defs.push(var.name);
+                }
+                Assign(vty, _, rhs) if matches!(&rhs.op, Load(..)) => {
+                    let Load(ldop, ldvar) = &rhs.op else {
+                        unreachable!()
+                    };
+                    if !promoted(m2rinfo, ldvar.name) {
+                        continue;
                     }
-                    incoming_fcos.push((tbb_lb1, fco1));
+                    let sop = loadedvalue(*ldop, *vty, top(&stacks, ldvar.name));
+                    **rhs = SsaInstr::new_all(sop, rhs.living, rhs.bblb);
                 }
-                // new var assigend phi function
-                incoming_nodes.insert(
-                    alloca_label,
-                    vec![(tbb.lb, FirstClassObj::Variable(var.clone()))],
-                );
-                isr.op = Assign(
-                    *vty,
-                    var.clone(),
-                    Box::new(SsaInstr::new_all(
-                        Phi(Some(alloca_label), incoming_fcos),
-                        rhs.living,
-                        rhs.bblb,
-                    )),
-                );
+                _ => {}
             }
-            _ => {}
         }
-    }
-
-    if new_edge {
-        current_reached_nodes.clear();
-    }
-
-    let mut next_nodes = vec![];
-    'outer2: loop {
-        if cfg.as_ref().unwrap().graph[target_id].len() == 0 {
-            break;
-        }
-        next_nodes.clone_from(&cfg.as_ref().unwrap().graph[target_id]);
-        next_nodes.shuffle(&mut rand::thread_rng());
-        for next_id in &next_nodes {
-            if able_reach_nodes[target_id].is_subset(current_reached_nodes) {
-                break 'outer2;
-            }
-            if reached_edges.contains(&(target_id, *next_id)) {
-                new_edge = false;
-            } else {
-                reached_edges.insert((target_id, *next_id));
-                new_edge = true;
+        // incoming values of the phis in the successors
+        let lb = bbs[id].lb;
+        for &succ in &cfg.graph[id] {
+            for isr in &mut bbs[succ].instrs {
+                if let Assign(_, _, rhs) = &mut isr.op {
+                    if let Phi(Some(alloca_label), incoming_fcos) = &mut rhs.op {
+                        // jnz may jump to the same block twice
+                        if incoming_fcos.iter().any(|(inlb, _)| *inlb == lb) {
+                            continue;
+                        }
+                        incoming_fcos.push((lb, top(&stacks, alloca_label)));
+                    }
+                }
             }
-            current_reached_nodes.insert(*next_id);
-            walk_bb(
-                incoming_nodes.clone(),
-                alloca_newvar_hash.clone(),
-                cfg,
-                bbs,
-                able_reach_nodes,
-                current_reached_nodes,
-                reached_edges,
-                edge_nums,
-                *next_id,
-                new_edge,
-            );
+        }
+        steps.push(RenameStep::Leave(defs));
+        for &child in cfg.domtree[id].iter().rev() {
+            steps.push(RenameStep::Enter(child));
         }
     }
 }
@@ -1,50 +1,79 @@
+use super::diagnostic::Diagnostic;
 use super::dominators::ControlFlowGraph;
 use super::lexer::*;
 use super::mem2reg::*;
 use super::*;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::Mutex;
 
-static FRESHREGNUM: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
-static GFRSN: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(-1));
-static BBNUM: Lazy<Mutex<usize>> = Lazy::new(|| Mutex::new(0));
+extern crate elsa;
+use elsa::sync::FrozenMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-fn get_gfrsn() -> i32 {
-    let cgf = *GFRSN.lock().unwrap();
-    *GFRSN.lock().unwrap() = cgf - 1;
-    cgf
-}
+// source of the fresh virtual register numbers in the module
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FreshRegs(i32);
 
-fn get_bbnum_n() -> usize {
-    let bbn = *BBNUM.lock().unwrap();
-    *BBNUM.lock().unwrap() = bbn + 1;
-    bbn
+impl FreshRegs {
+    pub fn nextfreshregister(&mut self) -> i32 {
+        let res = self.0;
+        self.0 += 1;
+        res
+    }
 }
 
-fn cur_bbnum() -> usize {
-    let bbn = *BBNUM.lock().unwrap();
-    if bbn < 1 {
-        panic!("BBNUM should be more than 0");
-    }
-    bbn - 1
+// storage of the names made by the passes (e.g. the phis of mem2reg). the pool outlives the
+// program, so the names are borrowed from it as those of the source text.
+#[derive(Default)]
+pub struct NamePool {
+    // the names in the program, which the new names must avoid. the names are never removed,
+    // so the borrowed names live as long as the pool.
+    names: FrozenMap<String, Box<str>>,
+    // suffix of the next fresh name
+    cnt: AtomicUsize,
 }
 
-fn reset_bbnum() {
-    *BBNUM.lock().unwrap() = 0;
+impl NamePool {
+    // name already used in the program
+    pub fn reserve(&self, name: &str) {
+        if self.names.get(name).is_none() {
+            self.names.insert(name.to_string(), name.into());
+        }
+    }
+    // new name `base.n`
+    pub fn fresh(&self, base: &str) -> &str {
+        loop {
+            let n = self.cnt.fetch_add(1, Ordering::Relaxed);
+            let name = format!("{}.{}", base, n);
+            if self.names.get(name.as_str()).is_none() {
+                return self.names.insert(name.clone(), name.into_boxed_str());
+            }
+        }
+    }
+    // the name itself if it is not used yet, otherwise the fresh name based on it
+    pub fn unique(&self, name: String) -> &str {
+        if self.names.get(name.as_str()).is_some() {
+            self.fresh(&name)
+        } else {
+            self.names.insert(name.clone(), name.into_boxed_str())
+        }
+    }
 }
 
-pub fn nextfreshregister() -> i32 {
-    let res = *FRESHREGNUM.lock().unwrap();
-    *FRESHREGNUM.lock().unwrap() += 1;
-    res
+impl fmt::Debug for NamePool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NamePool({})", self.names.len())
+    }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ValueType {
     Word,
     Long,
+    Half,
     Byte,
+    Single,
+    Double,
     Z,
 }
 
@@ -52,8 +81,9 @@ impl ValueType {
     pub fn bytesize(self) -> i32 {
         use ValueType::*;
         match self {
-            Word => 4,
-            Long => 8,
+            Word | Single => 4,
+            Long | Double => 8,
+            Half => 2,
             Byte => 1,
             Z => -1,
         }
@@ -61,43 +91,107 @@ impl ValueType {
     pub fn bitsize(self) -> i32 {
         use ValueType::*;
         match self {
-            Word => 32,
-            Long => 64,
+            Word | Single => 32,
+            Long | Double => 64,
+            Half => 16,
             Byte => 8,
             Z => -1,
         }
     }
-    pub fn tovarty(&self) -> VarType {
+    pub fn isfloat(self) -> bool {
+        matches!(self, ValueType::Single | ValueType::Double)
+    }
+    pub fn tovarty<'a>(&self) -> VarType<'a> {
         use ValueType::*;
         match self {
             Word => VarType::Word,
             Long => VarType::Long,
+            Half => VarType::Half,
             Byte => VarType::Byte,
+            Single => VarType::Single,
+            Double => VarType::Double,
             Z => {
                 panic!("tovarty error: {:?}", self);
             }
         }
     }
+    // type suffix in the textual IR
+    pub fn name(self) -> &'static str {
+        use ValueType::*;
+        match self {
+            Word => "w",
+            Long => "l",
+            Half => "h",
+            Byte => "b",
+            Single => "s",
+            Double => "d",
+            Z => "z",
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub enum VarType {
+pub enum VarType<'a> {
     Word,
     Long,
+    Half,
     Byte,
+    Single,
+    Double,
     Ptr2Word,
     Ptr2Long,
-    TypeTuple(Vec<VarType>),
+    TypeTuple(Vec<VarType<'a>>),
     Void,
-    ConT(Vec<(VarType, u32)>),
+    ConT(Vec<(VarType<'a>, u32)>),
+    Aggre(AggType<'a>),
 }
 
-impl VarType {
+// aggregate type defined by `type :name = { ... }`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggType<'a> {
+    pub name: &'a str,
+    pub align: i32,
+    pub size: i32,
+    // offset and type of every scalar member
+    pub fields: Vec<(i32, VarType<'a>)>,
+}
+
+impl<'a> AggType<'a> {
+    pub fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            align: 1,
+            size: 0,
+            fields: vec![],
+        }
+    }
+    // append cnt members of ty
+    fn pushmember(&mut self, ty: &VarType<'a>, cnt: i32) {
+        let (size, align, members) = match ty {
+            VarType::Aggre(agg) => (agg.size, agg.align, agg.fields.clone()),
+            _ => (ty.stacksize(), ty.stacksize(), vec![(0, ty.clone())]),
+        };
+        self.align = self.align.max(align);
+        for _ in 0..cnt {
+            self.size = (self.size + align - 1) / align * align;
+            for (offset, mty) in &members {
+                self.fields.push((self.size + offset, mty.clone()));
+            }
+            self.size += size;
+        }
+    }
+    fn finish(&mut self) {
+        self.size = (self.size + self.align - 1) / self.align * self.align;
+    }
+}
+
+impl<'a> VarType<'a> {
     pub fn stacksize(&self) -> i32 {
         use VarType::*;
         match self {
-            Word | Ptr2Word => 4,
-            Long | Ptr2Long => 8,
+            Word | Single | Ptr2Word => 4,
+            Long | Double | Ptr2Long => 8,
+            Half => 2,
             Byte => 1,
             TypeTuple(vvt) => {
                 let mut size = 0;
@@ -114,13 +208,16 @@ impl VarType {
                 }
                 size
             }
+            Aggre(agg) => agg.size,
         }
     }
     pub fn toregrefsize(&self) -> i32 {
         use VarType::*;
         match self {
-            Word => 4,
-            Long | Ptr2Long | Ptr2Word => 8,
+            Word | Single => 4,
+            // aggregate is referred by its address
+            Long | Double | Ptr2Long | Ptr2Word | Aggre(_) => 8,
+            Half => 2,
             Byte => 1,
             Void => 0,
             TypeTuple(_) => {
@@ -135,42 +232,97 @@ impl VarType {
             }
         }
     }
+    pub fn isfloat(&self) -> bool {
+        matches!(self, VarType::Single | VarType::Double)
+    }
+    pub fn tovalty(&self) -> ValueType {
+        use VarType::*;
+        match self {
+            Word => ValueType::Word,
+            Long => ValueType::Long,
+            Half => ValueType::Half,
+            Byte => ValueType::Byte,
+            Single => ValueType::Single,
+            Double => ValueType::Double,
+            Aggre(_) => ValueType::Long,
+            _ => {
+                panic!("tovalty error: {:?}", self);
+            }
+        }
+    }
+    // value which can be used as the address of load and store
+    pub fn isaddress(&self) -> bool {
+        use VarType::*;
+        matches!(self, Long | Ptr2Long | Ptr2Word | Aggre(_))
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-struct Arg {
-    vty: VarType,
-    name: VarName,
+#[derive(Debug)]
+pub struct SsaProgram<'a> {
+    pub funcs: Vec<SsaFunction<'a>>,
+    pub gvs: Vec<Gdata<'a>>,
+    pub regs: FreshRegs,
+    pub names: &'a NamePool,
 }
 
-#[derive(Debug)]
-pub struct SsaProgram {
-    pub funcs: Vec<SsaFunction>,
-    pub gvs: Vec<Gdata>,
+// the programs are the same regardless of the pools of their names
+impl<'a> PartialEq for SsaProgram<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.funcs == other.funcs && self.gvs == other.gvs && self.regs == other.regs
+    }
 }
 
-impl SsaProgram {
-    pub fn new(funcs: Vec<SsaFunction>, gvs: Vec<Gdata>) -> Self {
-        Self { funcs, gvs }
+impl<'a> SsaProgram<'a> {
+    pub fn new(funcs: Vec<SsaFunction<'a>>, gvs: Vec<Gdata<'a>>, names: &'a NamePool) -> Self {
+        Self {
+            funcs,
+            gvs,
+            regs: FreshRegs::default(),
+            names,
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Gdata {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gdata<'a> {
     pub frsn: i32,
     pub al: i32,
-    pub lb: &'static str,
-    pub dts: Vec<FirstClassObj>,
-    pub types: VarType,
+    pub lb: &'a str,
+    pub dts: Vec<DataItem<'a>>,
+    pub types: VarType<'a>,
+    pub linkage: Linkage<'a>,
 }
 
-impl Gdata {
+// linkage of functions and global data
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Linkage<'a> {
+    // visible from other object files
+    pub export: bool,
+    // thread local storage
+    pub thread: bool,
+    // section name and flags
+    pub section: Option<(&'a str, Option<&'a str>)>,
+}
+
+// element of global data
+#[derive(Clone, Debug, PartialEq)]
+pub enum DataItem<'a> {
+    Num(VarType<'a>, i64),
+    Float(VarType<'a>, f64),
+    String(&'a str),
+    // address of the symbol + offset
+    Symbol(Label<'a>, i32),
+    // zero fill of the size
+    Zero(i32),
+}
+
+impl<'a> Gdata<'a> {
     pub fn new(
         frsn: i32,
         al: i32,
-        lb: &'static str,
-        dts: Vec<FirstClassObj>,
-        types: VarType,
+        lb: &'a str,
+        dts: Vec<DataItem<'a>>,
+        types: VarType<'a>,
     ) -> Self {
         Self {
             frsn,
@@ -178,26 +330,31 @@ impl Gdata {
             lb,
             dts,
             types,
+            linkage: Linkage::default(),
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct SsaFunction {
-    pub name: &'static str,
-    pub retty: VarType,
-    pub args: Vec<Var>,
-    pub bls: Vec<SsaBlock>,
+pub struct SsaFunction<'a> {
+    pub name: &'a str,
+    pub linkage: Linkage<'a>,
+    pub retty: VarType<'a>,
+    pub args: Vec<Var<'a>>,
+    pub variadic: bool,
+    pub bls: Vec<SsaBlock<'a>>,
     pub cfg: Option<Box<ControlFlowGraph>>,
-    pub m2rinfo: HashMap<&'static str, MemToregAlloca>,
+    pub m2rinfo: HashMap<&'a str, MemToregAlloca<'a>>,
 }
 
-impl SsaFunction {
-    pub fn new(name: &'static str, retty: VarType) -> Self {
+impl<'a> SsaFunction<'a> {
+    pub fn new(name: &'a str, retty: VarType<'a>) -> Self {
         Self {
             name,
+            linkage: Linkage::default(),
             retty,
             args: vec![],
+            variadic: false,
             bls: vec![],
             cfg: None,
             m2rinfo: HashMap::new(),
@@ -206,84 +363,90 @@ impl SsaFunction {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Var {
-    pub name: &'static str,
-    pub ty: VarType,
+pub struct Var<'a> {
+    pub name: &'a str,
+    pub ty: VarType<'a>,
     pub rg_vr: i32,
-    pub global: Option<Label>,
+    pub global: Option<Label<'a>>,
+    // global data in thread local storage
+    pub thread: bool,
 }
 
-impl Var {
-    pub fn new(name: &'static str, ty: VarType, rg_vr: i32) -> Self {
+impl<'a> Var<'a> {
+    pub fn new(name: &'a str, ty: VarType<'a>, rg_vr: i32) -> Self {
         Self {
             name,
             ty,
             rg_vr,
             global: None,
+            thread: false,
         }
     }
-    pub fn new_all(name: &'static str, ty: VarType, rg_vr: i32, global: Option<Label>) -> Self {
+    pub fn new_all(name: &'a str, ty: VarType<'a>, rg_vr: i32, global: Option<Label<'a>>) -> Self {
         Self {
             name,
             ty,
             rg_vr,
             global,
+            thread: false,
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct SsaBlock {
-    pub lb: Label,
+pub struct SsaBlock<'a> {
+    pub lb: Label<'a>,
     pub id: usize,
-    pub instrs: Vec<SsaInstr>,
-    pub transbbs: Vec<&'static str>,
+    pub instrs: Vec<SsaInstr<'a>>,
+    pub transbbs: Vec<&'a str>,
     pub idom: usize,
     pub domfros: Vec<usize>,
 }
 
-impl SsaBlock {
-    pub fn new(lb: Label, id: usize, instrs: Vec<SsaInstr>) -> Self {
+impl<'a> SsaBlock<'a> {
+    pub fn new(lb: Label<'a>, id: usize, instrs: Vec<SsaInstr<'a>>) -> Self {
         Self {
             lb,
             id,
             instrs,
             transbbs: vec![],
-            idom: std::usize::MAX,
+            idom: usize::MAX,
             domfros: vec![],
         }
     }
 }
 
-#[derive(Clone, Debug, Eq)]
-pub enum FirstClassObj {
-    Variable(Var),
-    Num(VarType, i32),
-    String(&'static str),
+#[derive(Clone, Debug)]
+pub enum FirstClassObj<'a> {
+    Variable(Var<'a>),
+    Num(VarType<'a>, i64),
+    Float(VarType<'a>, f64),
+    String(&'a str),
 }
 
-impl PartialEq for FirstClassObj {
+impl<'a> PartialEq for FirstClassObj<'a> {
     fn eq(&self, other: &Self) -> bool {
         use FirstClassObj::*;
         match (self, other) {
-            (Variable(var1), Variable(var2)) => {
-                if var1.name == var2.name {
-                    true
-                } else {
-                    false
-                }
-            }
+            (Variable(var1), Variable(var2)) => var1.name == var2.name,
             (Num(_, num1), Num(_, num2)) => num1 == num2,
+            (Float(_, num1), Float(_, num2)) => num1 == num2,
             (String(string1), String(string2)) => string1 == string2,
             _ => false,
         }
     }
 }
 
-impl FirstClassObj {
-    pub fn get_varlb(&self) -> Option<&'static str> {
+impl<'a> From<Var<'a>> for FirstClassObj<'a> {
+    fn from(var: Var<'a>) -> Self {
+        FirstClassObj::Variable(var)
+    }
+}
+
+impl<'a> FirstClassObj<'a> {
+    pub fn get_varvr(&self) -> Option<i32> {
         if let Self::Variable(var) = self {
-            Some(var.name)
+            Some(var.rg_vr)
         } else {
             None
         }
@@ -292,30 +455,271 @@ impl FirstClassObj {
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CompOp {
-    Ceqw,
-    Csltw,
+    Ceq,
+    Cne,
+    Csle,
+    Cslt,
+    Csge,
+    Csgt,
+    Cule,
+    Cult,
+    Cuge,
+    Cugt,
+    Cle,
+    Clt,
+    Cge,
+    Cgt,
+    Co,
+    Cuo,
+}
+
+impl CompOp {
+    // instruction name in the textual IR (without the operand type suffix)
+    pub fn name(self) -> &'static str {
+        use CompOp::*;
+        match self {
+            Ceq => "ceq",
+            Cne => "cne",
+            Csle => "csle",
+            Cslt => "cslt",
+            Csge => "csge",
+            Csgt => "csgt",
+            Cule => "cule",
+            Cult => "cult",
+            Cuge => "cuge",
+            Cugt => "cugt",
+            Cle => "cle",
+            Clt => "clt",
+            Cge => "cge",
+            Cgt => "cgt",
+            Co => "co",
+            Cuo => "cuo",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadOp {
+    Loadl,
+    Loadw,
+    Loadsw,
+    Loaduw,
+    Loadsh,
+    Loaduh,
+    Loadsb,
+    Loadub,
+    Loads,
+    Loadd,
+}
+
+impl LoadOp {
+    // instruction name in the textual IR
+    pub fn name(self) -> &'static str {
+        use LoadOp::*;
+        match self {
+            Loadl => "loadl",
+            Loadw => "loadw",
+            Loadsw => "loadsw",
+            Loaduw => "loaduw",
+            Loadsh => "loadsh",
+            Loaduh => "loaduh",
+            Loadsb => "loadsb",
+            Loadub => "loadub",
+            Loads => "loads",
+            Loadd => "loadd",
+        }
+    }
+    // type of the value in memory
+    pub fn memty(self) -> ValueType {
+        use LoadOp::*;
+        match self {
+            Loadl => ValueType::Long,
+            Loadw | Loadsw | Loaduw => ValueType::Word,
+            Loadsh | Loaduh => ValueType::Half,
+            Loadsb | Loadub => ValueType::Byte,
+            Loads => ValueType::Single,
+            Loadd => ValueType::Double,
+        }
+    }
+    // whether the loaded value is sign extended
+    pub fn signed(self) -> bool {
+        use LoadOp::*;
+        matches!(self, Loadl | Loadw | Loadsw | Loadsh | Loadsb)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExtOp {
+    Extsw,
+    Extuw,
+    Extsh,
+    Extuh,
+    Extsb,
+    Extub,
+}
+
+impl ExtOp {
+    // instruction name in the textual IR
+    pub fn name(self) -> &'static str {
+        use ExtOp::*;
+        match self {
+            Extsw => "extsw",
+            Extuw => "extuw",
+            Extsh => "extsh",
+            Extuh => "extuh",
+            Extsb => "extsb",
+            Extub => "extub",
+        }
+    }
+    // type of the operand to be extended
+    pub fn srcty(self) -> ValueType {
+        use ExtOp::*;
+        match self {
+            Extsw | Extuw => ValueType::Word,
+            Extsh | Extuh => ValueType::Half,
+            Extsb | Extub => ValueType::Byte,
+        }
+    }
+    // whether the operand is sign extended
+    pub fn signed(self) -> bool {
+        use ExtOp::*;
+        matches!(self, Extsw | Extsh | Extsb)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CvtOp {
+    Exts,
+    Truncd,
+    Stosi,
+    Stoui,
+    Dtosi,
+    Dtoui,
+    Swtof,
+    Uwtof,
+    Sltof,
+    Ultof,
+    Cast,
+}
+
+impl CvtOp {
+    // instruction name in the textual IR
+    pub fn name(self) -> &'static str {
+        use CvtOp::*;
+        match self {
+            Exts => "exts",
+            Truncd => "truncd",
+            Stosi => "stosi",
+            Stoui => "stoui",
+            Dtosi => "dtosi",
+            Dtoui => "dtoui",
+            Swtof => "swtof",
+            Uwtof => "uwtof",
+            Sltof => "sltof",
+            Ultof => "ultof",
+            Cast => "cast",
+        }
+    }
+    // type of the operand converted to dstty
+    pub fn srcty(self, dstty: ValueType) -> ValueType {
+        use CvtOp::*;
+        match self {
+            Exts | Stosi | Stoui => ValueType::Single,
+            Truncd | Dtosi | Dtoui => ValueType::Double,
+            Swtof | Uwtof => ValueType::Word,
+            Sltof | Ultof => ValueType::Long,
+            Cast => match dstty {
+                ValueType::Word => ValueType::Single,
+                ValueType::Long => ValueType::Double,
+                ValueType::Single => ValueType::Word,
+                ValueType::Double => ValueType::Long,
+                _ => panic!("cast error: {:?}", dstty),
+            },
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum SsaInstrOp {
-    Ret(FirstClassObj),
-    Assign(ValueType, Var, Box<SsaInstr>),
-    Alloc4(Var, i32),
-    Storew(FirstClassObj, Var),
-    Loadw(Var),
-    Bop(Binop, FirstClassObj, FirstClassObj),
-    Call(VarType, Label, Vec<FirstClassObj>, bool),
-    Comp(CompOp, Var, Var, FirstClassObj),
-    Jnz(Var, Label, Label),
-    Jmp(Label),
-    Phi(Option<&'static str>, Vec<(Label, FirstClassObj)>),
-    Src(FirstClassObj),
+pub enum SsaInstrOp<'a> {
+    Ret(FirstClassObj<'a>),
+    Assign(ValueType, Var<'a>, Box<SsaInstr<'a>>),
+    Alloc(Var<'a>, i32, i32),
+    Store(ValueType, FirstClassObj<'a>, Var<'a>),
+    Load(LoadOp, Var<'a>),
+    Ext(ExtOp, FirstClassObj<'a>),
+    Cvt(CvtOp, ValueType, FirstClassObj<'a>),
+    Bop(Binop, FirstClassObj<'a>, FirstClassObj<'a>),
+    Call(VarType<'a>, Label<'a>, Vec<FirstClassObj<'a>>, bool),
+    Vastart(Var<'a>),
+    Vaarg(ValueType, Var<'a>),
+    Comp(
+        CompOp,
+        ValueType,
+        Var<'a>,
+        FirstClassObj<'a>,
+        FirstClassObj<'a>,
+    ),
+    Jnz(Var<'a>, Label<'a>, Label<'a>),
+    Jmp(Label<'a>),
+    Phi(Option<&'a str>, Vec<(Label<'a>, FirstClassObj<'a>)>),
+    Src(FirstClassObj<'a>),
     Nop,
     DummyOp,
 }
 
-impl SsaInstrOp {
-    pub fn get_phi_vec(&self) -> Option<Vec<(Label, FirstClassObj)>> {
+// how the variable is used as the operand of the instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandUse<'a> {
+    Value,
+    // address of load and store
+    Address,
+    // value of phi coming from the block
+    Incoming(Label<'a>),
+}
+
+impl<'a> SsaInstrOp<'a> {
+    // calls f for each variable used by the instruction (and by the rhs of assign)
+    pub fn visitoperands<'b, F>(&'b self, f: &mut F)
+    where
+        F: FnMut(&'b Var<'a>, OperandUse<'a>),
+    {
+        use OperandUse::*;
+        let value = |fco: &'b FirstClassObj<'a>, f: &mut F| {
+            if let FirstClassObj::Variable(var) = fco {
+                f(var, Value);
+            }
+        };
+        match self {
+            SsaInstrOp::Ret(fco)
+            | SsaInstrOp::Src(fco)
+            | SsaInstrOp::Ext(_, fco)
+            | SsaInstrOp::Cvt(_, _, fco) => value(fco, f),
+            SsaInstrOp::Assign(_, _, rhs) => rhs.op.visitoperands(f),
+            SsaInstrOp::Store(_, fco, var) => {
+                value(fco, f);
+                f(var, Address);
+            }
+            SsaInstrOp::Load(_, var) => f(var, Address),
+            SsaInstrOp::Vastart(var) | SsaInstrOp::Vaarg(_, var) | SsaInstrOp::Jnz(var, ..) => {
+                f(var, Value)
+            }
+            SsaInstrOp::Bop(_, fco1, fco2) | SsaInstrOp::Comp(_, _, _, fco1, fco2) => {
+                value(fco1, f);
+                value(fco2, f);
+            }
+            SsaInstrOp::Call(_, _, args, _) => args.iter().for_each(|fco| value(fco, f)),
+            SsaInstrOp::Phi(_, pv) => {
+                for (lb, fco) in pv {
+                    if let FirstClassObj::Variable(var) = fco {
+                        f(var, Incoming(lb));
+                    }
+                }
+            }
+            SsaInstrOp::Alloc(..) | SsaInstrOp::Jmp(..) | SsaInstrOp::Nop | SsaInstrOp::DummyOp => {
+            }
+        }
+    }
+    pub fn get_phi_vec(&self) -> Option<Vec<(Label<'a>, FirstClassObj<'a>)>> {
         if let SsaInstrOp::Phi(_, phi_vecs) = &self {
             Some(phi_vecs.clone())
         } else {
@@ -325,38 +729,38 @@ impl SsaInstrOp {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct SsaInstr {
-    pub op: SsaInstrOp,
+pub struct SsaInstr<'a> {
+    pub op: SsaInstrOp<'a>,
     pub living: bool,
-    pub bblb: Label,
+    pub bblb: Label<'a>,
 }
 
-impl SsaInstr {
-    pub fn new(op: SsaInstrOp) -> Self {
+impl<'a> SsaInstr<'a> {
+    pub fn new(op: SsaInstrOp<'a>) -> Self {
         Self {
             op,
             living: false,
             bblb: "",
         }
     }
-    pub fn new_all(op: SsaInstrOp, living: bool, bblb: Label) -> Self {
+    pub fn new_all(op: SsaInstrOp<'a>, living: bool, bblb: Label<'a>) -> Self {
         Self { op, living, bblb }
     }
-    pub fn getld_vn(&self) -> &'static str {
-        if let SsaInstrOp::Loadw(var) = &self.op {
+    pub fn getld_vn(&self) -> &'a str {
+        if let SsaInstrOp::Load(_, var) = &self.op {
             var.name
         } else {
             panic!("getld_vn error: {:?}", self);
         }
     }
-    pub fn getalloca_label(&self) -> Label {
+    pub fn getalloca_label(&self) -> Label<'a> {
         if let SsaInstrOp::Phi(alloca_label, _) = &self.op {
-            alloca_label.clone().unwrap()
+            (*alloca_label).unwrap()
         } else {
             panic!("getalloca_label error");
         }
     }
-    pub fn getincoming_fcos(&self) -> Vec<(Label, FirstClassObj)> {
+    pub fn getincoming_fcos(&self) -> Vec<(Label<'a>, FirstClassObj<'a>)> {
         if let SsaInstrOp::Phi(_, incoming_fcos) = &self.op {
             incoming_fcos.clone()
         } else {
@@ -365,237 +769,694 @@ impl SsaInstr {
     }
 }
 
+// textual IR which can be parsed again
+
+impl<'a> fmt::Display for VarType<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use VarType::*;
+        match self {
+            Word => write!(f, "w"),
+            // address of the stack slot
+            Long | Ptr2Word | Ptr2Long => write!(f, "l"),
+            Half => write!(f, "h"),
+            Byte => write!(f, "b"),
+            Single => write!(f, "s"),
+            Double => write!(f, "d"),
+            Aggre(agg) => write!(f, ":{}", agg.name),
+            Void => Ok(()),
+            TypeTuple(_) | ConT(_) => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl<'a> fmt::Display for AggType<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // members are flattened, so the gaps are filled by the opaque bytes
+        let mut members = vec![];
+        let mut size = 0;
+        for (offset, ty) in &self.fields {
+            if *offset > size {
+                members.push((offset - size).to_string());
+            }
+            members.push(ty.to_string());
+            size = offset + ty.stacksize();
+        }
+        if self.size > size {
+            members.push((self.size - size).to_string());
+        }
+        write!(
+            f,
+            "type :{} = align {} {{ {} }}",
+            self.name,
+            self.align,
+            members.join(", ")
+        )
+    }
+}
+
+impl<'a> fmt::Display for Linkage<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.export {
+            write!(f, "export ")?;
+        }
+        if self.thread {
+            write!(f, "thread ")?;
+        }
+        if let Some((name, flags)) = self.section {
+            write!(f, "section \"{}\" ", name)?;
+            if let Some(flags) = flags {
+                write!(f, "\"{}\" ", flags)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Gdata<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}data ${} = align {} {{",
+            self.linkage, self.lb, self.al
+        )?;
+        let groups = match &self.types {
+            VarType::ConT(groups) => groups.clone(),
+            _ => vec![],
+        };
+        let mut items = self.dts.iter();
+        for (i, (ty, cnt)) in groups.iter().enumerate() {
+            write!(f, "{}", if i == 0 { " " } else { ", " })?;
+            if let Some(DataItem::Zero(size)) = self.dts.get(self.dts.len() - items.len()) {
+                items.next();
+                write!(f, "z {}", size)?;
+                continue;
+            }
+            write!(f, "{}", ty)?;
+            for item in items.by_ref().take(*cnt as usize) {
+                match item {
+                    DataItem::Num(_, num) => write!(f, " {}", num)?,
+                    DataItem::Float(VarType::Single, num) => write!(f, " s_{}", num)?,
+                    DataItem::Float(_, num) => write!(f, " d_{}", num)?,
+                    DataItem::String(string) => write!(f, " \"{}\"", string)?,
+                    DataItem::Symbol(sym, 0) => write!(f, " ${}", sym)?,
+                    DataItem::Symbol(sym, offset) => write!(f, " ${} + {}", sym, offset)?,
+                    DataItem::Zero(size) => write!(f, " z {}", size)?,
+                }
+            }
+        }
+        write!(f, " }}")
+    }
+}
+
+impl<'a> fmt::Display for Var<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.global {
+            Some(lb) => write!(f, "${}", lb),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl<'a> fmt::Display for FirstClassObj<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use FirstClassObj::*;
+        match self {
+            Variable(var) => write!(f, "{}", var),
+            Num(_, num) => write!(f, "{}", num),
+            Float(VarType::Single, num) => write!(f, "s_{}", num),
+            Float(_, num) => write!(f, "d_{}", num),
+            String(string) => write!(f, "\"{}\"", string),
+        }
+    }
+}
+
+// type of the argument passed to the function
+fn fcoabity<'a>(fco: &FirstClassObj<'a>) -> VarType<'a> {
+    match fco {
+        FirstClassObj::Variable(var) => var.ty.clone(),
+        FirstClassObj::Num(ty, _) | FirstClassObj::Float(ty, _) => ty.clone(),
+        FirstClassObj::String(_) => VarType::Long,
+    }
+}
+
+impl<'a> fmt::Display for SsaInstrOp<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SsaInstrOp::*;
+        match self {
+            Ret(fco) => write!(f, "ret {}", fco),
+            Assign(valty, var, rhs) => match &var.ty {
+                VarType::Aggre(_) => write!(f, "{} ={} {}", var, var.ty, rhs),
+                _ => write!(f, "{} ={} {}", var, valty.name(), rhs),
+            },
+            Alloc(var, align, size) => write!(f, "{} =l alloc{} {}", var, align, size),
+            Store(valty, fco, var) => write!(f, "store{} {}, {}", valty.name(), fco, var),
+            Load(ldop, var) => write!(f, "{} {}", ldop.name(), var),
+            Ext(extop, fco) => write!(f, "{} {}", extop.name(), fco),
+            Cvt(cvtop, _, fco) => write!(f, "{} {}", cvtop.name(), fco),
+            Bop(binop, lhs, rhs) => write!(f, "{} {}, {}", binop.name(), lhs, rhs),
+            Call(_, funlb, args, variadic) => {
+                let mut args = args
+                    .iter()
+                    .map(|arg| format!("{} {}", fcoabity(arg), arg))
+                    .collect::<Vec<_>>();
+                if *variadic {
+                    args.push("...".to_string());
+                }
+                write!(f, "call ${}({})", funlb, args.join(", "))
+            }
+            Vastart(var) => write!(f, "vastart {}", var),
+            Vaarg(_, var) => write!(f, "vaarg {}", var),
+            Comp(cop, valty, var, lhs, rhs) => write!(
+                f,
+                "{} ={} {}{} {}, {}",
+                var,
+                var.ty,
+                cop.name(),
+                valty.name(),
+                lhs,
+                rhs
+            ),
+            Jnz(var, blb1, blb2) => write!(f, "jnz {}, @{}, @{}", var, blb1, blb2),
+            Jmp(blb) => write!(f, "jmp @{}", blb),
+            Phi(_, pv) => {
+                let args = pv
+                    .iter()
+                    .map(|(lb, fco)| format!("@{} {}", lb, fco))
+                    .collect::<Vec<_>>();
+                write!(f, "phi {}", args.join(", "))
+            }
+            Src(fco) => write!(f, "copy {}", fco),
+            // removed by the passes
+            Nop | DummyOp => write!(f, "# {:?}", self),
+        }
+    }
+}
+
+impl<'a> fmt::Display for SsaInstr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.op)
+    }
+}
+
+impl<'a> fmt::Display for SsaBlock<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "@{}:", self.lb)?;
+        for instr in &self.instrs {
+            writeln!(f, "\t{}", instr)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for SsaFunction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}function ", self.linkage)?;
+        if self.retty != VarType::Void {
+            write!(f, "{} ", self.retty)?;
+        }
+        let mut args = self
+            .args
+            .iter()
+            .map(|arg| format!("{} {}", arg.ty, arg.name))
+            .collect::<Vec<_>>();
+        if self.variadic {
+            args.push("...".to_string());
+        }
+        writeln!(f, "${}({}) {{", self.name, args.join(", "))?;
+        for bb in &self.bls {
+            write!(f, "{}", bb)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl<'a> SsaProgram<'a> {
+    // aggregate types used in the program
+    pub fn aggtypes(&self) -> Vec<AggType<'a>> {
+        let mut aggs: Vec<AggType<'a>> = vec![];
+        let mut push = |ty: &VarType<'a>| {
+            if let VarType::Aggre(agg) = ty {
+                if aggs.iter().all(|a| a.name != agg.name) {
+                    aggs.push(agg.clone());
+                }
+            }
+        };
+        for func in &self.funcs {
+            push(&func.retty);
+            for arg in &func.args {
+                push(&arg.ty);
+            }
+            for bb in &func.bls {
+                for instr in &bb.instrs {
+                    let (var, rhs) = match &instr.op {
+                        SsaInstrOp::Assign(_, var, rhs) => (Some(var), &rhs.op),
+                        op => (None, op),
+                    };
+                    if let Some(var) = var {
+                        push(&var.ty);
+                    }
+                    if let SsaInstrOp::Call(retty, _, args, _) = rhs {
+                        push(retty);
+                        for arg in args {
+                            if let FirstClassObj::Variable(var) = arg {
+                                push(&var.ty);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        aggs
+    }
+}
+
+impl<'a> fmt::Display for SsaProgram<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // types and data are defined before they are referred
+        for agg in self.aggtypes() {
+            writeln!(f, "{}", agg)?;
+        }
+        for gv in &self.gvs {
+            writeln!(f, "{}", gv)?;
+        }
+        for func in &self.funcs {
+            writeln!(f)?;
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct Env {
-    fns: HashMap<&'static str, VarType>,
-    lvs: HashMap<&'static str, Var>,
-    gvs: HashMap<&'static str, Gdata>,
+pub struct Env<'a> {
+    fns: HashMap<&'a str, VarType<'a>>,
+    lvs: HashMap<&'a str, Var<'a>>,
+    gvs: HashMap<&'a str, Gdata<'a>>,
+    tys: HashMap<&'a str, AggType<'a>>,
+    pub regs: FreshRegs,
+    // numbering of the global data
+    gfrsn: i32,
+    // numbering of the basic blocks in the current function
+    bbnum: usize,
+    // token of the variables referred by phi before the definition
+    phirefs: HashMap<&'a str, usize>,
+    // blocks of the current function and the blocks referred by jmp, jnz and phi with their
+    // tokens
+    bblbs: HashSet<&'a str>,
+    bbrefs: Vec<(&'a str, usize)>,
+    // functions and data, which share the symbols of the assembly
+    syms: HashSet<&'a str>,
+    // errors found so far (the parser resumes after each error)
+    pub errors: Vec<Diagnostic>,
 }
 
-impl Env {
+impl<'a> Default for Env<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Env<'a> {
     pub fn new() -> Self {
         Self {
             fns: HashMap::new(),
             lvs: HashMap::new(),
             gvs: HashMap::new(),
+            tys: HashMap::new(),
+            regs: FreshRegs::default(),
+            gfrsn: -1,
+            bbnum: 0,
+            phirefs: HashMap::new(),
+            bblbs: HashSet::new(),
+            bbrefs: vec![],
+            syms: HashSet::new(),
+            errors: vec![],
+        }
+    }
+    fn get_gfrsn(&mut self) -> i32 {
+        let cgf = self.gfrsn;
+        self.gfrsn -= 1;
+        cgf
+    }
+    fn get_bbnum_n(&mut self) -> usize {
+        let bbn = self.bbnum;
+        self.bbnum += 1;
+        bbn
+    }
+    fn cur_bbnum(&self) -> usize {
+        if self.bbnum < 1 {
+            panic!("bbnum should be more than 0");
         }
+        self.bbnum - 1
+    }
+    pub fn g_tys(&self, key: &'a str) -> Option<VarType<'a>> {
+        self.tys.get(key).map(|agg| VarType::Aggre(agg.clone()))
     }
-    pub fn g_fns(&self, key: &'static str) -> VarType {
+    pub fn g_fns(&self, key: &'a str) -> VarType<'a> {
         if let Some(v) = self.fns.get(key) {
-            return v.clone();
+            v.clone()
         } else {
-            return VarType::Void;
+            VarType::Void
         }
     }
-    pub fn g_lvs(&self, key: &'static str) -> Var {
+    pub fn g_lvs(&mut self, key: &'a str) -> Option<Var<'a>> {
         if let Some(v) = self.lvs.get(key) {
-            return v.clone();
+            Some(v.clone())
         } else {
             self.g_gvs(key)
         }
     }
-    pub fn g_gvs(&self, key: &'static str) -> Var {
+    pub fn g_gvs(&mut self, key: &'a str) -> Option<Var<'a>> {
         if let Some(v) = self.gvs.get(key) {
-            return Var::new_all(v.lb, v.types.clone(), nextfreshregister(), Some(v.lb));
+            // global data is referred by its address
+            let (lb, thread) = (v.lb, v.linkage.thread);
+            let mut var = Var::new_all(lb, VarType::Long, self.regs.nextfreshregister(), Some(lb));
+            var.thread = thread;
+            Some(var)
+        } else if let Some((&lb, _)) = self.fns.get_key_value(key) {
+            // address of the function
+            Some(Var::new_all(
+                lb,
+                VarType::Long,
+                self.regs.nextfreshregister(),
+                Some(lb),
+            ))
         } else {
-            panic!("{} is not in Env.\nEnv: {:?}", key, self);
+            None
+        }
+    }
+    // the name of the function or the data at the id-th token
+    fn definesym(&mut self, tms: &TokenMass<'a>, id: usize, name: &'a str) {
+        if !self.syms.insert(name) {
+            self.errors
+                .push(tms.error_at(id, format!("${} is defined twice", name)));
         }
     }
-    fn i_fns(&mut self, key: &'static str, vty: VarType) {
+    // block label of jmp, jnz or phi, which must be defined in the function
+    fn getblockref_n(&mut self, tms: &mut TokenMass<'a>) -> Result<&'a str, Diagnostic> {
+        let id = tms.cpos;
+        let lb = tms.getblocklb_n()?;
+        self.bbrefs.push((lb, id));
+        Ok(lb)
+    }
+    fn i_fns(&mut self, key: &'a str, vty: VarType<'a>) {
         self.fns.insert(key, vty);
     }
-    fn i_lvs(&mut self, key: &'static str, var: Var) {
+    fn i_lvs(&mut self, key: &'a str, var: Var<'a>) {
         self.lvs.insert(key, var);
     }
-    fn i_gvs(&mut self, key: &'static str, ssd: Gdata) {
+    fn i_gvs(&mut self, key: &'a str, ssd: Gdata<'a>) {
         self.gvs.insert(key, ssd);
     }
+    fn i_tys(&mut self, key: &'a str, agg: AggType<'a>) {
+        self.tys.insert(key, agg);
+    }
 }
 
 // parser rhs of instr
-fn parseinstrrhs(
-    tms: &mut TokenMass,
-    env: &mut Env,
-    m2rinfo: &mut HashMap<&'static str, MemToregAlloca>,
-) -> SsaInstr {
-    // loadw
-    if tms.eq_tkty(TokenType::Loadw) {
-        let rhs = tms.getvar_n(env);
-        assert!(rhs.ty == VarType::Ptr2Word || rhs.ty == VarType::Ptr2Long);
-        m2rinfo
-            .get_mut(rhs.name)
-            .unwrap_or_else(|| panic!("cannot find \"{}\" variable.", rhs.name))
-            .usgbbs
-            .insert(cur_bbnum());
-        return SsaInstr::new(SsaInstrOp::Loadw(rhs));
+fn parseinstrrhs<'a>(
+    tms: &mut TokenMass<'a>,
+    env: &mut Env<'a>,
+    m2rinfo: &mut HashMap<&'a str, MemToregAlloca<'a>>,
+    vty: VarType<'a>,
+) -> Result<SsaInstr<'a>, Diagnostic> {
+    // load
+    if let TokenType::Load(ldop) = tms.cur_tkty() {
+        tms.cpos += 1;
+        let rhs = tms.getaddrvar_n(env)?;
+        // loads from the stack slot of alloc
+        if let Some(m2ralloc) = m2rinfo.get_mut(rhs.name) {
+            m2ralloc.ldpush(env.cur_bbnum(), ldop);
+        }
+        return Ok(SsaInstr::new(SsaInstrOp::Load(ldop, rhs)));
+    }
+    // extension
+    if let TokenType::Ext(extop) = tms.cur_tkty() {
+        tms.cpos += 1;
+        let src = tms.getnarrowfco_n(extop.srcty().tovarty(), env)?;
+        return Ok(SsaInstr::new(SsaInstrOp::Ext(extop, src)));
+    }
+    // conversion between integer and floating point
+    if let TokenType::Cvt(cvtop) = tms.cur_tkty() {
+        tms.cpos += 1;
+        let dstty = vty.tovalty();
+        let src = tms.getfco_n(cvtop.srcty(dstty).tovarty(), env)?;
+        return Ok(SsaInstr::new(SsaInstrOp::Cvt(cvtop, dstty, src)));
+    }
+    // copy
+    if tms.eq_tkty(TokenType::Copy) {
+        let src = tms.getfco_n(vty, env)?;
+        return Ok(SsaInstr::new(SsaInstrOp::Src(src)));
     }
     // binop
     if let Some(binop) = tms.getbinop() {
-        let lhs = tms.getfco_n(VarType::Word, env);
-        tms.as_tkty(TokenType::Comma);
-        let rhs = tms.getfco_n(VarType::Word, env);
-        return SsaInstr::new(SsaInstrOp::Bop(binop, lhs, rhs));
+        let lhs = tms.getfco_n(vty.clone(), env)?;
+        tms.as_tkty(TokenType::Comma)?;
+        let rhs = tms.getfco_n(vty, env)?;
+        return Ok(SsaInstr::new(SsaInstrOp::Bop(binop, lhs, rhs)));
     }
     // call
     if tms.eq_tkty(TokenType::Call) {
-        tms.as_tkty(TokenType::Dollar);
-        let funlb = tms.gettext_n();
-        let retty = env.g_fns(&funlb);
+        tms.as_tkty(TokenType::Dollar)?;
+        let funlb = tms.getname_n()?;
+        // the result has the type of the assigned variable (e.g. external functions)
+        let retty = if vty == VarType::Void {
+            env.g_fns(funlb)
+        } else {
+            vty.clone()
+        };
         let mut variadic = false;
         // arguments
         let mut args = vec![];
-        tms.as_tkty(TokenType::Lbrace);
+        tms.as_tkty(TokenType::Lbrace)?;
         if tms.eq_tkty(TokenType::Rbrace) {
-            return SsaInstr::new(SsaInstrOp::Call(retty, funlb, args, variadic));
+            return Ok(SsaInstr::new(SsaInstrOp::Call(
+                retty, funlb, args, variadic,
+            )));
         }
         loop {
             if tms.eq_tkty(TokenType::Threedot) {
                 variadic = true;
             } else {
-                let ty = tms.gettype_n();
+                let ty = tms.getabity_n(env)?;
                 tms.eq_tkty(TokenType::Dollar);
-                let arg = tms.getfco_n(ty, env);
-
+                let mut arg = tms.getfco_n(ty.clone(), env)?;
+                // the address is passed as the aggregate of the argument type
+                if let (FirstClassObj::Variable(var), VarType::Aggre(_)) = (&mut arg, &ty) {
+                    var.ty = ty;
+                }
                 args.push(arg);
             }
             if tms.eq_tkty(TokenType::Rbrace) {
                 break;
             }
-            tms.as_tkty(TokenType::Comma);
+            tms.as_tkty(TokenType::Comma)?;
         }
-        return SsaInstr::new(SsaInstrOp::Call(retty, funlb, args, variadic));
+        return Ok(SsaInstr::new(SsaInstrOp::Call(
+            retty, funlb, args, variadic,
+        )));
+    }
+    // vaarg
+    if tms.eq_tkty(TokenType::Vaarg) {
+        let ap = tms.getaddrvar_n(env)?;
+        // va_list is accessed other than load and store
+        m2rinfo.remove(ap.name);
+        return Ok(SsaInstr::new(SsaInstrOp::Vaarg(vty.tovalty(), ap)));
     }
     if tms.eq_tkty(TokenType::Phi) {
         let mut pv = vec![];
         loop {
-            assert_eq!(tms.cur_tkty(), TokenType::Blocklb);
-            let lb = tms.gettext_n();
-            let fco;
-            match tms.getcurrent_token().tty {
+            let lb = env.getblockref_n(tms)?;
+            // a variable may be defined after the phi, so it is resolved
+            // after the whole function is parsed (see resolvephiargs).
+            let fco = match tms.getcurrent_token().tty {
                 TokenType::Ident => {
-                    fco =
-                        FirstClassObj::Variable(Var::new("dummy_for_phi", VarType::Void, i32::MAX));
+                    env.phirefs.entry(tms.gettext()).or_insert(tms.cpos);
+                    FirstClassObj::Variable(Var::new(tms.gettext_n(), VarType::Void, i32::MAX))
                 }
-                _ => {
-                    fco = tms.getfco_n(VarType::Word, env);
-                }
-            }
+                _ => tms.getfco_n(vty.clone(), env)?,
+            };
             pv.push((lb, fco));
             if !tms.eq_tkty(TokenType::Comma) {
                 break;
             }
         }
-        return SsaInstr::new(SsaInstrOp::Phi(None, pv));
+        return Ok(SsaInstr::new(SsaInstrOp::Phi(None, pv)));
     }
-    let curtk = tms.getcurrent_token();
-    panic!(
-        "parseinstr panic {:?}: {}",
-        curtk,
-        &PROGRAM[curtk.poss..curtk.pose]
-    );
+    Err(tms.expected("instruction"))
 }
 
-fn parseinstroverall(
-    tms: &mut TokenMass,
-    env: &mut Env,
-    transbbs: &mut Vec<&'static str>,
-    m2rinfo: &mut HashMap<&'static str, MemToregAlloca>,
-) -> SsaInstr {
-    // ret
+fn parseinstroverall<'a>(
+    tms: &mut TokenMass<'a>,
+    env: &mut Env<'a>,
+    transbbs: &mut Vec<&'a str>,
+    m2rinfo: &mut HashMap<&'a str, MemToregAlloca<'a>>,
+    retty: &VarType<'a>,
+) -> Result<SsaInstr<'a>, Diagnostic> {
+    // ret. the literal has the return type of the function (the address for the aggregate).
     if tms.eq_tkty(TokenType::Ret) {
-        let retnum = tms.getfco_n(VarType::Word, env);
-        return SsaInstr::new(SsaInstrOp::Ret(retnum));
+        let vty = match retty {
+            VarType::Long | VarType::Aggre(_) => VarType::Long,
+            VarType::Half | VarType::Byte => retty.clone(),
+            _ => VarType::Word,
+        };
+        let retnum = tms.getnarrowfco_n(vty, env)?;
+        return Ok(SsaInstr::new(SsaInstrOp::Ret(retnum)));
     }
     // lhs =* rhs instruction
     if tms.cur_tkty() == TokenType::Ident {
         let varn = tms.gettext_n();
         let cur_tkty = tms.cur_tkty();
-        let assignty;
-        let mut var;
-        if cur_tkty == TokenType::Eql {
-            assignty = ValueType::Long;
-            var = Var::new(varn, VarType::Long, nextfreshregister());
-        } else {
-            assert_eq!(cur_tkty, TokenType::Eqw);
-            assignty = ValueType::Word;
-            var = Var::new(varn, VarType::Word, nextfreshregister());
-        }
+        let assignty = match cur_tkty {
+            TokenType::Eqw => ValueType::Word,
+            TokenType::Eql => ValueType::Long,
+            TokenType::Eqs => ValueType::Single,
+            TokenType::Eqd => ValueType::Double,
+            // =:aggregate
+            TokenType::Eq => ValueType::Long,
+            _ => {
+                return Err(tms.expected("`=w`, `=l`, `=s`, `=d` or `=`"));
+            }
+        };
+        let mut var = Var::new(varn, assignty.tovarty(), env.regs.nextfreshregister());
         tms.cpos += 1;
-        // alloc4
-        if tms.eq_tkty(TokenType::Alloc4) {
-            let rhs = tms.getnum_n();
-            var.ty = VarType::Ptr2Word;
+        if cur_tkty == TokenType::Eq {
+            var.ty = tms.getabity_n(env)?;
+        }
+        // alloc4, alloc8, alloc16
+        if let TokenType::Alloc(align) = tms.cur_tkty() {
+            tms.cpos += 1;
+            let size = tms.getnum_n()?;
+            var.ty = if align == 4 {
+                VarType::Ptr2Word
+            } else {
+                VarType::Ptr2Long
+            };
             env.i_lvs(var.name, var.clone());
             m2rinfo.insert(var.name, MemToregAlloca::new(var.name));
-            return SsaInstr::new(SsaInstrOp::Alloc4(var, rhs));
+            return Ok(SsaInstr::new(SsaInstrOp::Alloc(var, align, size)));
         }
-        // ceqw, csltw
-        let ctkty = tms.cur_tkty();
-        if ctkty == TokenType::Ceqw || ctkty == TokenType::Csltw {
+        // comparison
+        if let TokenType::Comp(cop, valty) = tms.cur_tkty() {
             tms.cpos += 1;
-            let lhs = tms.getvar_n(env);
-            tms.as_tkty(TokenType::Comma);
-            let rhs = tms.getfco_n(VarType::Word, env);
-            var.ty = VarType::Word;
+            let lhs = tms.getfco_n(valty.tovarty(), env)?;
+            tms.as_tkty(TokenType::Comma)?;
+            let rhs = tms.getfco_n(valty.tovarty(), env)?;
             env.i_lvs(var.name, var.clone());
-            return if ctkty == TokenType::Ceqw {
-                SsaInstr::new(SsaInstrOp::Comp(CompOp::Ceqw, var, lhs, rhs))
-            } else {
-                SsaInstr::new(SsaInstrOp::Comp(CompOp::Csltw, var, lhs, rhs))
-            };
+            return Ok(SsaInstr::new(SsaInstrOp::Comp(cop, valty, var, lhs, rhs)));
         }
-        let rhs = parseinstrrhs(tms, env, m2rinfo);
+        let rhs = parseinstrrhs(tms, env, m2rinfo, var.ty.clone())?;
         env.i_lvs(var.name, var.clone());
-        return SsaInstr::new(SsaInstrOp::Assign(assignty, var, Box::new(rhs)));
-    }
-    // storew
-    if tms.eq_tkty(TokenType::Storew) {
-        let lhs = tms.getfco_n(VarType::Word, env);
-        tms.as_tkty(TokenType::Comma);
-        let rhs = tms.getvar_n(env);
-        m2rinfo
-            .get_mut(rhs.name)
-            .unwrap_or_else(|| panic!("cannot find \"{}\" variable.", rhs.name))
-            .strpush(cur_bbnum());
-        return SsaInstr::new(SsaInstrOp::Storew(lhs, rhs));
+        return Ok(SsaInstr::new(SsaInstrOp::Assign(
+            assignty,
+            var,
+            Box::new(rhs),
+        )));
+    }
+    // store
+    if let TokenType::Store(valty) = tms.cur_tkty() {
+        tms.cpos += 1;
+        let lhs = tms.getnarrowfco_n(valty.tovarty(), env)?;
+        tms.as_tkty(TokenType::Comma)?;
+        let rhs = tms.getaddrvar_n(env)?;
+        // stores to the stack slot of alloc
+        if let Some(m2ralloc) = m2rinfo.get_mut(rhs.name) {
+            m2ralloc.strpush(env.cur_bbnum(), valty);
+        }
+        return Ok(SsaInstr::new(SsaInstrOp::Store(valty, lhs, rhs)));
     }
     // jnz
     if tms.eq_tkty(TokenType::Jnz) {
-        let condvar = tms.getvar_n(env);
-        tms.as_tkty(TokenType::Comma);
-        let blb1 = tms.getblocklb_n();
-        tms.as_tkty(TokenType::Comma);
-        let blb2 = tms.getblocklb_n();
+        let condvar = tms.getvar_n(env)?;
+        tms.as_tkty(TokenType::Comma)?;
+        let blb1 = env.getblockref_n(tms)?;
+        tms.as_tkty(TokenType::Comma)?;
+        let blb2 = env.getblockref_n(tms)?;
         transbbs.push(blb1);
         transbbs.push(blb2);
-        return SsaInstr::new(SsaInstrOp::Jnz(condvar, blb1, blb2));
+        return Ok(SsaInstr::new(SsaInstrOp::Jnz(condvar, blb1, blb2)));
     }
     // jmp
     if tms.eq_tkty(TokenType::Jmp) {
-        let blb = tms.getblocklb_n();
+        let blb = env.getblockref_n(tms)?;
         transbbs.push(blb);
-        return SsaInstr::new(SsaInstrOp::Jmp(blb));
+        return Ok(SsaInstr::new(SsaInstrOp::Jmp(blb)));
+    }
+    // vastart
+    if tms.eq_tkty(TokenType::Vastart) {
+        let ap = tms.getaddrvar_n(env)?;
+        m2rinfo.remove(ap.name);
+        return Ok(SsaInstr::new(SsaInstrOp::Vastart(ap)));
     }
     // call
     if tms.cur_tkty() == TokenType::Call {
-        return parseinstrrhs(tms, env, m2rinfo);
+        return parseinstrrhs(tms, env, m2rinfo, VarType::Void);
+    }
+    Err(tms.expected("instruction"))
+}
+
+// the parser resumes from these tokens after the error
+fn istoplevel(tty: TokenType) -> bool {
+    use TokenType::*;
+    matches!(
+        tty,
+        Function | Data | Type | Export | Thread | Section | Eof
+    )
+}
+
+fn isblockstart(tms: &TokenMass) -> bool {
+    tms.cur_tkty() == TokenType::Blocklb && tms.tks[tms.cpos + 1].tty == TokenType::Colon
+}
+
+// skip from the start of the broken instruction to the next block label or top level item.
+// the variables assigned in the skipped instructions are defined so that their uses are not
+// reported again.
+fn recoverinfun<'a>(tms: &mut TokenMass<'a>, env: &mut Env<'a>, start: usize) {
+    use TokenType::*;
+    tms.cpos = start;
+    while !isblockstart(tms) && !istoplevel(tms.cur_tkty()) {
+        let assignty = match tms.tks[tms.cpos + 1].tty {
+            Eqw => Some(VarType::Word),
+            Eql | Eq => Some(VarType::Long),
+            Eqs => Some(VarType::Single),
+            Eqd => Some(VarType::Double),
+            _ => None,
+        };
+        if let (Ident, Some(ty)) = (tms.cur_tkty(), assignty) {
+            let name = tms.gettext();
+            let var = Var::new(name, ty, env.regs.nextfreshregister());
+            env.i_lvs(name, var);
+        }
+        tms.cpos += 1;
+    }
+}
+
+// skip to the next function, data or type
+fn recovertoplevel(tms: &mut TokenMass) {
+    while !istoplevel(tms.cur_tkty()) {
+        tms.cpos += 1;
     }
-    panic!(
-        "parseinstroverall error. {:?}\n{:?}",
-        tms.getcurrent_token(),
-        tms.tks[tms.cpos + 1].get_text()
-    );
 }
 
 // parse basic block
-fn parsebb(
-    tms: &mut TokenMass,
-    env: &mut Env,
-    m2rinfo: &mut HashMap<&'static str, MemToregAlloca>,
-) -> SsaBlock {
-    let mut ssb = SsaBlock::new(tms.gettext_n(), get_bbnum_n(), vec![]);
+fn parsebb<'a>(
+    tms: &mut TokenMass<'a>,
+    env: &mut Env<'a>,
+    m2rinfo: &mut HashMap<&'a str, MemToregAlloca<'a>>,
+    retty: &VarType<'a>,
+) -> SsaBlock<'a> {
+    let start = tms.cpos;
+    let mut ssb = SsaBlock::new(tms.gettext_n(), env.get_bbnum_n(), vec![]);
+    if !env.bblbs.insert(ssb.lb) {
+        env.errors
+            .push(tms.error_at(start, format!("block @{} is defined twice", ssb.lb)));
+    }
     let mut transbbs = vec![];
-    tms.as_tkty(TokenType::Colon);
+    if let Err(diag) = tms.as_tkty(TokenType::Colon) {
+        env.errors.push(diag);
+        recoverinfun(tms, env, start + 1);
+        return ssb;
+    }
     let mut empty_block = true;
     loop {
         let tkty = tms.cur_tkty();
@@ -612,118 +1473,308 @@ fn parsebb(
             }
             break;
         }
-        if tkty == TokenType::Crbrace {
+        // `}` may be missing at the end of the function
+        if tkty == TokenType::Crbrace || istoplevel(tkty) {
             break;
         }
-        ssb.instrs
-            .push(parseinstroverall(tms, env, &mut transbbs, m2rinfo));
+        let start = tms.cpos;
+        match parseinstroverall(tms, env, &mut transbbs, m2rinfo, retty) {
+            Ok(instr) => ssb.instrs.push(instr),
+            Err(diag) => {
+                // the rest of the block is skipped
+                env.errors.push(diag);
+                recoverinfun(tms, env, start);
+                break;
+            }
+        }
         empty_block = false;
     }
     ssb.transbbs = transbbs;
     ssb
 }
 
-fn parseargs(tms: &mut TokenMass, env: &mut Env) -> Vec<Var> {
+// parse the parameters and whether the function is variadic
+fn parseargs<'a>(
+    tms: &mut TokenMass<'a>,
+    env: &mut Env<'a>,
+) -> Result<(Vec<Var<'a>>, bool), Diagnostic> {
     let mut argvars = vec![];
-    tms.as_tkty(TokenType::Lbrace);
+    tms.as_tkty(TokenType::Lbrace)?;
     if tms.eq_tkty(TokenType::Rbrace) {
-        return vec![];
+        return Ok((vec![], false));
     }
     // parse each arguments
-    let mut frsn = -1;
+    let mut params = vec![];
+    let mut variadic = false;
     loop {
-        let vty = tms.gettype_n();
-        let lb = tms.gettext_n();
-        let var = Var::new(lb, vty, frsn);
-        env.i_lvs(lb, var.clone());
-        argvars.push(var);
+        // `...` follows the named parameters
+        if tms.eq_tkty(TokenType::Threedot) {
+            variadic = true;
+            tms.as_tkty(TokenType::Rbrace)?;
+            break;
+        }
+        let vty = tms.getabity_n(env)?;
+        let lb = tms.getname_n()?;
+        params.push((vty, lb));
         if tms.eq_tkty(TokenType::Rbrace) {
             break;
         }
-        tms.as_tkty(TokenType::Comma);
-        frsn -= 1;
+        tms.as_tkty(TokenType::Comma)?;
+    }
+    // arguments are copied from the argument registers or the stack at the entry
+    for (vty, lb) in params {
+        let var = Var::new(lb, vty, env.regs.nextfreshregister());
+        env.i_lvs(lb, var.clone());
+        argvars.push(var);
+    }
+    Ok((argvars, variadic))
+}
+
+fn resolvephiargs<'a>(tms: &TokenMass<'a>, bls: &mut [SsaBlock<'a>], env: &mut Env<'a>) {
+    for bb in bls {
+        for instr in &mut bb.instrs {
+            if let SsaInstrOp::Assign(_, _, rhs) = &mut instr.op {
+                if let SsaInstrOp::Phi(_, pv) = &mut rhs.op {
+                    for (_, fco) in pv {
+                        if let FirstClassObj::Variable(var) = fco {
+                            if var.rg_vr == i32::MAX {
+                                match env.g_lvs(var.name) {
+                                    Some(v) => *var = v,
+                                    None => env.errors.push(tms.error_at(
+                                        env.phirefs[var.name],
+                                        format!("cannot find `{}` in this scope", var.name),
+                                    )),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
     }
-    argvars
 }
 
 // parse function ...
-fn parsefun(tms: &mut TokenMass, env: &mut Env) -> SsaFunction {
+fn parsefun<'a>(tms: &mut TokenMass<'a>, env: &mut Env<'a>) -> Result<SsaFunction<'a>, Diagnostic> {
     let mut sfn = SsaFunction::new("", VarType::Void);
-    tms.as_tkty(TokenType::Function);
+    tms.as_tkty(TokenType::Function)?;
     if tms.cur_tkty() != TokenType::Dollar {
-        sfn.retty = tms.gettype_n();
+        sfn.retty = tms.getabity_n(env)?;
     }
-    tms.as_tkty(TokenType::Dollar);
-    sfn.name = tms.gettext_n();
+    tms.as_tkty(TokenType::Dollar)?;
+    let nameid = tms.cpos;
+    sfn.name = tms.getname_n()?;
+    env.definesym(tms, nameid, sfn.name);
+    env.i_fns(sfn.name, sfn.retty.clone());
     // parse arguments
-    sfn.args = parseargs(tms, env);
+    (sfn.args, sfn.variadic) = parseargs(tms, env)?;
     // function body
-    tms.as_tkty(TokenType::Clbrace);
-    reset_bbnum();
+    tms.as_tkty(TokenType::Clbrace)?;
+    env.bbnum = 0;
+    env.phirefs.clear();
+    env.bblbs.clear();
+    env.bbrefs.clear();
+    let nerrors = env.errors.len();
     // information for mem2reg
     let mut m2rinfo = HashMap::new();
     loop {
         let ctkty = tms.cur_tkty();
         if ctkty == TokenType::Blocklb {
-            sfn.bls.push(parsebb(tms, env, &mut m2rinfo));
+            sfn.bls.push(parsebb(tms, env, &mut m2rinfo, &sfn.retty));
+        } else if istoplevel(ctkty) && env.errors.len() > nerrors {
+            // `}` was skipped while recovering from the error
+            break;
         } else {
-            tms.as_tkty(TokenType::Crbrace);
+            tms.as_tkty(TokenType::Crbrace)?;
             break;
         }
     }
-    MemToregAlloca::decision_type(&mut m2rinfo);
+    // the blocks skipped while recovering from the error may be referred
+    if env.errors.len() == nerrors {
+        for &(lb, id) in &env.bbrefs {
+            if !env.bblbs.contains(lb) {
+                env.errors
+                    .push(tms.error_at(id, format!("block @{} is not defined", lb)));
+            }
+        }
+    }
+    resolvephiargs(tms, &mut sfn.bls, env);
+    MemToregAlloca::decision_type(&mut m2rinfo, &sfn.bls);
     sfn.m2rinfo = m2rinfo;
-    sfn
+    Ok(sfn)
 }
 
-fn parsedata(tms: &mut TokenMass, env: &mut Env) -> Gdata {
-    let mut gd = Gdata::new(get_gfrsn(), 0, "", vec![], VarType::Void);
-    tms.as_tkty(TokenType::Dollar);
-    gd.lb = tms.gettext_n();
-    tms.as_tkty(TokenType::Eq);
+fn parsedata<'a>(tms: &mut TokenMass<'a>, env: &mut Env<'a>) -> Result<Gdata<'a>, Diagnostic> {
+    let mut gd = Gdata::new(env.get_gfrsn(), 0, "", vec![], VarType::Void);
+    tms.as_tkty(TokenType::Dollar)?;
+    let nameid = tms.cpos;
+    gd.lb = tms.getname_n()?;
+    env.definesym(tms, nameid, gd.lb);
+    tms.as_tkty(TokenType::Eq)?;
     if tms.eq_tkty(TokenType::Align) {
-        gd.al = tms.getnum_n();
+        gd.al = tms.getnum_n()?;
     }
-    tms.as_tkty(TokenType::Clbrace);
+    tms.as_tkty(TokenType::Clbrace)?;
     let mut typesv = vec![];
+    // natural alignment is that of the largest element
+    let mut natal = 1;
 
     // get each element from global data
-    loop {
-        let dty = tms.gettype_n();
+    while !tms.eq_tkty(TokenType::Crbrace) {
+        // zero fill
+        if tms.gettext() == "z" {
+            tms.cpos += 1;
+            let size = tms.getnum_n()?;
+            gd.dts.push(DataItem::Zero(size));
+            typesv.push((VarType::Byte, size as u32));
+            tms.eq_tkty(TokenType::Comma);
+            continue;
+        }
+        let dty = tms.gettype_n()?;
+        natal = natal.max(dty.stacksize());
         let mut cnt = 0;
-        while !tms.eq_tkty(TokenType::Comma) {
-            gd.dts.push(tms.getfco_n(dty.clone(), env));
+        while !matches!(tms.cur_tkty(), TokenType::Comma | TokenType::Crbrace) {
+            let item = match tms.cur_tkty() {
+                TokenType::Dollar => {
+                    tms.cpos += 1;
+                    let sym = tms.getname_n()?;
+                    let offset = if tms.eq_tkty(TokenType::Plus) {
+                        tms.getnum_n()?
+                    } else {
+                        0
+                    };
+                    DataItem::Symbol(sym, offset)
+                }
+                TokenType::String => DataItem::String(tms.gettext_n()),
+                TokenType::Ilit => DataItem::Num(dty.clone(), tms.getint_n(&dty)?),
+                TokenType::Flit => match tms.getfco_n(dty.clone(), env)? {
+                    FirstClassObj::Float(_, num) => DataItem::Float(dty.clone(), num),
+                    fco => unreachable!("{:?}", fco),
+                },
+                _ => return Err(tms.expected("data item")),
+            };
+            gd.dts.push(item);
             cnt += 1;
-            if tms.eq_tkty(TokenType::Crbrace) {
-                gd.types = VarType::ConT(typesv);
-                env.i_gvs(gd.lb, gd.clone());
-                return gd;
-            }
         }
         typesv.push((dty, cnt));
+        tms.eq_tkty(TokenType::Comma);
+    }
+    if gd.al == 0 {
+        gd.al = natal;
     }
+    gd.types = VarType::ConT(typesv);
+    env.i_gvs(gd.lb, gd.clone());
+    Ok(gd)
 }
 
-pub fn parse(tms: &mut TokenMass) -> SsaProgram {
-    let mut spg = SsaProgram::new(vec![], vec![]);
-    let mut env = Env::new();
+// parse aggregate type
+fn parsetype<'a>(tms: &mut TokenMass<'a>, env: &mut Env<'a>) -> Result<(), Diagnostic> {
+    tms.as_tkty(TokenType::Colon)?;
+    let mut agg = AggType::new(tms.getname_n()?);
+    tms.as_tkty(TokenType::Eq)?;
+    let mut align = None;
+    if tms.eq_tkty(TokenType::Align) {
+        align = Some(tms.getnum_n()?);
+    }
+    tms.as_tkty(TokenType::Clbrace)?;
+    while !tms.eq_tkty(TokenType::Crbrace) {
+        if tms.cur_tkty() == TokenType::Ilit {
+            // opaque type
+            agg.size += tms.getnum_n()?;
+        } else {
+            let ty = tms.getabity_n(env)?;
+            let mut cnt = 1;
+            if tms.cur_tkty() == TokenType::Ilit {
+                cnt = tms.getnum_n()?;
+            }
+            agg.pushmember(&ty, cnt);
+        }
+        tms.eq_tkty(TokenType::Comma);
+    }
+    if let Some(al) = align {
+        agg.align = al;
+    }
+    agg.finish();
+    env.i_tys(agg.name, agg);
+    Ok(())
+}
+
+// parse linkage before function or data
+fn parselinkage<'a>(tms: &mut TokenMass<'a>) -> Result<Linkage<'a>, Diagnostic> {
+    let mut linkage = Linkage::default();
     loop {
-        // function
-        if tms.eq_tkty(TokenType::Function) {
-            let (funlb, retty) = tms.getfuncdata();
-            env.i_fns(funlb, retty);
-            spg.funcs.push(parsefun(tms, &mut env));
-            continue;
+        if tms.eq_tkty(TokenType::Export) {
+            linkage.export = true;
+        } else if tms.eq_tkty(TokenType::Thread) {
+            linkage.thread = true;
+        } else if tms.eq_tkty(TokenType::Section) {
+            if tms.cur_tkty() != TokenType::String {
+                return Err(tms.expected("section name"));
+            }
+            let name = tms.gettext_n();
+            let mut flags = None;
+            if tms.cur_tkty() == TokenType::String {
+                flags = Some(tms.gettext_n());
+            }
+            linkage.section = Some((name, flags));
+        } else {
+            return Ok(linkage);
         }
-        // global data
-        if tms.eq_tkty(TokenType::Data) {
-            let ssd = parsedata(tms, &mut env);
-            env.i_gvs(ssd.lb, ssd.clone());
-            spg.gvs.push(ssd);
-            continue;
+    }
+}
+
+pub fn parse<'a>(
+    tms: &mut TokenMass<'a>,
+    names: &'a NamePool,
+) -> Result<SsaProgram<'a>, Vec<Diagnostic>> {
+    let mut spg = SsaProgram::new(vec![], vec![], names);
+    for id in 0..tms.tks.len() {
+        if matches!(tms.tks[id].tty, TokenType::Ident | TokenType::Blocklb) {
+            names.reserve(tms.tktext(id));
         }
-        tms.as_tkty(TokenType::Eof);
-        break;
     }
-    spg
+    let mut env = Env::new();
+    while tms.cur_tkty() != TokenType::Eof {
+        if let Err(diag) = parsetoplevel(tms, &mut env, &mut spg) {
+            env.errors.push(diag);
+            recovertoplevel(tms);
+        }
+    }
+    if !env.errors.is_empty() {
+        return Err(env.errors);
+    }
+    spg.regs = env.regs;
+    Ok(spg)
+}
+
+// parse function, aggregate type or global data
+fn parsetoplevel<'a>(
+    tms: &mut TokenMass<'a>,
+    env: &mut Env<'a>,
+    spg: &mut SsaProgram<'a>,
+) -> Result<(), Diagnostic> {
+    let linkage = parselinkage(tms)?;
+    // function
+    if tms.cur_tkty() == TokenType::Function {
+        let mut sfn = parsefun(tms, env)?;
+        sfn.linkage = linkage;
+        spg.funcs.push(sfn);
+        return Ok(());
+    }
+    // aggregate type
+    if tms.eq_tkty(TokenType::Type) {
+        return parsetype(tms, env);
+    }
+    // global data
+    if tms.eq_tkty(TokenType::Data) {
+        let mut ssd = parsedata(tms, env)?;
+        ssd.linkage = linkage;
+        env.i_gvs(ssd.lb, ssd.clone());
+        spg.gvs.push(ssd);
+        return Ok(());
+    }
+    let diag = tms.expected("`function`, `data` or `type`");
+    // the unexpected token is skipped
+    tms.cpos += 1;
+    Err(diag)
 }
@@ -1,25 +1,123 @@
 use super::lowir::{
-    LowIrBlock, LowIrInstr, LowIrProgram, NeedStack, Register, RegorNum, StashStacked,
+    Address, LowIrBlock, LowIrInstr, LowIrProgram, NeedStack, Register, RegorNum, StashStacked,
 };
+use super::parser::LoadOp;
 
 pub const GENEREGSIZE: usize = 6;
+pub const XMMREGSIZE: usize = 7;
 
-fn regaoflir(
-    lbb: &mut LowIrBlock,
+// allocatable real registers for each register class
+#[derive(Clone)]
+pub struct RealRegs<'a> {
+    pub gene: [Option<Register<'a>>; GENEREGSIZE],
+    pub xmm: [Option<Register<'a>>; XMMREGSIZE],
+    // virtual registers used by the current instruction
+    pub pinned: Vec<i32>,
+    // general registers used in the current function
+    pub used: [bool; GENEREGSIZE],
+    // registers loaded from the homes by the current instruction and their homes
+    pub homed: Vec<(Register<'a>, i32)>,
+}
+
+impl<'a> Default for RealRegs<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> RealRegs<'a> {
+    pub fn new() -> Self {
+        Self {
+            gene: [None; GENEREGSIZE],
+            xmm: [None; XMMREGSIZE],
+            pinned: vec![],
+            used: [false; GENEREGSIZE],
+            homed: vec![],
+        }
+    }
+    // register to be spilled, which is not used by the current instruction
+    pub fn victim(&mut self, fp: bool) -> Option<usize> {
+        let pinned = self.pinned.clone();
+        self.class(fp)
+            .iter()
+            .position(|r| r.is_some_and(|r| !pinned.contains(&r.vr)))
+    }
+    pub fn class(&mut self, fp: bool) -> &mut [Option<Register<'a>>] {
+        if fp {
+            &mut self.xmm
+        } else {
+            &mut self.gene
+        }
+    }
+    // release the registers whose lives finished before day without being used on the last
+    // day (e.g. the lives extended to the end of the loop)
+    fn expire(&mut self, day: i32) {
+        for realreg in self.gene.iter_mut().chain(self.xmm.iter_mut()) {
+            if realreg.is_some_and(|r| r.vr >= 0 && r.daday < day) {
+                *realreg = None;
+            }
+        }
+    }
+    // release r if its life finishes on day
+    fn free(&mut self, r: &Register<'a>, day: i32) {
+        if r.vr >= 0 && !r.fp {
+            self.used[r.rr as usize] = true;
+        }
+        // registers loaded from the homes are released after the instruction
+        if self.homed.iter().any(|(h, _)| h.vr == r.vr) {
+            return;
+        }
+        if r.daday == day && r.vr >= 0 {
+            self.class(r.fp)[r.rr as usize] = None;
+        }
+    }
+}
+
+fn regaoflir<'a>(
+    lbb: &mut LowIrBlock<'a>,
     day: &mut i32,
-    realregs: &mut [Option<Register>; GENEREGSIZE],
+    realregs: &mut RealRegs<'a>,
     ir_id: usize,
-    stash_stacked: &mut StashStacked,
+    stash_stacked: &mut StashStacked<'a>,
     var_frame_size: i32,
-) -> Vec<LowIrInstr> {
+) -> Vec<LowIrInstr<'a>> {
     use LowIrInstr::*;
     let mut get_stash_reg_instrs = vec![];
     let mut target_instr = std::mem::replace(&mut lbb.instrs[ir_id], LowIrInstr::LowNop);
+    realregs.pinned.clear();
     match &mut target_instr {
+        Storereg(ref mut r1, Address::Base(ref mut r2, _))
+        | Load(_, ref mut r1, Address::Base(ref mut r2, _))
+        | Vaarg(ref mut r1, Address::Base(ref mut r2, _)) => {
+            let needstack2 = r2.regalloc(realregs, stash_stacked);
+            get_stash_register(
+                needstack2,
+                &mut get_stash_reg_instrs,
+                r2,
+                realregs,
+                var_frame_size,
+            );
+            realregs.free(r2, *day);
+            let needstack1 = r1.regalloc(realregs, stash_stacked);
+            get_stash_register(
+                needstack1,
+                &mut get_stash_reg_instrs,
+                r1,
+                realregs,
+                var_frame_size,
+            );
+            realregs.free(r1, *day);
+        }
         Movenum(ref mut r, _)
+        | Movefnum(ref mut r, _)
         | Ret(ref mut r)
-        | Storewreg(ref mut r, _)
-        | Loadw(ref mut r, _)
+        | Retaggre(ref mut r, _)
+        | Storereg(ref mut r, _)
+        | Load(_, ref mut r, _)
+        | Storenum(_, _, Address::Base(ref mut r, _))
+        | Vastart(Address::Base(ref mut r, _), _)
+        | Vaarg(ref mut r, _)
+        | Lea(ref mut r, _)
         | Jnz(ref mut r, ..) => {
             let needstack = r.regalloc(realregs, stash_stacked);
             get_stash_register(
@@ -29,11 +127,11 @@ fn regaoflir(
                 realregs,
                 var_frame_size,
             );
-            if r.daday == *day && r.vr >= 0 {
-                realregs[r.rr as usize] = None;
-            }
+            realregs.free(r, *day);
         }
-        Movereg(ref mut r1, ref mut r2) => {
+        Movereg(ref mut r1, ref mut r2)
+        | Ext(_, ref mut r1, ref mut r2)
+        | Cvt(_, ref mut r1, ref mut r2) => {
             let needstack1 = r1.regalloc(realregs, stash_stacked);
             get_stash_register(
                 needstack1,
@@ -50,12 +148,8 @@ fn regaoflir(
                 realregs,
                 var_frame_size,
             );
-            if r1.daday == *day && r1.vr >= 0 {
-                realregs[r1.rr as usize] = None;
-            }
-            if r2.daday == *day && r2.vr >= 0 {
-                realregs[r2.rr as usize] = None;
-            }
+            realregs.free(r1, *day);
+            realregs.free(r2, *day);
         }
         Bop(_, ref mut r1, ref mut r2) => {
             let needstack1 = r1.regalloc(realregs, stash_stacked);
@@ -66,9 +160,7 @@ fn regaoflir(
                 realregs,
                 var_frame_size,
             );
-            if r1.daday == *day && r1.vr >= 0 {
-                realregs[r1.rr as usize] = None;
-            }
+            realregs.free(r1, *day);
             if let RegorNum::Reg(ref mut r) = r2 {
                 let needstack2 = r.regalloc(realregs, stash_stacked);
                 get_stash_register(
@@ -78,15 +170,14 @@ fn regaoflir(
                     realregs,
                     var_frame_size,
                 );
-                if r.daday == *day && r.vr >= 0 {
-                    realregs[r.rr as usize] = None;
-                }
+                realregs.free(r, *day);
             }
         }
-        Call(ref mut r, _, ref mut args, ref mut usedrs) => {
-            for i in 0..realregs.len() {
-                if realregs[i] != None {
-                    usedrs.push(i);
+        Call(ref mut r, _, ref mut args, ref mut usedrs, _) => {
+            // registers living across the call
+            for realreg in realregs.gene.iter().chain(realregs.xmm.iter()).flatten() {
+                if realreg.daday > *day {
+                    usedrs.push(*realreg);
                 }
             }
             let needstack = r.regalloc(realregs, stash_stacked);
@@ -98,7 +189,7 @@ fn regaoflir(
                 var_frame_size,
             );
             let mut regargs = vec![];
-            for ref mut arg in args {
+            for (ref mut arg, _) in args {
                 if let RegorNum::Reg(r2) = arg {
                     let needstack2 = r2.regalloc(realregs, stash_stacked);
                     get_stash_register(
@@ -111,16 +202,9 @@ fn regaoflir(
                     regargs.push(*r2);
                 }
             }
-            if r.daday == *day && r.vr >= 0 {
-                realregs[r.rr as usize] = None;
-            }
+            realregs.free(r, *day);
             for r2 in regargs {
-                if r2.daday == *day {
-                    if r2.rr > 6 {
-                        panic!("{:?}", r2);
-                    }
-                    realregs[r2.rr as usize] = None;
-                }
+                realregs.free(&r2, *day);
             }
         }
         Comp(_op, ref mut r1, ref mut r2, ref mut rorn) => {
@@ -149,80 +233,134 @@ fn regaoflir(
                     realregs,
                     var_frame_size,
                 );
-                if r3.daday == *day && r3.vr >= 0 {
-                    realregs[r2.rr as usize] = None;
-                }
-            }
-            if r1.daday == *day && r1.vr >= 0 {
-                realregs[r1.rr as usize] = None;
-            }
-            if r2.daday == *day && r2.vr >= 0 {
-                realregs[r2.rr as usize] = None;
+                realregs.free(r3, *day);
             }
+            realregs.free(r1, *day);
+            realregs.free(r2, *day);
         }
-        Storewnum(..) | Jmp(..) => {}
+        Storenum(..) | Vastart(..) | Jmp(..) => {}
         LowNop => {
             panic!("impossible to reach LowNop instr.");
         }
     }
     get_stash_reg_instrs.push(target_instr);
+    // the values living in memory are stored back to their homes
+    for (mut h, home) in std::mem::take(&mut realregs.homed) {
+        realregs.class(h.fp)[h.rr as usize] = None;
+        h.regsize = 8;
+        get_stash_reg_instrs.push(LowIrInstr::Storereg(
+            h,
+            Address::Stack(var_frame_size + home),
+        ));
+    }
     get_stash_reg_instrs
 }
 
-fn get_stash_register(
+fn get_stash_register<'a>(
     needstack: NeedStack,
-    get_stash_reg_instrs: &mut Vec<LowIrInstr>,
-    reg: &Register,
-    realregs: &[Option<Register>; GENEREGSIZE],
+    get_stash_reg_instrs: &mut Vec<LowIrInstr<'a>>,
+    reg: &Register<'a>,
+    realregs: &mut RealRegs<'a>,
     var_frame_size: i32,
 ) {
     use NeedStack::*;
     match needstack {
         Exist(offset) => {
             let stack_offset = var_frame_size + offset;
-            let mut tmp_reg = reg.clone();
-            // use 7th register for buffer register
-            tmp_reg.rr = 6;
-            get_stash_reg_instrs.push(LowIrInstr::Loadw(tmp_reg.clone(), stack_offset));
-            get_stash_reg_instrs.push(LowIrInstr::Storewreg(
-                realregs[0].unwrap().clone(),
-                stack_offset,
+            // spilled values are saved and restored with their full 64 bits
+            let mut tmp_reg = *reg;
+            tmp_reg.regsize = 8;
+            // use the register next to the allocatable ones for buffer register
+            tmp_reg.rr = realregs.class(reg.fp).len() as i32;
+            let mut cur_reg = realregs.class(reg.fp)[reg.rr as usize].unwrap();
+            cur_reg.regsize = 8;
+            let ldop = if reg.fp { LoadOp::Loadd } else { LoadOp::Loadl };
+            get_stash_reg_instrs.push(LowIrInstr::Load(
+                ldop,
+                tmp_reg,
+                Address::Stack(stack_offset),
             ));
-            get_stash_reg_instrs.push(LowIrInstr::Movereg(tmp_reg, realregs[0].unwrap().clone()));
+            get_stash_reg_instrs.push(LowIrInstr::Storereg(cur_reg, Address::Stack(stack_offset)));
+            get_stash_reg_instrs.push(LowIrInstr::Movereg(cur_reg, tmp_reg));
         }
         NoExist(offset) => {
             let stack_offset = var_frame_size + offset;
-            get_stash_reg_instrs.push(LowIrInstr::Storewreg(
-                realregs[0].unwrap().clone(),
-                stack_offset,
+            let mut cur_reg = realregs.class(reg.fp)[reg.rr as usize].unwrap();
+            cur_reg.regsize = 8;
+            get_stash_reg_instrs.push(LowIrInstr::Storereg(cur_reg, Address::Stack(stack_offset)));
+        }
+        Reload(offset) => {
+            let mut cur_reg = *reg;
+            cur_reg.regsize = 8;
+            let ldop = if reg.fp { LoadOp::Loadd } else { LoadOp::Loadl };
+            get_stash_reg_instrs.push(LowIrInstr::Load(
+                ldop,
+                cur_reg,
+                Address::Stack(var_frame_size + offset),
+            ));
+        }
+        Home(stashed, home) => {
+            let mut cur_reg = *reg;
+            cur_reg.regsize = 8;
+            if let Some(offset) = stashed {
+                get_stash_reg_instrs.push(LowIrInstr::Storereg(
+                    cur_reg,
+                    Address::Stack(var_frame_size + offset),
+                ));
+            }
+            let ldop = if reg.fp { LoadOp::Loadd } else { LoadOp::Loadl };
+            get_stash_reg_instrs.push(LowIrInstr::Load(
+                ldop,
+                cur_reg,
+                Address::Stack(var_frame_size + home),
             ));
+            realregs.homed.push((*reg, home));
         }
         NoNeed => {}
     }
 }
 
-pub fn registeralloc(mut lpg: LowIrProgram) -> LowIrProgram {
+pub fn registeralloc<'a>(mut lpg: LowIrProgram<'a>) -> LowIrProgram<'a> {
     let mut day = 1;
-    let mut realregs: [Option<Register>; GENEREGSIZE] = [None; GENEREGSIZE];
+    let mut realregs = RealRegs::new();
     for lowfunc in &mut lpg.funcs {
-        let mut stash_stacked = StashStacked::new();
-        for lowbb in &mut lowfunc.rbbs {
-            let mut new_instrs = vec![];
-            for ir_id in 0..lowbb.instrs.len() {
-                let mut instrs = regaoflir(
-                    lowbb,
-                    &mut day,
-                    &mut realregs,
-                    ir_id,
-                    &mut stash_stacked,
-                    lowfunc.framesize,
-                );
-                day += 1;
-                new_instrs.append(&mut instrs);
+        // the register spilled in the middle of its life is not restored on the jump back to
+        // where it was in the register. such registers live in memory (their homes) for their
+        // whole lives and the allocation is redone until no register is spilled.
+        let (rbbs, fday, frealregs) = (lowfunc.rbbs.clone(), day, realregs.clone());
+        let mut homes = vec![];
+        let stash_stacked = loop {
+            let mut stash_stacked = StashStacked::withhomes(&homes);
+            realregs.used = [false; GENEREGSIZE];
+            for lowbb in &mut lowfunc.rbbs {
+                let mut new_instrs = vec![];
+                for ir_id in 0..lowbb.instrs.len() {
+                    realregs.expire(day);
+                    let mut instrs = regaoflir(
+                        lowbb,
+                        &mut day,
+                        &mut realregs,
+                        ir_id,
+                        &mut stash_stacked,
+                        lowfunc.framesize,
+                    );
+                    day += 1;
+                    new_instrs.append(&mut instrs);
+                }
+                lowbb.instrs = new_instrs;
             }
-            lowbb.instrs = new_instrs;
-        }
+            if stash_stacked.spilled.is_empty() {
+                break stash_stacked;
+            }
+            homes.append(&mut stash_stacked.spilled);
+            lowfunc.rbbs.clone_from(&rbbs);
+            day = fday;
+            realregs = frealregs.clone();
+        };
         lowfunc.framesize += stash_stacked.vecs.len() as i32 * 8;
+        lowfunc.usedregs = (0..GENEREGSIZE as i32)
+            .filter(|rr| realregs.used[*rr as usize])
+            .collect();
     }
     lpg
 }
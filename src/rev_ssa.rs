@@ -1,214 +1,179 @@
-use super::parser::{
-    nextfreshregister, FirstClassObj, SsaInstr, SsaInstrOp, SsaProgram, ValueType, Var, VarType,
-};
+use super::dominators::dominators;
+use super::parser::SsaInstrOp::*;
+use super::parser::*;
 use super::*;
-use once_cell::sync::Lazy;
 use std::collections::HashMap;
-use std::sync::Mutex;
 
-type VarLabel = &'static str;
+// `dst = src` of the phi
+type PhiCopy<'a> = (ValueType, Var<'a>, FirstClassObj<'a>);
 
-pub static FRESH_TMP_V_MAP: Lazy<Mutex<HashMap<usize, &'static str>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-pub static FRESH_N_TMP: Lazy<Mutex<usize>> = Lazy::new(|| Mutex::new(0));
-
-fn new_freshnum_tmp() -> usize {
-    let res = *FRESH_N_TMP.lock().unwrap();
-    *FRESH_N_TMP.lock().unwrap() = res + 1;
-    res
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq)]
-struct Life {
-    bt: usize,
-    dt: usize,
+fn reads(src: &FirstClassObj, var: &Var) -> bool {
+    matches!(src, FirstClassObj::Variable(srcv) if srcv.global.is_none() && srcv.rg_vr == var.rg_vr)
 }
 
-impl Life {
-    fn new(bt: usize, dt: usize) -> Self {
-        Self { bt, dt }
-    }
+// the block ends in jnz or jmp (otherwise it falls through to the next block or returns)
+fn branchpos(bb: &SsaBlock) -> Option<usize> {
+    bb.instrs
+        .iter()
+        .rposition(|instr| instr.living && matches!(instr.op, Jnz(..) | Jmp(..)))
 }
 
-trait UpLife {
-    fn update(&mut self, varlb: VarLabel, day: usize);
-}
-
-impl UpLife for HashMap<VarLabel, Life> {
-    fn update(&mut self, varlb: VarLabel, day: usize) {
-        if self.contains_key(varlb) {
-            self.get_mut(varlb).unwrap().dt = day;
-        } else {
-            self.insert(varlb, Life::new(day, day));
+// phis of the living instructions. the incoming labels are returned with the copies.
+fn phicopies<'a>(bb: &SsaBlock<'a>) -> Vec<(Label<'a>, PhiCopy<'a>)> {
+    let mut copies = vec![];
+    for instr in bb.instrs.iter().filter(|instr| instr.living) {
+        if let Assign(vty, var, rhs) = &instr.op {
+            if let Phi(_, pv) = &rhs.op {
+                for (lb, fco) in pv {
+                    copies.push((*lb, (*vty, var.clone(), fco.clone())));
+                }
+            }
         }
     }
+    copies
 }
 
-fn sub_cal_var_lifes(instr: &SsaInstr, lifes: &mut HashMap<VarLabel, Life>, day: usize) {
-    use SsaInstrOp::*;
-    match &instr.op {
-        Ret(fco) | Src(fco) => {
-            if let Some(varlb) = fco.get_varlb() {
-                lifes.update(varlb, day);
-            }
-        }
-        Assign(_, var, rhs) => {
-            lifes.update(var.name, day);
-            sub_cal_var_lifes(rhs, lifes, day);
-        }
-        Alloc4(var, _) | Loadw(var) | Jnz(var, ..) => {
-            lifes.update(var.name, day);
-        }
-        Storew(fco, var) => {
-            if let Some(varlb) = fco.get_varlb() {
-                lifes.update(varlb, day);
-            }
-            lifes.update(var.name, day);
-        }
-        Bop(_, fco1, fco2) => {
-            if let Some(varlb) = fco1.get_varlb() {
-                lifes.update(varlb, day);
-            }
-            if let Some(varlb) = fco2.get_varlb() {
-                lifes.update(varlb, day);
+// split the edges from the blocks ending in jnz to the blocks with phis, so that the copies of
+// the phis are executed only on the edge. the new block is placed after the source block.
+// returns the new block of each edge.
+fn splitcriticaledges<'a>(
+    func: &mut SsaFunction<'a>,
+    names: &'a NamePool,
+) -> HashMap<(Label<'a>, Label<'a>), Label<'a>> {
+    let lbids = func
+        .bls
+        .iter()
+        .map(|bb| (bb.lb, bb.id))
+        .collect::<HashMap<_, _>>();
+    let mut edges = vec![];
+    for bb in &func.bls {
+        for (lb, _) in phicopies(bb) {
+            let pred = &func.bls[lbids[lb]];
+            let jnz = branchpos(pred).is_some_and(|i| matches!(pred.instrs[i].op, Jnz(..)));
+            if jnz && !edges.contains(&(lb, bb.lb)) {
+                edges.push((lb, bb.lb));
             }
         }
-        Call(_, _, fcovs, _) => {
-            for fco in fcovs {
-                if let Some(varlb) = fco.get_varlb() {
-                    lifes.update(varlb, day);
+    }
+    let mut splitbbs = HashMap::new();
+    let mut newbls: Vec<Vec<SsaBlock>> = vec![vec![]; func.bls.len()];
+    for (pred, succ) in edges {
+        // the names of the new blocks (`@pred.succ`) are taken from the pool of the program
+        let lb: Label = names.unique(format!("{}.{}", pred, succ));
+        let mut bb = SsaBlock::new(lb, 0, vec![SsaInstr::new_all(Jmp(succ), true, lb)]);
+        bb.transbbs = vec![succ];
+        newbls[lbids[pred]].push(bb);
+        // jnz of pred jumps to the new block
+        let predbb = &mut func.bls[lbids[pred]];
+        let i = branchpos(predbb).unwrap();
+        if let Jnz(_, blb1, blb2) = &mut predbb.instrs[i].op {
+            for blb in [blb1, blb2] {
+                if *blb == succ {
+                    *blb = lb;
                 }
             }
         }
-        Comp(_, var1, var2, fco) => {
-            lifes.update(var1.name, day);
-            lifes.update(var2.name, day);
-            if let Some(varlb) = fco.get_varlb() {
-                lifes.update(varlb, day);
+        for translb in &mut predbb.transbbs {
+            if *translb == succ {
+                *translb = lb;
             }
         }
-        Phi(_, vecs) => {
-            for (_, fco) in vecs {
-                if let Some(varlb) = fco.get_varlb() {
-                    lifes.update(varlb, day);
-                }
-            }
-        }
-        Jmp(..) | Nop => {}
-        DummyOp => panic!("cal_var_lifes DummyOp error."),
+        splitbbs.insert((pred, succ), lb);
+    }
+    let bls = std::mem::take(&mut func.bls);
+    for (bb, mut splits) in bls.into_iter().zip(newbls) {
+        func.bls.push(bb);
+        func.bls.append(&mut splits);
+    }
+    for (id, bb) in func.bls.iter_mut().enumerate() {
+        bb.id = id;
     }
+    splitbbs
 }
 
-fn cal_var_lifes(
-    spg: &mut SsaProgram,
-) -> (HashMap<VarLabel, Life>, HashMap<BBLabel, (usize, usize)>) {
-    let mut day = 0;
-    let mut lifes = HashMap::new();
-    let mut bb_instr_lifes = HashMap::new();
-    for func in &spg.funcs {
-        for bb in &func.bls {
-            bb_instr_lifes.insert(bb.lb, (day, usize::MAX));
-            if bb.instrs.is_empty() {
-                bb_instr_lifes.insert(bb.lb, (usize::MAX, usize::MAX));
-                continue;
-            }
-            for instr in &bb.instrs {
-                sub_cal_var_lifes(instr, &mut lifes, day);
-                day += 1;
+// order the parallel copies so that no destination is overwritten before it is read.
+// the copies in the cycle (e.g. `a, b = b, a`) are broken by the temporary.
+fn sequentialize<'a>(
+    regs: &mut FreshRegs,
+    names: &'a NamePool,
+    copies: Vec<PhiCopy<'a>>,
+) -> Vec<PhiCopy<'a>> {
+    let mut pending = copies
+        .into_iter()
+        .filter(|(_, dst, src)| !reads(src, dst))
+        .collect::<Vec<_>>();
+    let mut seq = vec![];
+    while !pending.is_empty() {
+        // copy whose destination is not read by the other copies
+        let ready = pending
+            .iter()
+            .position(|(_, dst, _)| !pending.iter().any(|(_, _, src)| reads(src, dst)));
+        if let Some(i) = ready {
+            seq.push(pending.remove(i));
+            continue;
+        }
+        // every destination is read, so the old value of one is saved to the temporary
+        let (vty, dst, _) = pending[0].clone();
+        let tmp = Var::new(
+            names.fresh(dst.name),
+            dst.ty.clone(),
+            regs.nextfreshregister(),
+        );
+        seq.push((vty, tmp.clone(), FirstClassObj::Variable(dst.clone())));
+        for (_, _, src) in &mut pending {
+            if reads(src, &dst) {
+                *src = FirstClassObj::Variable(tmp.clone());
             }
-            let st_day = bb_instr_lifes.get(bb.lb).unwrap().0;
-            bb_instr_lifes.insert(bb.lb, (st_day, day - 1));
         }
     }
-    (lifes, bb_instr_lifes)
+    seq
 }
 
+// convert the program out of SSA form. the phis are replaced by the copies on the incoming
+// edges, after the critical edges are split.
 pub fn rev_ssa(spg: &mut SsaProgram) {
-    let (lifes, bb_instr_lifes) = cal_var_lifes(spg);
-    use SsaInstrOp::*;
+    let regs = &mut spg.regs;
+    let names = spg.names;
     for func in &mut spg.funcs {
-        let mut bb_lbid_hash = HashMap::new();
-        let mut proxy_instrs = vec![vec![]; func.bls.len()];
-        for bb in &func.bls {
-            bb_lbid_hash.insert(bb.lb, bb.id);
-        }
+        let splitbbs = splitcriticaledges(func, names);
+        let lbids = func
+            .bls
+            .iter()
+            .map(|bb| (bb.lb, bb.id))
+            .collect::<HashMap<_, _>>();
+        // parallel copies of each edge
+        let mut edgecopies: Vec<(usize, Vec<PhiCopy>)> = vec![];
         for bb in &mut func.bls {
-            let instrs = std::mem::replace(&mut bb.instrs, vec![]);
-            let mut tmp_var_copy_instrs = std::mem::replace(&mut proxy_instrs[bb.id], vec![]);
-            for mut instr in instrs {
-                match &instr.op {
-                    Assign(vty, var, rhs) if matches!(&rhs.op, Phi(_, _)) => {
-                        let var_life = lifes.get(var.name).unwrap();
-                        let phi_vecs = rhs.op.get_phi_vec().unwrap();
-                        let add_var;
-                        // insert "tmp = x_i" to target basic block.
-                        let mut need_tmp = false;
-                        for (lb, _) in &phi_vecs {
-                            let (st, ed) = *bb_instr_lifes.get(lb).unwrap();
-                            need_tmp |= (var_life.bt >= st && var_life.bt <= ed)
-                                || (var_life.dt >= st && var_life.dt <= ed);
-                        }
-                        // need tmp var
-                        if need_tmp {
-                            let freshn = new_freshnum_tmp();
-                            let varlb = format!("tmp#_{}", freshn);
-                            FRESH_TMP_V_MAP
-                                .lock()
-                                .unwrap()
-                                .insert(freshn, Box::leak(varlb.into_boxed_str()));
-                            add_var = Var::new(
-                                FRESH_TMP_V_MAP.lock().unwrap().get(&freshn).unwrap(),
-                                VarType::Word,
-                                nextfreshregister(),
-                            );
-                            instr.op = Assign(
-                                *vty,
-                                var.clone(),
-                                Box::new(SsaInstr::new_all(
-                                    SsaInstrOp::Src(FirstClassObj::Variable(add_var.clone())),
-                                    true,
-                                    bb.lb,
-                                )),
-                            )
-                        // simple method is sufficient.
-                        } else {
-                            add_var = var.clone();
-                            instr = SsaInstr::new_all(Nop, true, bb.lb);
-                        }
-                        for (lb, fco) in phi_vecs {
-                            let bb_id = bb_lbid_hash.get(lb).unwrap();
-                            proxy_instrs[*bb_id].push(SsaInstr::new_all(
-                                Assign(
-                                    ValueType::Word,
-                                    add_var.clone(),
-                                    Box::new(SsaInstr::new_all(Src(fco.clone()), true, lb)),
-                                ),
-                                true,
-                                lb,
-                            ));
-                        }
-                    }
-                    _ => {}
+            for (pred, copy) in phicopies(bb) {
+                let edgelb = splitbbs.get(&(pred, bb.lb)).copied().unwrap_or(pred);
+                let edgeid = lbids[edgelb];
+                match edgecopies.iter_mut().find(|(id, _)| *id == edgeid) {
+                    Some((_, copies)) => copies.push(copy),
+                    None => edgecopies.push((edgeid, vec![copy])),
                 }
-                proxy_instrs[bb.id].push(instr);
             }
-            proxy_instrs[bb.id].append(&mut tmp_var_copy_instrs);
-            // bb.instrs = std::mem::replace(&mut proxy_instrs[bb.id], vec![]);
-        }
-        for bb in &mut func.bls {
-            let mut branch_instrs = vec![];
-            for instr in &mut proxy_instrs[bb.id] {
-                match instr.op {
-                    Jnz(..) | Jmp(..) => {
-                        let tmp_instr = std::mem::replace(instr, SsaInstr::new(Nop));
-                        branch_instrs.push(tmp_instr);
+            for instr in &mut bb.instrs {
+                if let Assign(_, _, rhs) = &instr.op {
+                    if matches!(rhs.op, Phi(..)) {
+                        *instr = SsaInstr::new_all(Nop, true, bb.lb);
                     }
-                    _ => {}
                 }
             }
-            proxy_instrs[bb.id].append(&mut branch_instrs);
-            bb.instrs.append(&mut proxy_instrs[bb.id]);
         }
-        proxy_instrs.clear();
+        // the copies are inserted before the jump (or at the end of the block falling through)
+        for (id, copies) in edgecopies {
+            let bb = &mut func.bls[id];
+            let lb = bb.lb;
+            let pos = branchpos(bb).unwrap_or(bb.instrs.len());
+            let instrs = sequentialize(regs, names, copies)
+                .into_iter()
+                .map(|(vty, dst, src)| {
+                    let rhs = SsaInstr::new_all(Src(src), true, lb);
+                    SsaInstr::new_all(Assign(vty, dst, Box::new(rhs)), true, lb)
+                });
+            bb.instrs.splice(pos..pos, instrs);
+        }
     }
+    // the new blocks are added to the control flow graph
+    dominators(spg);
 }
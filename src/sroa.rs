@@ -0,0 +1,243 @@
+use super::lexer::Binop;
+use super::mem2reg::MemToregAlloca;
+use super::parser::SsaInstrOp::*;
+use super::parser::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+
+// alloca and the offset from it
+type FieldAddr<'a> = (&'a str, i32);
+
+// stack slot of alloc accessed at the constant offsets
+struct Aggregate {
+    size: i32,
+    // offset and the largest access at the offset
+    fields: BTreeMap<i32, i32>,
+    // has the address at the nonzero offset or the address computed by add
+    derived: bool,
+}
+
+// address `%q =l add %a, n` of alloc %a (or the address derived from it)
+fn derivedaddr<'a>(op: &SsaInstrOp<'a>) -> Option<FieldAddr<'a>> {
+    let Bop(Binop::Add, fco1, fco2) = op else {
+        return None;
+    };
+    match (fco1, fco2) {
+        (FirstClassObj::Variable(var), FirstClassObj::Num(_, num))
+        | (FirstClassObj::Num(_, num), FirstClassObj::Variable(var))
+            if var.global.is_none() =>
+        {
+            Some((var.name, i32::try_from(*num).ok()?))
+        }
+        _ => None,
+    }
+}
+
+// allocas split into the fields and the offsets of their addresses (the alloca itself is at 0)
+fn splittable<'a>(
+    func: &SsaFunction<'a>,
+) -> (HashMap<&'a str, Aggregate>, HashMap<&'a str, FieldAddr<'a>>) {
+    let mut aggs = HashMap::new();
+    let mut addrs = HashMap::new();
+    for bb in &func.bls {
+        for instr in &bb.instrs {
+            if let Alloc(var, _, size) = &instr.op {
+                if func.m2rinfo.contains_key(var.name) {
+                    aggs.insert(
+                        var.name,
+                        Aggregate {
+                            size: *size,
+                            fields: BTreeMap::new(),
+                            derived: false,
+                        },
+                    );
+                    addrs.insert(var.name, (var.name, 0));
+                }
+            }
+        }
+    }
+    // addresses derived from the derived addresses are found in the later rounds
+    loop {
+        let mut changed = false;
+        for bb in &func.bls {
+            for instr in &bb.instrs {
+                let Assign(_, var, rhs) = &instr.op else {
+                    continue;
+                };
+                let Some((base, num)) = derivedaddr(&rhs.op) else {
+                    continue;
+                };
+                if addrs.contains_key(var.name) {
+                    continue;
+                }
+                if let Some(&(alloca, offset)) = addrs.get(base) {
+                    addrs.insert(var.name, (alloca, offset + num));
+                    aggs.get_mut(alloca).unwrap().derived = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    // the addresses must be used only by load, store and the derived addresses
+    let mut escaped = HashSet::new();
+    let mut escape = |var: &Var<'a>| {
+        if let Some(&(alloca, _)) = addrs.get(var.name).filter(|_| var.global.is_none()) {
+            escaped.insert(alloca);
+        }
+    };
+    let mut accesses = vec![];
+    for bb in &func.bls {
+        for instr in &bb.instrs {
+            let op = match &instr.op {
+                Assign(_, var, rhs) if addrs.contains_key(var.name) => {
+                    if derivedaddr(&rhs.op).is_none() {
+                        escape(var);
+                    }
+                    continue;
+                }
+                Assign(_, _, rhs) => &rhs.op,
+                op => op,
+            };
+            match op {
+                Load(ldop, var) => accesses.push((var.name, ldop.memty())),
+                Store(valty, _, var) => accesses.push((var.name, *valty)),
+                _ => {}
+            }
+            op.visitoperands(&mut |var, operanduse| {
+                if operanduse != OperandUse::Address {
+                    escape(var);
+                }
+            });
+        }
+    }
+    for (name, memty) in accesses {
+        let Some(&(alloca, offset)) = addrs.get(name) else {
+            continue;
+        };
+        let agg = aggs.get_mut(alloca).unwrap();
+        let size = agg.fields.entry(offset).or_insert(0);
+        *size = (*size).max(memty.bytesize());
+        agg.derived |= offset != 0;
+    }
+    aggs.retain(|alloca, agg| {
+        if escaped.contains(alloca) || !agg.derived {
+            return false;
+        }
+        // the fields are in the slot and don't overlap
+        let mut end = 0;
+        for (&offset, &size) in &agg.fields {
+            if offset < end || offset + size > agg.size {
+                return false;
+            }
+            end = offset + size;
+        }
+        true
+    });
+    addrs.retain(|_, (alloca, _)| aggs.contains_key(alloca));
+    (aggs, addrs)
+}
+
+// scalar replacement of aggregates. the alloca accessed only at the constant offsets is split
+// into the alloca for each field, which mem2reg can promote to the register.
+pub fn sroa(spg: &mut SsaProgram) {
+    let regs = &mut spg.regs;
+    let names = spg.names;
+    for func in &mut spg.funcs {
+        let (aggs, addrs) = splittable(func);
+        if aggs.is_empty() {
+            continue;
+        }
+        // alloca of each field. the names (`%a.8`) are taken from the pool of the program.
+        let mut fieldvars: HashMap<FieldAddr, Var> = HashMap::new();
+        let mut aggnames = aggs.keys().copied().collect::<Vec<_>>();
+        aggnames.sort();
+        for alloca in aggnames {
+            func.m2rinfo.remove(alloca);
+            for (&offset, &size) in &aggs[alloca].fields {
+                let name = names.unique(format!("{}.{}", alloca, offset));
+                let ty = if size <= 4 {
+                    VarType::Ptr2Word
+                } else {
+                    VarType::Ptr2Long
+                };
+                fieldvars.insert(
+                    (alloca, offset),
+                    Var::new(name, ty, regs.nextfreshregister()),
+                );
+                func.m2rinfo.insert(name, MemToregAlloca::new(name));
+            }
+        }
+        let fieldvar = |var: &Var<'_>| {
+            addrs
+                .get(var.name)
+                .filter(|_| var.global.is_none())
+                .map(|&(alloca, offset)| fieldvars[&(alloca, offset)].clone())
+        };
+        for bb in &mut func.bls {
+            let mut instrs = vec![];
+            for mut instr in std::mem::take(&mut bb.instrs) {
+                match &mut instr.op {
+                    Alloc(var, ..) if aggs.contains_key(var.name) => {
+                        for (&offset, &size) in &aggs[var.name].fields {
+                            let fvar = fieldvars[&(var.name, offset)].clone();
+                            let align = if size <= 4 { 4 } else { 8 };
+                            instrs.push(SsaInstr::new_all(
+                                Alloc(fvar, align, size),
+                                instr.living,
+                                bb.lb,
+                            ));
+                        }
+                        continue;
+                    }
+                    // the derived addresses are no longer used
+                    Assign(_, var, _) if addrs.contains_key(var.name) => instr.op = Nop,
+                    Assign(_, _, rhs) => {
+                        if let Load(_, var) = &mut rhs.op {
+                            if let Some(fvar) = fieldvar(var) {
+                                *var = fvar;
+                            }
+                        }
+                    }
+                    Store(_, _, var) => {
+                        if let Some(fvar) = fieldvar(var) {
+                            *var = fvar;
+                        }
+                    }
+                    _ => {}
+                }
+                instrs.push(instr);
+            }
+            bb.instrs = instrs;
+        }
+        // information for mem2reg of the fields
+        let fieldnames = fieldvars
+            .values()
+            .map(|var| var.name)
+            .collect::<HashSet<_>>();
+        for bb in &func.bls {
+            for instr in &bb.instrs {
+                let (name, access) = match &instr.op {
+                    Assign(_, _, rhs) => match &rhs.op {
+                        Load(ldop, var) => (var.name, Err(*ldop)),
+                        _ => continue,
+                    },
+                    Store(valty, _, var) => (var.name, Ok(*valty)),
+                    _ => continue,
+                };
+                if !fieldnames.contains(name) {
+                    continue;
+                }
+                if let Some(m2ralloc) = func.m2rinfo.get_mut(name) {
+                    match access {
+                        Ok(valty) => m2ralloc.strpush(bb.id, valty),
+                        Err(ldop) => m2ralloc.ldpush(bb.id, ldop),
+                    }
+                }
+            }
+        }
+        MemToregAlloca::decision_type(&mut func.m2rinfo, &func.bls);
+    }
+}
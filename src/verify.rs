@@ -0,0 +1,370 @@
+use super::diagnostic::Diagnostic;
+use super::parser::SsaInstrOp::*;
+use super::parser::*;
+use super::Label;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// form of the program checked by verify
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Form {
+    // every instruction is checked (before removeuselessinstr)
+    Parsed,
+    // only the living instructions are checked
+    Pruned,
+    // phi is replaced by the copies (after rev_ssa), so variables may be assigned many times
+    Normal,
+}
+
+// definition of the variable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Def {
+    // function argument
+    Arg,
+    // index of the block and the instruction
+    Instr(usize, usize),
+    // assigned more than once
+    Many,
+}
+
+// integer or floating point
+fn isfloatvar(var: &Var) -> bool {
+    var.ty.isfloat()
+}
+
+fn fcoclass(fco: &FirstClassObj) -> Option<bool> {
+    match fco {
+        FirstClassObj::Variable(var) if var.global.is_none() => Some(isfloatvar(var)),
+        FirstClassObj::Float(..) => Some(true),
+        // integer literal is also used as the floating point
+        _ => None,
+    }
+}
+
+fn classname(float: bool) -> &'static str {
+    if float {
+        "floating point"
+    } else {
+        "integer"
+    }
+}
+
+// variable defined by the instruction
+fn definedvar<'a, 'b>(op: &'b SsaInstrOp<'a>) -> Option<&'b Var<'a>> {
+    match op {
+        Assign(_, var, _) | Alloc(var, ..) | Comp(_, _, var, ..) => Some(var),
+        _ => None,
+    }
+}
+
+struct FunVerifier<'a, 'b> {
+    func: &'b SsaFunction<'a>,
+    form: Form,
+    lbids: HashMap<Label<'a>, usize>,
+    reachable: Vec<bool>,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a, 'b> FunVerifier<'a, 'b> {
+    fn new(func: &'b SsaFunction<'a>, form: Form) -> Self {
+        let mut lbids = HashMap::new();
+        for bb in &func.bls {
+            lbids.insert(bb.lb, bb.id);
+        }
+        // blocks reachable from the entry
+        let mut reachable = vec![false; func.bls.len()];
+        if let Some(cfg) = &func.cfg {
+            let mut queue = VecDeque::from([0]);
+            reachable[0] = true;
+            while let Some(id) = queue.pop_front() {
+                for &next in &cfg.graph[id] {
+                    if !reachable[next] {
+                        reachable[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        Self {
+            func,
+            form,
+            lbids,
+            reachable,
+            errors: vec![],
+        }
+    }
+    fn error(&mut self, bb: &SsaBlock<'a>, message: String) {
+        self.errors.push(Diagnostic::new(format!(
+            "${} @{}: {}",
+            self.func.name, bb.lb, message
+        )));
+    }
+    // instructions checked in the form
+    fn instrs(&self, bb: &'b SsaBlock<'a>) -> Vec<&'b SsaInstr<'a>> {
+        bb.instrs
+            .iter()
+            .filter(|instr| self.form == Form::Parsed || instr.living)
+            .filter(|instr| !matches!(instr.op, Nop | DummyOp))
+            .collect()
+    }
+    // block a dominates block b
+    fn dominates(&self, a: usize, b: usize) -> bool {
+        let mut cur = b;
+        for _ in 0..=self.func.bls.len() {
+            if cur == a {
+                return true;
+            }
+            if cur == usize::MAX || cur == 0 {
+                return false;
+            }
+            cur = self.func.bls[cur].idom;
+        }
+        false
+    }
+    // every block ends in one jnz, jmp or ret (or falls through to the next block)
+    fn terminators(&mut self) {
+        let nbls = self.func.bls.len();
+        for (i, bb) in self.func.bls.iter().enumerate() {
+            let instrs = self.instrs(bb);
+            for (j, instr) in instrs.iter().enumerate() {
+                if matches!(instr.op, Jnz(..) | Jmp(..) | Ret(..)) && j + 1 != instrs.len() {
+                    self.error(bb, format!("`{}` is not at the end of the block", instr));
+                }
+            }
+            let terminated = matches!(
+                instrs.last().map(|instr| &instr.op),
+                Some(Jnz(..) | Jmp(..) | Ret(..))
+            );
+            if !terminated && i + 1 == nbls {
+                self.error(
+                    bb,
+                    "the last block doesn't end in jnz, jmp or ret".to_string(),
+                );
+            }
+            // jump targets
+            for instr in instrs {
+                let targets = match &instr.op {
+                    Jnz(_, lb1, lb2) => vec![*lb1, *lb2],
+                    Jmp(lb) => vec![*lb],
+                    _ => vec![],
+                };
+                for lb in targets {
+                    if !self.lbids.contains_key(lb) {
+                        self.error(bb, format!("jump to the undefined block @{}", lb));
+                    }
+                }
+            }
+        }
+    }
+    // phi has one operand for each predecessor
+    fn phis(&mut self) {
+        let Some(cfg) = &self.func.cfg else {
+            return;
+        };
+        for bb in &self.func.bls {
+            let preds = cfg.rgraph[bb.id]
+                .iter()
+                .map(|&id| self.func.bls[id].lb)
+                .collect::<HashSet<_>>();
+            for instr in self.instrs(bb) {
+                let Assign(_, var, rhs) = &instr.op else {
+                    continue;
+                };
+                let Phi(_, pv) = &rhs.op else {
+                    continue;
+                };
+                let lbs = pv.iter().map(|(lb, _)| *lb).collect::<HashSet<_>>();
+                for lb in preds.difference(&lbs) {
+                    self.error(bb, format!("phi of {} has no operand for @{}", var, lb));
+                }
+                for lb in lbs.difference(&preds) {
+                    self.error(
+                        bb,
+                        format!("phi of {} has an operand for non predecessor @{}", var, lb),
+                    );
+                }
+            }
+        }
+    }
+    // every use is dominated by its definition
+    fn dominance(&mut self) {
+        let mut defs: HashMap<i32, Def> = HashMap::new();
+        for arg in &self.func.args {
+            defs.insert(arg.rg_vr, Def::Arg);
+        }
+        for bb in &self.func.bls {
+            for (j, instr) in self.instrs(bb).iter().enumerate() {
+                if let Some(var) = definedvar(&instr.op) {
+                    defs.entry(var.rg_vr)
+                        .and_modify(|def| *def = Def::Many)
+                        .or_insert(Def::Instr(bb.id, j));
+                }
+            }
+        }
+        for bb in &self.func.bls {
+            if !self.reachable[bb.id] {
+                continue;
+            }
+            for (j, instr) in self.instrs(bb).iter().enumerate() {
+                let mut uses = vec![];
+                instr.op.visitoperands(&mut |var, operanduse| {
+                    let incoming = match operanduse {
+                        OperandUse::Incoming(lb) => Some(lb),
+                        OperandUse::Value | OperandUse::Address => None,
+                    };
+                    uses.push((var, incoming));
+                });
+                for (var, incoming) in uses {
+                    if var.global.is_some() {
+                        continue;
+                    }
+                    let dominated = match defs.get(&var.rg_vr).copied() {
+                        None => {
+                            self.error(bb, format!("{} is used but never defined", var));
+                            continue;
+                        }
+                        Some(Def::Arg) => true,
+                        // not in SSA form
+                        Some(Def::Many) => {
+                            if self.form != Form::Normal {
+                                self.error(bb, format!("{} is defined more than once", var));
+                            }
+                            continue;
+                        }
+                        // phi operand is used at the end of the incoming block
+                        Some(Def::Instr(dbb, dj)) => {
+                            match incoming.and_then(|lb| self.lbids.get(lb)) {
+                                Some(&pred) => self.dominates(dbb, pred),
+                                None if dbb == bb.id => dj < j,
+                                None => self.dominates(dbb, bb.id),
+                            }
+                        }
+                    };
+                    if !dominated {
+                        self.error(
+                            bb,
+                            format!("use of {} is not dominated by its definition", var),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    // each name denotes one variable (the redefinition in the source is a new variable)
+    fn names(&mut self) {
+        let mut vrs: HashMap<&str, i32> = HashMap::new();
+        for arg in &self.func.args {
+            vrs.insert(arg.name, arg.rg_vr);
+        }
+        for bb in &self.func.bls {
+            for instr in self.instrs(bb) {
+                let Some(var) = definedvar(&instr.op) else {
+                    continue;
+                };
+                if *vrs.entry(var.name).or_insert(var.rg_vr) != var.rg_vr {
+                    self.error(bb, format!("{} is redefined", var));
+                }
+            }
+        }
+    }
+    fn expectclass(
+        &mut self,
+        bb: &SsaBlock<'a>,
+        fco: &FirstClassObj,
+        float: bool,
+        instr: &SsaInstr,
+    ) {
+        if let Some(class) = fcoclass(fco) {
+            if class != float {
+                self.error(
+                    bb,
+                    format!(
+                        "{} operand {} in `{}`, expected {}",
+                        classname(class),
+                        fco,
+                        instr,
+                        classname(float)
+                    ),
+                );
+            }
+        }
+    }
+    fn expectaddress(&mut self, bb: &SsaBlock<'a>, var: &Var, instr: &SsaInstr) {
+        if !var.ty.isaddress() && var.ty != VarType::Word {
+            self.error(bb, format!("{} is not an address in `{}`", var, instr));
+        }
+    }
+    // operand types agree with the instruction
+    fn types(&mut self) {
+        let retfloat = self.func.retty.isfloat();
+        let retvoid = self.func.retty == VarType::Void;
+        for bb in &self.func.bls {
+            for instr in self.instrs(bb) {
+                let (dst, op) = match &instr.op {
+                    Assign(_, var, rhs) => (Some(isfloatvar(var)), &rhs.op),
+                    op => (None, op),
+                };
+                match (op, dst) {
+                    (Ret(fco), _) if !retvoid => self.expectclass(bb, fco, retfloat, instr),
+                    (Src(fco) | Bop(_, fco, _), Some(float)) => {
+                        self.expectclass(bb, fco, float, instr);
+                        if let Bop(_, _, fco2) = op {
+                            self.expectclass(bb, fco2, float, instr);
+                        }
+                    }
+                    (Phi(_, pv), Some(float)) => {
+                        for (_, fco) in pv {
+                            self.expectclass(bb, fco, float, instr);
+                        }
+                    }
+                    (Ext(_, fco), _) => self.expectclass(bb, fco, false, instr),
+                    (Cvt(cvtop, dstty, fco), _) => {
+                        self.expectclass(bb, fco, cvtop.srcty(*dstty).isfloat(), instr)
+                    }
+                    (Comp(_, valty, _, fco1, fco2), _) => {
+                        self.expectclass(bb, fco1, valty.isfloat(), instr);
+                        self.expectclass(bb, fco2, valty.isfloat(), instr);
+                    }
+                    (Store(valty, fco, var), _) => {
+                        self.expectclass(bb, fco, valty.isfloat(), instr);
+                        self.expectaddress(bb, var, instr);
+                    }
+                    (Load(_, var) | Vastart(var) | Vaarg(_, var), _) => {
+                        self.expectaddress(bb, var, instr)
+                    }
+                    (Jnz(var, ..), _) if isfloatvar(var) => {
+                        self.error(bb, format!("condition {} of jnz is not an integer", var))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// check the well-formedness of the program. dominators must be computed before.
+pub fn verify(spg: &SsaProgram, form: Form) -> Result<(), Vec<Diagnostic>> {
+    let mut errors = vec![];
+    for func in &spg.funcs {
+        let mut fv = FunVerifier::new(func, form);
+        if func.cfg.is_none() {
+            fv.errors.push(Diagnostic::new(format!(
+                "${}: dominators are not computed",
+                func.name
+            )));
+        } else {
+            fv.terminators();
+            if form != Form::Normal {
+                fv.phis();
+            }
+            fv.names();
+            fv.dominance();
+            fv.types();
+        }
+        errors.append(&mut fv.errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
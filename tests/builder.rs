@@ -0,0 +1,84 @@
+use mirlvm::builder::ModuleBuilder;
+use mirlvm::dominators::dominators;
+use mirlvm::interp::interpret;
+use mirlvm::lexer::Binop;
+use mirlvm::parser::FirstClassObj::Num;
+use mirlvm::parser::{CompOp, FirstClassObj, NamePool, SsaProgram, ValueType, Var, VarType};
+use mirlvm::verify::Form;
+use mirlvm::{compile_program, Options};
+
+fn w<'a>(num: i64) -> FirstClassObj<'a> {
+    Num(VarType::Word, num)
+}
+
+fn var<'a>(var: &Var<'a>) -> FirstClassObj<'a> {
+    FirstClassObj::Variable(var.clone())
+}
+
+// the 50th prime number of the example in README
+fn prime<'a>(names: &'a NamePool) -> SsaProgram<'a> {
+    use ValueType::Word;
+    let mut mb = ModuleBuilder::new(names);
+    let mut fb = mb.function("main", VarType::Word, vec![]);
+    // start falls through to loop
+    fb.block("start");
+    let lp = fb.block("loop");
+    let tloop = fb.block("tloop");
+    let next = fb.block("next");
+    let yes = fb.block("yes");
+    let end = fb.block("end");
+    let (n, p, p1) = {
+        let mut b = fb.at(lp);
+        let n = b.phi("%n", Word, vec![("start", w(5))]);
+        let p = b.phi("%p", Word, vec![("start", w(13))]);
+        let p1 = b.bop("%p1", Word, Binop::Add, var(&p), w(2));
+        (n, p, p1)
+    };
+    let t = {
+        let mut b = fb.at(tloop);
+        let t = b.phi("%t", Word, vec![("loop", w(3))]);
+        let r = b.bop("%r", Word, Binop::Rem, var(&p), var(&t));
+        b.jnz(&r, "next", "loop");
+        t
+    };
+    let t1 = {
+        let mut b = fb.at(next);
+        let t1 = b.bop("%t1", Word, Binop::Add, w(2), var(&t));
+        let tsq = b.bop("%tsq", Word, Binop::Mul, var(&t1), var(&t1));
+        let c0 = b.comp("%c0", Word, CompOp::Csgt, Word, var(&tsq), var(&p));
+        b.jnz(&c0, "yes", "tloop");
+        t1
+    };
+    let n1 = {
+        let mut b = fb.at(yes);
+        let n1 = b.bop("%n1", Word, Binop::Add, w(1), var(&n));
+        let c1 = b.comp("%c1", Word, CompOp::Ceq, Word, w(50), var(&n1));
+        b.jnz(&c1, "end", "loop");
+        n1
+    };
+    fb.at(end).ret(var(&p));
+    fb.addincoming(&n, "tloop", var(&n));
+    fb.addincoming(&n, "yes", var(&n1));
+    fb.addincoming(&p, "tloop", var(&p1));
+    fb.addincoming(&p, "yes", var(&p1));
+    fb.addincoming(&t, "next", var(&t1));
+    fb.finish();
+    mb.finish()
+}
+
+#[test]
+fn build_and_compile() {
+    let names = NamePool::default();
+    let mut spg = prime(&names);
+    dominators(&mut spg);
+    assert_eq!(interpret(&spg, Form::Parsed).unwrap().ret, 229);
+    for optimize in [false, true] {
+        let options = Options {
+            optimize,
+            verify: true,
+            ..Options::default()
+        };
+        let asm = compile_program(prime(&names), options).unwrap();
+        assert!(asm.contains("main:"));
+    }
+}
@@ -0,0 +1,109 @@
+use mirlvm::{compile, Options};
+
+static LOOP: &str = "
+function w $main() {
+@start:
+    %a =l alloc4 4
+    %s =l alloc4 4
+    storew 0, %a
+    storew 0, %s
+    jmp @cond
+@cond:
+    %i =w loadw %a
+    %c =w csltw %i, 10
+    jnz %c, @body, @end
+@body:
+    %t =w loadw %s
+    %u =w add %t, %i
+    storew %u, %s
+    %j =w add %i, 1
+    storew %j, %a
+    jmp @cond
+@end:
+    %r =w loadw %s
+    ret %r
+}
+";
+
+#[test]
+fn compile_source() {
+    for optimize in [false, true] {
+        let options = Options {
+            optimize,
+            verify: true,
+            ..Options::default()
+        };
+        let asm = compile(LOOP, options).unwrap();
+        assert!(asm.contains(".globl main"));
+        assert!(asm.contains("main:"));
+    }
+}
+
+#[test]
+fn compile_errors() {
+    let source = "function w $main() {\n@start:\n    ret %x\n}\n";
+    let diags = compile(source, Options::default()).unwrap_err();
+    assert_eq!(diags.len(), 1);
+    assert!(diags[0].message.contains("%x"));
+}
+
+#[test]
+fn compile_non_ascii() {
+    let source =
+        "# café\ndata $s = { b \"héllo\", b 0 }\nfunction w $main() {\n@start:\n    ret 0\n}\n";
+    let asm = compile(source, Options::default()).unwrap();
+    assert!(asm.contains(".ascii \"héllo\""));
+    let diags = compile(
+        "function w $main() {\n@start:\n    ret é\n}\n",
+        Options::default(),
+    )
+    .unwrap_err();
+    assert_eq!((diags[0].line, diags[0].col), (3, 9));
+}
+
+#[test]
+fn compile_redefinition() {
+    let source =
+        "function w $main() {\n@start:\n    %a =w add 1, 1\n    %a =w add 1, 1\n    ret %a\n}\n";
+    let options = Options {
+        verify: true,
+        ..Options::default()
+    };
+    let diags = compile(source, options).unwrap_err();
+    assert!(diags[0].message.contains("%a is redefined"));
+}
+
+#[test]
+fn compile_undefined_block() {
+    let source = "function w $main() {\n@start:\n    %p =w phi @none 1\n    jmp @nowhere\n}\n";
+    let diags = compile(source, Options::default()).unwrap_err();
+    let located = diags
+        .iter()
+        .map(|diag| (diag.message.as_str(), diag.line, diag.col))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        located,
+        [
+            ("block @none is not defined", 3, 16),
+            ("block @nowhere is not defined", 4, 10)
+        ]
+    );
+}
+
+#[test]
+fn compile_duplicates() {
+    let source = "data $a = { w 1 }\ndata $a = { w 2 }\nfunction w $main() {\n@b:\n    jmp @b\n@b:\n    ret 0\n}\nfunction w $main() {\n@start:\n    ret 0\n}\n";
+    let diags = compile(source, Options::default()).unwrap_err();
+    let located = diags
+        .iter()
+        .map(|diag| (diag.message.as_str(), diag.line, diag.col))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        located,
+        [
+            ("$a is defined twice", 2, 7),
+            ("block @b is defined twice", 6, 2),
+            ("$main is defined twice", 9, 13)
+        ]
+    );
+}
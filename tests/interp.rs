@@ -0,0 +1,60 @@
+use mirlvm::dominators::dominators;
+use mirlvm::interp::interpret;
+use mirlvm::lexer::lex;
+use mirlvm::parser::{parse, NamePool};
+use mirlvm::verify::Form;
+
+// main calls $r with the depth n, which returns n
+fn recursion(depth: i64) -> String {
+    format!(
+        "function w $r(w %n) {{
+@start:
+    jnz %n, @rec, @end
+@rec:
+    %m =w sub %n, 1
+    %v =w call $r(w %m)
+    %s =w add %v, 1
+    ret %s
+@end:
+    ret 0
+}}
+function w $main() {{
+@start:
+    %a =w call $r(w {})
+    ret %a
+}}
+",
+        depth
+    )
+}
+
+#[test]
+fn interp_deep_calls() {
+    for (depth, res) in [
+        (1000, Ok(1000)),
+        (4000, Ok(4000)),
+        (5000, Err("too deep calls")),
+    ] {
+        let source = recursion(depth);
+        let names = NamePool::default();
+        let mut tms = lex(&source).unwrap();
+        let mut spg = parse(&mut tms, &names).unwrap();
+        dominators(&mut spg);
+        match (interpret(&spg, Form::Parsed), res) {
+            (Ok(run), Ok(ret)) => assert_eq!(run.ret, ret),
+            (Err(diag), Err(message)) => assert!(diag.message.contains(message)),
+            (run, _) => panic!("depth {}: {:?}", depth, run),
+        }
+    }
+}
+
+// main without the return type exits with the operand of ret as the compiled program
+#[test]
+fn interp_void_main() {
+    let source = std::fs::read_to_string("test/prime.ssa").unwrap();
+    let names = NamePool::default();
+    let mut tms = lex(&source).unwrap();
+    let mut spg = parse(&mut tms, &names).unwrap();
+    dominators(&mut spg);
+    assert_eq!(interpret(&spg, Form::Parsed).unwrap().ret, 229);
+}
@@ -0,0 +1,195 @@
+use mirlvm::deadcode::removeuselessinstr;
+use mirlvm::dominators::dominators;
+use mirlvm::interp::{interpret, Run};
+use mirlvm::lexer::lex;
+use mirlvm::mem2reg::{ezmem2reg, mem2reg};
+use mirlvm::parser::{parse, NamePool, SsaProgram};
+use mirlvm::rev_ssa::rev_ssa;
+use mirlvm::sroa::sroa;
+use mirlvm::verify::Form;
+use mirlvm::{compile, Options};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// return value of main of each test program
+static EXPECTED: [(&str, i64); 41] = [
+    ("add.ssa", 140),
+    ("addaddadd.ssa", 28),
+    ("aggregate.ssa", 46),
+    ("array.ssa", 5),
+    ("bitops.ssa", 12),
+    ("branch_1.ssa", 54),
+    ("branch_2.ssa", 108),
+    ("branch_3.ssa", 108),
+    ("call_1.ssa", 51),
+    ("calleesaved.ssa", 153),
+    ("compare.ssa", 238),
+    ("data.ssa", 151),
+    ("escape.ssa", 43),
+    ("extend.ssa", 177),
+    ("fib.ssa", 89),
+    ("float.ssa", 31),
+    ("for-while.ssa", 10),
+    ("latestore.ssa", 12),
+    ("linkage.ssa", 47),
+    ("loop2.ssa", 55),
+    ("manyargs.ssa", 236),
+    ("memory.ssa", 189),
+    ("mul.ssa", 60),
+    ("mul_overflow.ssa", 1591644218),
+    ("names.ssa", 43),
+    ("nest_loop.ssa", 0),
+    ("pointer.ssa", 30),
+    ("prime.ssa", 229),
+    ("promotewide.ssa", 44),
+    ("qbe.ssa", 0),
+    ("ret42.ssa", 229),
+    ("simple_call.ssa", 8),
+    ("simple_loop.ssa", 45),
+    ("sroa.ssa", 42),
+    ("string.ssa", 42),
+    ("swap.ssa", 54),
+    ("unsigned.ssa", 63),
+    ("uselessinstr1.ssa", 54),
+    ("uselessinstr2.ssa", 20),
+    ("variadic.ssa", 240),
+    ("wideint.ssa", 20),
+];
+
+// the syntax which is not supported yet (see tests/roundtrip.rs)
+static UNSUPPORTED: [&str; 2] = ["array2.ssa", "loop.ssa"];
+
+// calling the C library functions which the interpreter doesn't have
+static NATIVEONLY: [&str; 1] = ["calleesaved.ssa"];
+
+// looping forever, which are only compiled
+static COMPILEONLY: [&str; 1] = ["sample_ssa.ssa"];
+
+// runs of main before the passes and after each pass
+fn interpruns(spg: &mut SsaProgram, optimize: bool) -> Vec<(&'static str, Run)> {
+    let mut runs = vec![];
+    let mut run = |spg: &SsaProgram, form: Form, pass: &'static str| {
+        let res = interpret(spg, form).unwrap_or_else(|diag| panic!("after {}: {}", pass, diag));
+        runs.push((pass, res));
+    };
+    dominators(spg);
+    run(spg, Form::Parsed, "parse");
+    removeuselessinstr(spg);
+    run(spg, Form::Pruned, "removeuselessinstr");
+    if optimize {
+        sroa(spg);
+        run(spg, Form::Pruned, "sroa");
+        ezmem2reg(spg);
+        mem2reg(spg);
+        run(spg, Form::Pruned, "mem2reg");
+    }
+    rev_ssa(spg);
+    run(spg, Form::Normal, "rev_ssa");
+    runs
+}
+
+fn options(optimize: bool) -> Options {
+    Options {
+        optimize,
+        ..Options::default()
+    }
+}
+
+// directory of the compiled programs
+fn builddir() -> PathBuf {
+    std::env::temp_dir().join(format!("mirlvm-programs-{}", std::process::id()))
+}
+
+// the executable of the compiled program
+fn nativebuild(file: &str, source: &str, optimize: bool) -> PathBuf {
+    let asm =
+        compile(source, options(optimize)).unwrap_or_else(|diags| panic!("{}: {}", file, diags[0]));
+    let dir = builddir();
+    fs::create_dir_all(&dir).unwrap();
+    let stem = format!("{}-{}", file.trim_end_matches(".ssa"), optimize);
+    let (asmpath, exepath) = (dir.join(format!("{}.s", stem)), dir.join(stem));
+    fs::write(&asmpath, asm).unwrap();
+    let status = Command::new("cc")
+        .arg("-static")
+        .arg("-o")
+        .arg(&exepath)
+        .arg(&asmpath)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{}: cc failed", file);
+    exepath
+}
+
+// exit status and the output of the compiled program
+fn nativerun(file: &str, source: &str, optimize: bool) -> (i32, String) {
+    let output = Command::new(nativebuild(file, source, optimize))
+        .output()
+        .unwrap();
+    let code = output.status.code().unwrap();
+    (code, String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn programs() {
+    let mut files = fs::read_dir("test")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|file| file.ends_with(".ssa") && !UNSUPPORTED.contains(&file.as_str()))
+        .collect::<Vec<_>>();
+    files.sort();
+    let mut listed = EXPECTED.iter().map(|(file, _)| *file).collect::<Vec<_>>();
+    listed.extend(COMPILEONLY);
+    listed.sort();
+    assert_eq!(
+        files, listed,
+        "the result of each test program must be recorded"
+    );
+    let hascc = Command::new("cc").arg("--version").output().is_ok();
+    for file in COMPILEONLY {
+        let source = fs::read_to_string(format!("test/{}", file)).unwrap();
+        for optimize in [false, true] {
+            if hascc {
+                nativebuild(file, &source, optimize);
+            } else {
+                compile(&source, options(optimize)).unwrap();
+            }
+        }
+    }
+    for (file, expected) in EXPECTED {
+        let source = fs::read_to_string(format!("test/{}", file)).unwrap();
+        for optimize in [false, true] {
+            let mut stdout = None;
+            if !NATIVEONLY.contains(&file) {
+                let names = NamePool::default();
+                let mut tms = lex(&source).unwrap();
+                let mut spg = parse(&mut tms, &names).unwrap();
+                for (pass, run) in interpruns(&mut spg, optimize) {
+                    assert_eq!(
+                        run.ret, expected,
+                        "{} -O{} after {}",
+                        file, optimize as i32, pass
+                    );
+                    let first = stdout.get_or_insert_with(|| run.stdout.clone());
+                    assert_eq!(&run.stdout, first, "{} after {}", file, pass);
+                }
+            }
+            if hascc {
+                let (code, out) = nativerun(file, &source, optimize);
+                assert_eq!(
+                    code as i64,
+                    expected & 0xff,
+                    "{} -O{}",
+                    file,
+                    optimize as i32
+                );
+                if let Some(stdout) = stdout {
+                    assert_eq!(out, stdout, "{} -O{}", file, optimize as i32);
+                }
+            }
+        }
+    }
+    if hascc {
+        fs::remove_dir_all(builddir()).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use mirlvm::deadcode::removeuselessinstr;
+use mirlvm::dominators::dominators;
+use mirlvm::lexer::lex;
+use mirlvm::mem2reg::{ezmem2reg, mem2reg};
+use mirlvm::parser::{parse, NamePool, SsaInstrOp, SsaProgram};
+use mirlvm::sroa::sroa;
+use std::fs;
+
+// the tests of the syntax which is not supported yet
+static UNSUPPORTED: [&str; 2] = ["test/array2.ssa", "test/loop.ssa"];
+
+fn parsed<'a>(source: &'a str, names: &'a NamePool) -> SsaProgram<'a> {
+    let mut tms = lex(source).unwrap();
+    parse(&mut tms, names).unwrap_or_else(|diags| panic!("{}\n{}", diags[0], source))
+}
+
+// the program after the passes of -O1 as the textual IR (--out-ssair_1 without the nops)
+fn optimized(source: &str) -> String {
+    let names = NamePool::default();
+    let mut spg = parsed(source, &names);
+    dominators(&mut spg);
+    removeuselessinstr(&mut spg);
+    sroa(&mut spg);
+    ezmem2reg(&mut spg);
+    mem2reg(&mut spg);
+    for func in &mut spg.funcs {
+        for bb in &mut func.bls {
+            bb.instrs
+                .retain(|instr| instr.living && !matches!(instr.op, SsaInstrOp::Nop));
+        }
+    }
+    spg.to_string()
+}
+
+fn sources() -> Vec<(String, String)> {
+    let mut files = fs::read_dir("test")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ssa"))
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+        .into_iter()
+        .map(|file| {
+            let source = fs::read_to_string(&file).unwrap();
+            (file, source)
+        })
+        .collect()
+}
+
+// the unsupported tests are still rejected by the parser
+#[test]
+fn roundtrip_unsupported() {
+    for file in UNSUPPORTED {
+        let source = fs::read_to_string(file).unwrap();
+        let names = NamePool::default();
+        let parsed = lex(&source)
+            .ok()
+            .and_then(|mut tms| parse(&mut tms, &names).ok());
+        assert!(parsed.is_none(), "{} is parsed", file);
+    }
+}
+
+// the printed program parses back to the same program
+#[test]
+fn roundtrip_parsed() {
+    for (file, source) in sources() {
+        if UNSUPPORTED.contains(&file.as_str()) {
+            continue;
+        }
+        let names = NamePool::default();
+        let spg = parsed(&source, &names);
+        let printed = spg.to_string();
+        let rnames = NamePool::default();
+        let reparsed = parsed(&printed, &rnames);
+        assert_eq!(reparsed.to_string(), printed, "{}", file);
+        assert!(reparsed == spg, "{}", file);
+    }
+}
+
+#[test]
+fn roundtrip_optimized() {
+    for (file, source) in sources() {
+        if UNSUPPORTED.contains(&file.as_str()) {
+            continue;
+        }
+        let printed = optimized(&source);
+        let names = NamePool::default();
+        let reparsed = parsed(&printed, &names);
+        assert_eq!(reparsed.to_string(), printed, "{}", file);
+    }
+}